@@ -0,0 +1,181 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use reqwest::{Method, Response, header::CONTENT_TYPE};
+use serde_json::Value;
+
+use crate::{cli::CliError, utils::settings::Settings};
+
+/// Talks to a running daemon over its HTTP API, through the Unix socket or
+/// TCP.
+pub struct Client {
+    http: reqwest::Client,
+    base: String,
+
+    /// Where requests go, for error messages.
+    target: String,
+}
+
+/// `server.listen` as seen from the same host: a wildcard address is
+/// reached through loopback.
+fn local_url(listen: &str) -> String {
+    let Ok(mut addr) = listen.parse::<SocketAddr>() else {
+        return format!("http://{}", listen);
+    };
+
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        _ => {}
+    }
+
+    format!("http://{}", addr)
+}
+
+/// Builds an error from a non-2xx response. Besides `error`, bodies may carry
+/// details such as xray output or lint issues, which are kept.
+async fn api_error(response: Response) -> CliError {
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+
+    let message = match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Object(mut body)) => {
+            let error = body.remove("error");
+            let mut message = match error {
+                Some(Value::String(error)) => error,
+                Some(error) => error.to_string(),
+                None => String::new(),
+            };
+            if !body.is_empty() {
+                let details = serde_json::to_string_pretty(&body).unwrap_or_default();
+                message = format!("{}\n{}", message, details).trim().to_string();
+            }
+            message
+        }
+        _ => text,
+    };
+
+    CliError::Api {
+        status: status.as_u16(),
+        message: match message.is_empty() {
+            true => status.canonical_reason().unwrap_or_default().to_string(),
+            false => message,
+        },
+    }
+}
+
+impl Client {
+    /// `server` is a base URL or a socket path. Without it the socket from
+    /// `elux.kdl` is used; a missing socket is an error rather than a silent
+    /// switch to TCP.
+    pub fn new(server: Option<String>) -> Result<Self, CliError> {
+        let socket = match server {
+            Some(server) if server.starts_with('/') => PathBuf::from(server),
+            Some(server) => {
+                let base = server.trim_end_matches('/').to_string();
+
+                return Ok(Client {
+                    http: reqwest::Client::new(),
+                    target: base.clone(),
+                    base,
+                });
+            }
+            None => {
+                let settings = Settings::load()?.server;
+                let socket = settings.socket_path();
+
+                if !socket.exists() {
+                    let hint = match settings.tcp {
+                        true => format!(
+                            " Use --server {} to connect over TCP.",
+                            local_url(&settings.listen)
+                        ),
+                        false => String::new(),
+                    };
+
+                    return Err(CliError::Invalid(format!(
+                        "No elux socket at {}, is it running?{}",
+                        socket.display(),
+                        hint
+                    )));
+                }
+
+                socket
+            }
+        };
+
+        let http = reqwest::Client::builder()
+            .unix_socket(socket.as_path())
+            .build()
+            .map_err(|err| CliError::Response(err.to_string()))?;
+
+        Ok(Client {
+            http,
+            base: "http://localhost".to_string(),
+            target: socket.display().to_string(),
+        })
+    }
+
+    /// Sends the request and fails on a non-2xx status.
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, CliError> {
+        let url = format!("{}{}", self.base, path);
+
+        let mut request = self.http.request(method, &url);
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+
+        let response = request.send().await.map_err(|source| CliError::Connect {
+            target: self.target.clone(),
+            source,
+        })?;
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(api_error(response).await)
+        }
+    }
+
+    /// Like `send`, with the body parsed as JSON. An empty body is `null`.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, CliError> {
+        let text = self
+            .send(method, path, body)
+            .await?
+            .text()
+            .await
+            .map_err(|err| CliError::Response(err.to_string()))?;
+
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+
+        serde_json::from_str(&text).map_err(|err| CliError::Response(err.to_string()))
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Value, CliError> {
+        self.request(Method::GET, path, None).await
+    }
+
+    pub async fn post(&self, path: &str, body: Option<&Value>) -> Result<Value, CliError> {
+        self.request(Method::POST, path, body).await
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<Value, CliError> {
+        self.request(Method::DELETE, path, None).await
+    }
+}
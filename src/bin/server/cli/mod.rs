@@ -0,0 +1,422 @@
+use std::{io::Write, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::{
+    cli::{
+        client::Client,
+        output::{cell, print_json, table},
+    },
+    utils::settings::SettingsError,
+};
+
+pub mod client;
+pub mod output;
+
+/// Manager for xray-core. Without a command, runs the daemon.
+#[derive(Debug, Parser)]
+#[command(name = "elux", version, about)]
+pub struct Cli {
+    /// URL or socket path of the running daemon, e.g. http://127.0.0.1:8400.
+    /// Taken from elux.kdl if not set.
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+
+    /// Print the daemon's JSON instead of tables.
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the daemon.
+    Serve(ServeArgs),
+
+    /// Manage config groups.
+    #[command(subcommand)]
+    Group(GroupCommand),
+
+    /// Import or export xray.json.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Control the xray process.
+    #[command(subcommand)]
+    Xray(XrayCommand),
+
+    /// Show the observatory's probes of a group's applied outbounds.
+    Check {
+        /// Group ID.
+        group: i32,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Address to listen on, overrides server.listen.
+    #[arg(long)]
+    pub listen: Option<String>,
+
+    /// Directory for elux.kdl, xray.json and the database instead of
+    /// ~/.config/elux.
+    #[arg(long)]
+    pub config_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GroupCommand {
+    /// List groups.
+    List,
+
+    /// Create a group.
+    Add {
+        name: String,
+
+        /// Subscription URL to fetch configs from.
+        #[arg(long)]
+        url: Option<Url>,
+    },
+
+    /// Fetch the group's subscription again, replacing its configs.
+    Refresh { id: i32 },
+
+    /// Delete a group and its configs.
+    Delete { id: i32 },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Replace xray.json with a file or the document at a URL.
+    Import {
+        /// Path or http(s) URL.
+        source: String,
+    },
+
+    /// Print xray.json.
+    Export {
+        /// Write to a file instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum XrayCommand {
+    Start,
+    Stop,
+    Restart,
+    Status,
+
+    /// Print the xray log.
+    Logs {
+        /// Keep printing new lines.
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Lines from the end of the log to start with.
+        #[arg(short = 'n', long, default_value_t = 100)]
+        lines: usize,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("Failed to reach elux at {target}, is it running? {source}")]
+    Connect {
+        target: String,
+        source: reqwest::Error,
+    },
+
+    #[error("elux responded with {status}: {message}")]
+    Api { status: u16, message: String },
+
+    #[error("Unexpected response from elux: {0}")]
+    Response(String),
+
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to download {url}: {source}")]
+    Download { url: String, source: reqwest::Error },
+
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+}
+
+/// `host:port` of the first server of an outbound, as shown in tables.
+fn address(config: &Value) -> String {
+    let server = config
+        .pointer("/settings/vnext/0")
+        .or_else(|| config.pointer("/settings/servers/0"));
+
+    match server {
+        Some(server) => format!(
+            "{}:{}",
+            cell(server.get("address")),
+            cell(server.get("port"))
+        ),
+        None => "-".to_string(),
+    }
+}
+
+fn print_groups(groups: &Value) {
+    let rows = groups
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|group| {
+            vec![
+                cell(group.get("id")),
+                cell(group.get("name")),
+                cell(group.get("subscribeUrl")),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    println!("{}", table(&["ID", "NAME", "SUBSCRIPTION"], &rows));
+}
+
+fn print_configs(configs: &Value) {
+    let rows = configs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|config| {
+            vec![
+                cell(config.get("id")),
+                cell(config.get("protocol")),
+                address(config),
+                cell(config.pointer("/extra/clientName")),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    println!("{}", table(&["ID", "PROTOCOL", "ADDRESS", "NAME"], &rows));
+}
+
+fn print_status(status: &Value) {
+    let mut rows = vec![
+        ("state", cell(status.get("state"))),
+        ("pid", cell(status.get("pid"))),
+        ("uptime", cell(status.get("uptimeSecs"))),
+        ("restarts", cell(status.get("restartCount"))),
+        ("binary", cell(status.get("binary"))),
+        ("version", cell(status.get("version"))),
+    ];
+    if let Some(exit) = status.get("lastExit") {
+        rows.push(("last exit", cell(exit.get("reason"))));
+    }
+
+    for (key, value) in rows {
+        println!("{:<10}{}", key, value);
+    }
+}
+
+/// Observatory results for the outbounds of `group`.
+fn probes_of(group: i32, statuses: Value) -> Vec<Value> {
+    match statuses {
+        Value::Array(statuses) => statuses
+            .into_iter()
+            .filter(|status| status.get("groupId").and_then(Value::as_i64) == Some(group.into()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn print_probes(group: i32, probes: &[Value]) {
+    let rows = probes
+        .iter()
+        .map(|status| {
+            let alive = status.get("alive").and_then(Value::as_bool) == Some(true);
+            vec![
+                cell(status.get("configId")),
+                cell(status.get("tag")),
+                if alive { "yes" } else { "no" }.to_string(),
+                if alive {
+                    format!("{} ms", cell(status.get("delay")))
+                } else {
+                    cell(status.get("lastErrorReason"))
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    if rows.is_empty() {
+        println!(
+            "No probes for group {}: apply its configs and add a leastPing or leastLoad balancer for it",
+            group
+        );
+        return;
+    }
+
+    println!("{}", table(&["ID", "TAG", "ALIVE", "DELAY"], &rows));
+}
+
+async fn read_source(source: &str) -> Result<String, CliError> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let download = |err| CliError::Download {
+            url: source.to_string(),
+            source: err,
+        };
+
+        return reqwest::get(source)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(download)?
+            .text()
+            .await
+            .map_err(download);
+    }
+
+    std::fs::read_to_string(source).map_err(|err| CliError::Io {
+        path: PathBuf::from(source),
+        source: err,
+    })
+}
+
+async fn stream_logs(client: &Client, follow: bool, lines: usize) -> Result<(), CliError> {
+    let path = format!("/xray/logs?follow={}&lines={}", follow, lines);
+    let mut response = client.send(reqwest::Method::GET, &path, None).await?;
+
+    let mut stdout = std::io::stdout();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| CliError::Response(err.to_string()))?
+    {
+        if stdout
+            .write_all(&chunk)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a client command against the daemon.
+pub async fn run(cli: Cli) -> Result<(), CliError> {
+    let Some(command) = cli.command else {
+        return Ok(());
+    };
+    let client = Client::new(cli.server)?;
+    let json = cli.json;
+
+    match command {
+        // Handled by `main` before a client is needed.
+        Command::Serve(_) => {}
+
+        Command::Group(GroupCommand::List) => {
+            let groups = client.get("/groups").await?;
+            if json {
+                print_json(&groups);
+            } else {
+                print_groups(&groups);
+            }
+        }
+        Command::Group(GroupCommand::Add { name, url }) => {
+            let body = json!({"name": name, "subscribeUrl": url});
+            let group = client.post("/groups", Some(&body)).await?;
+            if json {
+                print_json(&group);
+            } else {
+                print_groups(&json!([group]));
+            }
+        }
+        Command::Group(GroupCommand::Refresh { id }) => {
+            let configs = client
+                .post(&format!("/groups/{}/refresh", id), None)
+                .await?;
+            if json {
+                print_json(&configs);
+            } else {
+                print_configs(&configs);
+            }
+        }
+        Command::Group(GroupCommand::Delete { id }) => {
+            client.delete(&format!("/groups/{}", id)).await?;
+            if !json {
+                println!("Deleted group {}", id);
+            }
+        }
+
+        Command::Config(ConfigCommand::Import { source }) => {
+            let content = read_source(&source).await?;
+            let config = serde_json::from_str::<Value>(&content)
+                .map_err(|err| CliError::Invalid(format!("{} is not JSON: {}", source, err)))?;
+
+            let result = client.post("/xray/config", Some(&config)).await?;
+            if json {
+                print_json(&result);
+            } else {
+                println!("Imported {}: {}", source, cell(result.get("applied")));
+            }
+        }
+        Command::Config(ConfigCommand::Export { output }) => {
+            let config = client.get("/xray/config").await?;
+            let content = serde_json::to_string_pretty(&config).unwrap_or_default();
+
+            match output {
+                Some(path) => std::fs::write(&path, content + "\n")
+                    .map_err(|source| CliError::Io { path, source })?,
+                None => println!("{}", content),
+            }
+        }
+
+        Command::Xray(XrayCommand::Start) => {
+            client.post("/xray/on", None).await?;
+            if !json {
+                println!("xray started");
+            }
+        }
+        Command::Xray(XrayCommand::Stop) => {
+            client.post("/xray/off", None).await?;
+            if !json {
+                println!("xray stopped");
+            }
+        }
+        Command::Xray(XrayCommand::Restart) => {
+            let result = client.post("/xray/restart", None).await?;
+            if json {
+                print_json(&result);
+            } else {
+                println!("xray restarted");
+            }
+        }
+        Command::Xray(XrayCommand::Status) => {
+            let status = client.get("/xray").await?;
+            if json {
+                print_json(&status);
+            } else {
+                print_status(&status);
+            }
+        }
+        Command::Xray(XrayCommand::Logs { follow, lines }) => {
+            stream_logs(&client, follow, lines).await?;
+        }
+
+        Command::Check { group } => {
+            let probes = probes_of(group, client.get("/xray/observatory/status").await?);
+            if json {
+                print_json(&Value::Array(probes));
+            } else {
+                print_probes(group, &probes);
+            }
+        }
+    }
+
+    Ok(())
+}
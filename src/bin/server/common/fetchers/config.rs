@@ -1,7 +1,15 @@
-use std::error::Error;
+use std::{error::Error, time::Duration};
+
+use crate::utils::settings::Settings;
 
 pub async fn fetch(url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let response = reqwest::get(url).await?;
+    let settings = Settings::get().subscriptions;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(settings.timeout))
+        .user_agent(settings.user_agent)
+        .build()?;
+
+    let response = client.get(url).send().await?;
 
     if !response.status().is_success() {
         return Err(format!("Request failed with status: {}", response.status()).into());
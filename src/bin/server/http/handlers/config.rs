@@ -23,9 +23,20 @@ use crate::{
         },
         db::TransactionManager,
         repository::config::{ConfigModel, ConfigRepository},
+        xray::{api::OutboundStatus, observatory},
     },
 };
 
+/// A config with the observatory's latest probe of its outbound, if any.
+#[derive(Serialize)]
+struct ConfigWithStatus {
+    #[serde(flatten)]
+    config: XrayOutboundClientConfigModel,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<OutboundStatus>,
+}
+
 #[derive(Serialize)]
 struct CreateConfigsResponseSuccess {
     id: i32,
@@ -115,11 +126,24 @@ pub async fn get_paginated_configs_by_group_id(
     Path(id): Path<i32>,
     Query(pagination): Query<PaginationParams>,
 ) -> impl IntoResponse {
-    match TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
+    let result = TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
         ConfigRepository::get_by_group_id_with_pagination(&tx, id, pagination)
-    }) {
+    });
+
+    match result {
         Ok(data) => match config_models_to_xray_outbounds(data) {
-            Ok(configs) => (StatusCode::OK, Json(configs)).into_response(),
+            Ok(configs) => {
+                let mut statuses = observatory::status_by_config(&state.xray_service).await;
+                let configs: Vec<ConfigWithStatus> = configs
+                    .into_iter()
+                    .map(|config| ConfigWithStatus {
+                        status: statuses.remove(&config.id),
+                        config,
+                    })
+                    .collect();
+
+                (StatusCode::OK, Json(configs)).into_response()
+            }
             Err(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({"error": e.to_string()})),
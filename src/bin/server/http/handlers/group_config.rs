@@ -11,15 +11,10 @@ use url::Url;
 use crate::{
     http::{models::xray_config::XrayOutboundClientConfigModel, server::AppState},
     services::{
-        common::{
-            convertors::{config_model_to_xray_outbound, config_models_to_xray_outbounds},
-            process_config,
-        },
+        common::convertors::config_models_to_xray_outbounds,
         db::TransactionManager,
-        repository::{
-            config::{ConfigModel, ConfigRepository},
-            group::GroupRepository,
-        },
+        repository::{config::ConfigRepository, group::GroupRepository},
+        subscriptions::{self, SubscriptionError},
     },
 };
 
@@ -72,61 +67,18 @@ pub async fn refresh_configs_by_group_id(
     State(state): State<Arc<AppState>>,
     Path(group_id): Path<i32>,
 ) -> impl IntoResponse {
-    let group = TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
-        GroupRepository::get_by_id(tx, group_id)
-    });
-
-    match group {
-        Ok(Some(group)) => {
-            let Some(current_sub_url) = group.subscribe_url.as_ref() else {
-                return (
-                    StatusCode::NOT_FOUND,
-                    Json(json!({"error": "Not found subscribe URL"})),
-                )
-                    .into_response();
+    match subscriptions::refresh(&mut state.get_conn(), group_id).await {
+        Ok(configs) => (StatusCode::OK, Json(configs)).into_response(),
+        Err(err) => {
+            let status = match err {
+                SubscriptionError::GroupNotFound(_) | SubscriptionError::NoUrl(_) => {
+                    StatusCode::NOT_FOUND
+                }
+                SubscriptionError::Fetch(_) => StatusCode::BAD_GATEWAY,
+                SubscriptionError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
             };
 
-            let configs = process_config(current_sub_url.as_str()).await.unwrap();
-
-            match TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
-                ConfigRepository::delete_by_group_id(&tx, group_id)?;
-
-                let result = configs
-                    .iter()
-                    .map(|config| {
-                        let data = serde_json::to_string(&config).unwrap();
-                        let extra = config
-                            .extra()
-                            .and_then(|extra| serde_json::to_string(&extra).ok())
-                            .unwrap_or_default();
-
-                        ConfigModel::new(group_id, data, extra)
-                    })
-                    .filter_map(|mut model| {
-                        model.id = ConfigRepository::create(&tx, &model).ok()?;
-                        config_model_to_xray_outbound(model).ok()
-                    })
-                    .collect::<Vec<_>>();
-
-                Ok(result)
-            }) {
-                Ok(result) => (StatusCode::OK, Json(result)).into_response(),
-                Err(e) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({"error": e.to_string()})),
-                )
-                    .into_response(),
-            }
+            (status, Json(json!({"error": err.to_string()}))).into_response()
         }
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": format!("Group with ID {} not found", group_id)})),
-        )
-            .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": e.to_string()})),
-        )
-            .into_response(),
     }
 }
@@ -1,5 +1,9 @@
 pub mod config;
+pub mod frontend;
 pub mod group;
 pub mod group_config;
+pub mod observatory;
+pub mod routing;
+pub mod settings;
+pub mod tproxy;
 pub mod xray;
-pub mod frontend;
@@ -0,0 +1,55 @@
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::{
+    http::{handlers::xray::service_error_response, server::AppState},
+    services::xray::observatory::{self, ObservatoryError, ObservatorySettings},
+};
+
+fn error_response(err: ObservatoryError) -> axum::response::Response {
+    match err {
+        ObservatoryError::Service(err) => service_error_response(err),
+        ObservatoryError::Api(err) => (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+        err => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_observatory() -> impl IntoResponse {
+    match observatory::get_settings() {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn update_observatory(
+    State(state): State<Arc<AppState>>,
+    Json(settings): Json<ObservatorySettings>,
+) -> impl IntoResponse {
+    match observatory::update_settings(&state.xray_service, settings).await {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_observatory_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match observatory::status(&state.xray_service).await {
+        Ok(statuses) => (StatusCode::OK, Json(statuses)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
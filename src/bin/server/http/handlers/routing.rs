@@ -0,0 +1,204 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use elux::XRAY_CONFIG_FILE;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::{
+    http::{
+        handlers::xray::service_error_response, models::xray_file::RoutingRule, server::AppState,
+    },
+    services::xray::{
+        balancers::{self, BalancerEntry, BalancerError},
+        file::XrayFileCore,
+        routing::{self, RoutingRuleEntry, RoutingRuleError},
+        simulator::{self, RouteQuery, SimulateError},
+    },
+};
+
+fn rules_response(
+    result: Result<Vec<RoutingRuleEntry>, RoutingRuleError>,
+) -> axum::response::Response {
+    match result {
+        Ok(rules) => (StatusCode::OK, Json(rules)).into_response(),
+        Err(RoutingRuleError::NotFound(id)) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Routing rule {} not found", id)})),
+        )
+            .into_response(),
+        Err(RoutingRuleError::Service(err)) => service_error_response(err),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+fn balancers_response(
+    result: Result<Vec<BalancerEntry>, BalancerError>,
+) -> axum::response::Response {
+    match result {
+        Ok(balancers) => (StatusCode::OK, Json(balancers)).into_response(),
+        Err(BalancerError::NotFound(tag)) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Balancer '{}' not found", tag)})),
+        )
+            .into_response(),
+        Err(BalancerError::Service(err)) => service_error_response(err),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_routing_rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    rules_response(routing::list_rules(&mut state.get_conn()))
+}
+
+#[axum::debug_handler]
+pub async fn create_routing_rule(
+    State(state): State<Arc<AppState>>,
+    Json(rule): Json<RoutingRule>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::create_rule(&state.xray_service, &mut conn, rule).await)
+}
+
+#[axum::debug_handler]
+pub async fn update_routing_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(rule): Json<RoutingRule>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::update_rule(&state.xray_service, &mut conn, id, rule).await)
+}
+
+#[axum::debug_handler]
+pub async fn delete_routing_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::delete_rule(&state.xray_service, &mut conn, id).await)
+}
+
+#[axum::debug_handler]
+pub async fn enable_routing_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::set_rule_enabled(&state.xray_service, &mut conn, id, true).await)
+}
+
+#[axum::debug_handler]
+pub async fn disable_routing_rule(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::set_rule_enabled(&state.xray_service, &mut conn, id, false).await)
+}
+
+#[axum::debug_handler]
+pub async fn reorder_routing_rules(
+    State(state): State<Arc<AppState>>,
+    Json(ids): Json<Vec<i32>>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    rules_response(routing::reorder_rules(&state.xray_service, &mut conn, &ids).await)
+}
+
+#[axum::debug_handler]
+pub async fn get_balancers() -> impl IntoResponse {
+    balancers_response(balancers::list_balancers())
+}
+
+#[axum::debug_handler]
+pub async fn create_balancer(
+    State(state): State<Arc<AppState>>,
+    Json(entry): Json<BalancerEntry>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    balancers_response(balancers::create_balancer(&state.xray_service, &mut conn, entry).await)
+}
+
+#[axum::debug_handler]
+pub async fn update_balancer(
+    State(state): State<Arc<AppState>>,
+    Path(tag): Path<String>,
+    Json(entry): Json<BalancerEntry>,
+) -> impl IntoResponse {
+    let mut conn = state.get_conn();
+
+    balancers_response(
+        balancers::update_balancer(&state.xray_service, &mut conn, &tag, entry).await,
+    )
+}
+
+#[axum::debug_handler]
+pub async fn delete_balancer(
+    State(state): State<Arc<AppState>>,
+    Path(tag): Path<String>,
+) -> impl IntoResponse {
+    balancers_response(balancers::delete_balancer(&state.xray_service, &tag).await)
+}
+
+#[axum::debug_handler]
+pub async fn simulate_route(
+    State(state): State<Arc<AppState>>,
+    Json(query): Json<RouteQuery>,
+) -> impl IntoResponse {
+    let config = match XrayFileCore::new(XRAY_CONFIG_FILE).read_config() {
+        Ok(config) => config,
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": err.to_string()})),
+            )
+                .into_response();
+        }
+    };
+
+    let mut decision = match simulator::simulate(&config, &query) {
+        Ok(decision) => decision,
+        Err(err @ SimulateError::EmptyQuery) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": err.to_string()})),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({"error": err.to_string()})),
+            )
+                .into_response();
+        }
+    };
+
+    if let Some(index) = decision.rule_index {
+        decision.rule_id = routing::list_rules(&mut state.get_conn())
+            .ok()
+            .and_then(|rules| rules.into_iter().filter(|r| r.enabled).nth(index))
+            .map(|rule| rule.id);
+    }
+
+    (StatusCode::OK, Json(decision)).into_response()
+}
@@ -0,0 +1,26 @@
+use axum::{extract::Json, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::utils::settings::{Settings, SettingsError};
+
+#[axum::debug_handler]
+pub async fn get_settings() -> impl IntoResponse {
+    (StatusCode::OK, Json(Settings::view())).into_response()
+}
+
+/// Writes the settings to `elux.kdl`. `server` and `xray` changes take
+/// effect after a restart, which the response reports.
+#[axum::debug_handler]
+pub async fn update_settings(Json(settings): Json<Settings>) -> impl IntoResponse {
+    match Settings::update(settings) {
+        Ok(view) => (StatusCode::OK, Json(view)).into_response(),
+        Err(err) => {
+            let status = match err {
+                SettingsError::Invalid(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+
+            (status, Json(json!({"error": err.to_string()}))).into_response()
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::{
+    http::{handlers::xray::service_error_response, server::AppState},
+    services::{
+        repository::{tproxy_bypass::TproxyBypassModel, tproxy_device::TproxyDeviceModel},
+        tproxy::{
+            manager::TproxyError, nft::NftError, policy::PolicyError, render::TproxySettings,
+        },
+    },
+};
+
+fn error_response(err: TproxyError) -> axum::response::Response {
+    let status = match err {
+        TproxyError::Xray(err) => return service_error_response(err),
+        TproxyError::Invalid(_) => StatusCode::BAD_REQUEST,
+        TproxyError::BypassNotFound(_) | TproxyError::DeviceNotFound(_) => StatusCode::NOT_FOUND,
+        TproxyError::NoInbound => StatusCode::CONFLICT,
+        TproxyError::Nft(NftError::Command { .. })
+        | TproxyError::Policy(PolicyError::Command { .. }) => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, Json(json!({"error": err.to_string()}))).into_response()
+}
+
+#[axum::debug_handler]
+pub async fn get_tproxy_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.tproxy.status(&mut state.get_conn()).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn enable_tproxy(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state
+        .tproxy
+        .enable(&state.xray_service, &mut state.get_conn())
+        .await
+    {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn disable_tproxy(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state
+        .tproxy
+        .disable(&state.xray_service, &mut state.get_conn())
+        .await
+    {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_tproxy_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.tproxy.settings(&mut state.get_conn()) {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn update_tproxy_settings(
+    State(state): State<Arc<AppState>>,
+    Json(settings): Json<TproxySettings>,
+) -> impl IntoResponse {
+    match state
+        .tproxy
+        .update_settings(&state.xray_service, &mut state.get_conn(), settings)
+        .await
+    {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+/// The ruleset that `on` would load, as plain text.
+#[axum::debug_handler]
+pub async fn get_tproxy_rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.tproxy.render(&mut state.get_conn()).await {
+        Ok(ruleset) => (StatusCode::OK, ruleset).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_tproxy_bypass(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.tproxy.bypass(&mut state.get_conn()) {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn create_tproxy_bypass(
+    State(state): State<Arc<AppState>>,
+    Json(entry): Json<TproxyBypassModel>,
+) -> impl IntoResponse {
+    match state.tproxy.add_bypass(&mut state.get_conn(), entry).await {
+        Ok(entry) => (StatusCode::CREATED, Json(entry)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn delete_tproxy_bypass(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    match state.tproxy.delete_bypass(&mut state.get_conn(), id).await {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn get_gateway_devices(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.tproxy.devices(&mut state.get_conn()) {
+        Ok(devices) => (StatusCode::OK, Json(devices)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn create_gateway_device(
+    State(state): State<Arc<AppState>>,
+    Json(device): Json<TproxyDeviceModel>,
+) -> impl IntoResponse {
+    match state.tproxy.add_device(&mut state.get_conn(), device).await {
+        Ok(device) => (StatusCode::CREATED, Json(device)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn delete_gateway_device(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    match state.tproxy.delete_device(&mut state.get_conn(), id).await {
+        Ok(devices) => (StatusCode::OK, Json(devices)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
@@ -2,28 +2,49 @@ use std::{process::Stdio, sync::Arc};
 
 use axum::{
     Json,
+    body::Body,
     extract::{
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
         ws::{CloseFrame, Message, Utf8Bytes, WebSocket},
     },
-    response::IntoResponse,
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
 use elux::XRAY_CONFIG_FILE;
 use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use tokio::{
-    fs::{self, File},
+    fs::File,
     io::{AsyncBufReadExt, BufReader},
 };
+use tokio_stream::{StreamExt, wrappers::BroadcastStream};
 
 use crate::{
-    http::{models::xray_config::XrayOutboundClientConfig, server::AppState},
+    http::{
+        models::{xray_config::XrayOutboundClientConfig, xray_file::XrayConfig},
+        server::AppState,
+    },
     services::{
         db::TransactionManager,
-        repository::config::{ConfigModel, ConfigRepository},
-        xray::{self, file::XrayFileCore},
+        repository::{
+            config::{ConfigModel, ConfigRepository},
+            xray_state::XrayStateRepository,
+        },
+        xray::{
+            self,
+            apply::write_and_apply,
+            file::{XrayFileCore, managed_id, managed_outbound_ids},
+            history::{self, XrayHistoryError},
+            lint,
+            service::XrayServiceError,
+            state::remember_outbounds,
+            validator::XrayConfigError,
+        },
     },
-    utils::config::AppPaths,
+    utils::{config::AppPaths, tail},
 };
 
 #[axum::debug_handler]
@@ -31,37 +52,167 @@ pub async fn get_xray_status(State(state): State<Arc<AppState>>) -> impl IntoRes
     (StatusCode::OK, Json(state.xray_service.status().await)).into_response()
 }
 
-#[axum::debug_handler]
-pub async fn start_xray(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.xray_service.start().await {
-        true => (StatusCode::OK).into_response(),
-        false => (
+fn config_error_response(err: XrayConfigError) -> axum::response::Response {
+    match err {
+        XrayConfigError::Rejected { output } => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"error": "Xray rejected the configuration", "output": output})),
+        )
+            .into_response(),
+        XrayConfigError::Unsupported(ref capabilities) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"error": err.to_string(), "unsupported": capabilities})),
+        )
+            .into_response(),
+        XrayConfigError::Lint(issues) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({"error": "The configuration has lint errors", "issues": issues})),
+        )
+            .into_response(),
+        XrayConfigError::Invalid(message) => {
+            (StatusCode::BAD_REQUEST, Json(json!({"error": message}))).into_response()
+        }
+        err => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub fn service_error_response(err: XrayServiceError) -> axum::response::Response {
+    match err {
+        XrayServiceError::InvalidConfig(err) => config_error_response(err),
+        XrayServiceError::AlreadyRunning | XrayServiceError::NotRunning => (
+            StatusCode::CONFLICT,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+        err => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("Failed to start Xray")})),
+            Json(json!({"error": err.to_string()})),
         )
             .into_response(),
     }
 }
 
+/// Records the requested run state so it can be restored after elux restarts.
+fn remember_running(state: &AppState, running: bool) {
+    if let Err(err) = TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
+        XrayStateRepository::set_running(tx, running)
+    }) {
+        eprintln!("Failed to persist xray run state: {}", err);
+    }
+}
+
+#[axum::debug_handler]
+pub async fn start_xray(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.xray_service.start().await {
+        Ok(()) => {
+            remember_running(&state, true);
+            (StatusCode::OK).into_response()
+        }
+        Err(err) => service_error_response(err),
+    }
+}
+
 #[axum::debug_handler]
 pub async fn stop_xray(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     match state.xray_service.stop().await {
-        true => (StatusCode::OK,).into_response(),
-        false => (
+        Ok(()) => {
+            remember_running(&state, false);
+            (StatusCode::OK,).into_response()
+        }
+        Err(err) => service_error_response(err),
+    }
+}
+
+#[axum::debug_handler]
+pub async fn restart_xray(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.xray_service.restart().await {
+        Ok(was_running) => {
+            remember_running(&state, true);
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "wasRunning": was_running,
+                    "running": state.xray_service.status().await.running,
+                })),
+            )
+                .into_response()
+        }
+        Err(err) => service_error_response(err),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedOutboundResponse {
+    pub id: i32,
+
+    /// `None` when the config behind the outbound was deleted from the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<i32>,
+
+    #[serde(flatten)]
+    pub config: XrayOutboundClientConfig,
+}
+
+/// Attaches config and group IDs to the managed outbounds.
+fn link_outbounds(
+    state: &AppState,
+    outbounds: Vec<XrayOutboundClientConfig>,
+) -> Result<Vec<AppliedOutboundResponse>, rusqlite::Error> {
+    let ids = outbounds
+        .iter()
+        .filter_map(|outbound| outbound.tag.as_deref().and_then(managed_id))
+        .collect::<Vec<_>>();
+
+    let configs = TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
+        ConfigRepository::get_by_ids(tx, ids.as_slice())
+    })?
+    .unwrap_or_default();
+
+    let outbounds = outbounds
+        .into_iter()
+        .filter_map(|config| {
+            let id = config.tag.as_deref().and_then(managed_id)?;
+            let group_id = configs.iter().find(|c| c.id == id).map(|c| c.group_id);
+
+            Some(AppliedOutboundResponse {
+                id,
+                group_id,
+                config,
+            })
+        })
+        .collect();
+
+    Ok(outbounds)
+}
+
+fn outbounds_response(
+    state: &AppState,
+    result: Result<Vec<XrayOutboundClientConfig>, XrayServiceError>,
+) -> axum::response::Response {
+    let outbounds = match result {
+        Ok(outbounds) => outbounds,
+        Err(err) => return service_error_response(err),
+    };
+
+    match link_outbounds(state, outbounds) {
+        Ok(outbounds) => (StatusCode::OK, Json(outbounds)).into_response(),
+        Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("Failed to stop Xray")})),
+            Json(json!({"error": format!("Failed to link outbounds to configs: {}", e)})),
         )
             .into_response(),
     }
 }
 
 #[axum::debug_handler]
-pub async fn restart_xray(State(state): State<Arc<AppState>>) -> impl IntoResponse {}
-
-#[axum::debug_handler]
-pub async fn get_outbounds() -> impl IntoResponse {
+pub async fn get_outbounds(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     match xray::outbounds::get_outbounds() {
-        Ok(configs) => (StatusCode::OK, Json(configs)).into_response(),
+        Ok(configs) => outbounds_response(&state, Ok(configs)),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"error": format!("Failed to get config: {}", err)})),
@@ -80,7 +231,7 @@ pub async fn update_outbounds(
             ConfigRepository::get_by_ids(tx, ids.as_slice())
         });
 
-    let configs_to_update = match configs_from_db_result {
+    let mut configs_to_update = match configs_from_db_result {
         Ok(configs_opt) => configs_opt.unwrap_or_default(),
         Err(e) => {
             return (
@@ -91,34 +242,134 @@ pub async fn update_outbounds(
         }
     };
 
-    match xray::outbounds::update_outbounds(configs_to_update.as_slice()) {
-        Ok(updated_configs) => (StatusCode::OK, Json(updated_configs)).into_response(),
+    configs_to_update.sort_by_key(|config| ids.iter().position(|id| *id == config.id));
+
+    let result = xray::outbounds::update_outbounds(
+        &state.xray_service,
+        &mut state.get_conn(),
+        configs_to_update.as_slice(),
+    )
+    .await;
+
+    outbounds_response(&state, result)
+}
+
+#[axum::debug_handler]
+pub async fn append_outbound(
+    State(state): State<Arc<AppState>>,
+    Path(config_id): Path<i32>,
+) -> impl IntoResponse {
+    let config = match TransactionManager::execute_with_result(&mut state.get_conn(), |tx| {
+        ConfigRepository::get_by_id(tx, config_id)
+    }) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": format!("Config with ID {} not found", config_id)})),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to retrieve config from database: {}", e)})),
+            )
+                .into_response();
+        }
+    };
+
+    let result =
+        xray::outbounds::append_outbound(&state.xray_service, &mut state.get_conn(), &config).await;
+
+    outbounds_response(&state, result)
+}
+
+#[axum::debug_handler]
+pub async fn reorder_outbounds(
+    State(state): State<Arc<AppState>>,
+    Json(ids): Json<Vec<i32>>,
+) -> impl IntoResponse {
+    let result = xray::outbounds::reorder_outbounds(
+        &state.xray_service,
+        &mut state.get_conn(),
+        ids.as_slice(),
+    )
+    .await;
+
+    outbounds_response(&state, result)
+}
+
+#[axum::debug_handler]
+pub async fn delete_outbounds(
+    State(state): State<Arc<AppState>>,
+    Json(ids): Json<Vec<i32>>,
+) -> impl IntoResponse {
+    if ids.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "No config IDs provided for deletion"})),
+        )
+            .into_response();
+    }
+
+    let result = xray::outbounds::delete_outbounds(
+        &state.xray_service,
+        &mut state.get_conn(),
+        ids.as_slice(),
+    )
+    .await;
+
+    outbounds_response(&state, result)
+}
+
+#[axum::debug_handler]
+pub async fn get_xray_config() -> impl IntoResponse {
+    let xray_core = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    match xray_core.read_config() {
+        Ok(config) => (StatusCode::OK, Json(config)).into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("Failed to update Xray outbounds: {}", err)})),
+            Json(json!({"error": format!("Failed to use config: {}", err)})),
         )
             .into_response(),
     }
 }
 
 #[axum::debug_handler]
-pub async fn delete_outbounds(Json(configs): Json<Vec<i32>>) -> impl IntoResponse {
-    // match xray::outbounds::delete_outbounds(&configs) {
-    //     Ok(configs) => (StatusCode::OK, Json(configs)).into_response(),
-    //     Err(err) => (
-    //         StatusCode::INTERNAL_SERVER_ERROR,
-    //         Json(json!({"error": format!("Failed to use config: {}", err)})),
-    //     )
-    //         .into_response(),
-    // }
+pub async fn update_xray_config(
+    State(state): State<Arc<AppState>>,
+    Json(config): Json<Value>,
+) -> impl IntoResponse {
+    let config: XrayConfig = match serde_json::from_value(config) {
+        Ok(config) => config,
+        Err(err) => {
+            return config_error_response(XrayConfigError::Invalid(format!(
+                "Malformed xray config: {}",
+                err
+            )));
+        }
+    };
+
+    let xray_core = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let outbound_ids = managed_outbound_ids(&config);
+
+    match write_and_apply(&state.xray_service, &xray_core, config).await {
+        Ok(outcome) => {
+            remember_outbounds(&mut state.get_conn(), outbound_ids);
+            (StatusCode::OK, Json(json!({"applied": outcome}))).into_response()
+        }
+        Err(err) => service_error_response(err),
+    }
 }
 
 #[axum::debug_handler]
-pub async fn get_xray_config() -> impl IntoResponse {
+pub async fn lint_xray_config() -> impl IntoResponse {
     let xray_core = XrayFileCore::new(XRAY_CONFIG_FILE);
 
-    match xray_core.read_xray_file() {
-        Ok(config) => (StatusCode::OK, Json(config)).into_response(),
+    match xray_core.read_config() {
+        Ok(config) => (StatusCode::OK, Json(lint::lint(&config))).into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"error": format!("Failed to use config: {}", err)})),
@@ -127,20 +378,125 @@ pub async fn get_xray_config() -> impl IntoResponse {
     }
 }
 
+/// Lints a proposed config without writing it.
+#[axum::debug_handler]
+pub async fn lint_proposed_xray_config(Json(config): Json<Value>) -> impl IntoResponse {
+    match serde_json::from_value::<XrayConfig>(config) {
+        Ok(config) => (StatusCode::OK, Json(lint::lint(&config))).into_response(),
+        Err(err) => config_error_response(XrayConfigError::Invalid(format!(
+            "Malformed xray config: {}",
+            err
+        ))),
+    }
+}
+
 #[axum::debug_handler]
-pub async fn update_xray_config(Json(config): Json<Value>) -> impl IntoResponse {
+pub async fn get_xray_config_history() -> impl IntoResponse {
     let xray_core = XrayFileCore::new(XRAY_CONFIG_FILE);
 
-    match xray_core.write_full_config(&config) {
-        Ok(()) => (StatusCode::OK).into_response(),
+    match history::list(&xray_core) {
+        Ok(revisions) => (StatusCode::OK, Json(revisions)).into_response(),
         Err(err) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("Failed to use config: {}", err)})),
+            Json(json!({"error": format!("Failed to read config history: {}", err)})),
         )
             .into_response(),
     }
 }
 
+/// Server-sent events for outside edits of `xray.json`, starting with the
+/// last one seen.
+#[axum::debug_handler]
+pub async fn get_xray_config_events(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let rx = state.config_events.subscribe();
+    let last = state.config_events.last();
+
+    let changes = tokio_stream::iter(last)
+        .chain(BroadcastStream::new(rx).filter_map(Result::ok))
+        .filter_map(|change| Event::default().event("change").json_data(change).ok())
+        .map(Ok::<_, std::convert::Infallible>);
+
+    Sse::new(changes).keep_alive(KeepAlive::default())
+}
+
+#[axum::debug_handler]
+pub async fn rollback_xray_config(
+    State(state): State<Arc<AppState>>,
+    Path(rev): Path<u64>,
+) -> impl IntoResponse {
+    let xray_core = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    match history::rollback(&state.xray_service, &xray_core, &mut state.get_conn(), rev).await {
+        Ok(outcome) => (StatusCode::OK, Json(json!({"applied": outcome}))).into_response(),
+        Err(XrayHistoryError::NotFound(rev)) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Revision {} not found", rev)})),
+        )
+            .into_response(),
+        Err(XrayHistoryError::Service(err)) => service_error_response(err),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LogsQuery {
+    /// Keep the response open and stream new lines as xray writes them.
+    pub follow: bool,
+
+    /// How many lines from the end of the log file to start with.
+    pub lines: usize,
+}
+
+impl Default for LogsQuery {
+    fn default() -> Self {
+        LogsQuery {
+            follow: false,
+            lines: 100,
+        }
+    }
+}
+
+/// Plain-text counterpart of the log websocket for clients without one.
+#[axum::debug_handler]
+pub async fn get_xray_logs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LogsQuery>,
+) -> impl IntoResponse {
+    // Subscribe first so nothing written while the file is read is lost.
+    let rx = state.xray_service.logs();
+
+    let tail = match tail::last_lines(&AppPaths::get().xray_log, query.lines).await {
+        Ok(tail) => tail,
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to read xray log: {}", err)})),
+            )
+                .into_response();
+        }
+    };
+
+    let headers = [("content-type", "text/plain; charset=utf-8")];
+    if !query.follow {
+        return (StatusCode::OK, headers, tail).into_response();
+    }
+
+    let stream = tokio_stream::once(tail)
+        .chain(BroadcastStream::new(rx).filter_map(|line| line.ok().map(|line| line + "\n")));
+
+    (
+        StatusCode::OK,
+        headers,
+        Body::from_stream(stream.map(Ok::<_, std::convert::Infallible>)),
+    )
+        .into_response()
+}
+
 #[axum::debug_handler]
 pub async fn ws_xray_logs_handler(
     State(state): State<Arc<AppState>>,
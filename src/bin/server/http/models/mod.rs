@@ -1 +1,2 @@
 pub mod xray_config;
+pub mod xray_file;
@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Typed view of `xray.json`. Every section keeps keys it doesn't model in
+/// `extra`, so reading and writing a config never drops anything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XrayConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<LogConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inbounds: Option<Vec<Inbound>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbounds: Option<Vec<Outbound>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Map<String, Value>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observatory: Option<ObservatoryConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst_observatory: Option<BurstObservatoryConfig>,
+
+    /// Either a single pool object or a list of pools.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fakedns: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl XrayConfig {
+    pub fn outbounds(&self) -> &[Outbound] {
+        self.outbounds.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(
+        rename = "loglevel",
+        alias = "logLevel",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub log_level: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_log: Option<bool>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub services: Option<Vec<String>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsConfig {
+    /// Plain addresses or server objects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub servers: Option<Vec<Value>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<Map<String, Value>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_strategy: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inbound {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
+
+    /// A number, a range like `"1000-2000"` or an env reference.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<Value>,
+
+    pub protocol: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_settings: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sniffing: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outbound {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    pub protocol: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_through: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_settings: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_settings: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mux: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_strategy: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_matcher: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<RoutingRule>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balancers: Option<Vec<Balancer>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRule {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub rule_type: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Vec<String>>,
+
+    /// A number or a string like `"53,443,1000-2000"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_port: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inbound_tag: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound_tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balancer_tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_tag: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balancer {
+    pub tag: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<BalancerStrategy>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancerStrategy {
+    /// `random`, `roundRobin`, `leastPing` or `leastLoad`.
+    #[serde(rename = "type")]
+    pub strategy_type: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<BalancerStrategySettings>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Tuning for the `leastLoad` strategy.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancerStrategySettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<u32>,
+
+    #[serde(rename = "maxRTT", skip_serializing_if = "Option::is_none")]
+    pub max_rtt: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baselines: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub costs: Option<Vec<StrategyCost>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Weight applied to outbounds whose tag matches `matcher`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyCost {
+    /// Treat `matcher` as a regular expression instead of an exact tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regexp: Option<bool>,
+
+    #[serde(rename = "match")]
+    pub matcher: String,
+
+    pub value: f64,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub levels: Option<Map<String, Value>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<Map<String, Value>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObservatoryConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_selector: Option<Vec<String>>,
+
+    /// xray spells it `probeURL`, not `probeUrl`.
+    #[serde(rename = "probeURL", skip_serializing_if = "Option::is_none")]
+    pub probe_url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_interval: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_concurrency: Option<bool>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BurstObservatoryConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_selector: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping_config: Option<PingConfig>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PingConfig {
+    /// URL probed through each outbound.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+
+    /// URL probed directly to tell a dead outbound from a dead uplink.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connectivity: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keeps_unknown_keys_in_every_section() {
+        let config = json!({
+            "log": {"loglevel": "warning", "dnsLog": true},
+            "api": {"tag": "api", "services": ["HandlerService"], "future": 1},
+            "dns": {"servers": ["1.1.1.1"], "queryStrategy": "UseIPv4"},
+            "inbounds": [{"tag": "in", "protocol": "socks", "sniffing": {"enabled": true}}],
+            "outbounds": [{"tag": "out", "protocol": "freedom", "targetStrategy": "AsIs"}],
+            "routing": {
+                "domainStrategy": "IPIfNonMatch",
+                "rules": [{"outboundTag": "out", "ruleTag": "r1", "domain": ["example.com"]}],
+                "balancers": [{
+                    "tag": "b",
+                    "selector": ["elux-1-"],
+                    "strategy": {
+                        "type": "leastLoad",
+                        "settings": {
+                            "expected": 2,
+                            "costs": [{"regexp": true, "match": "elux-1-.*", "value": 0.5, "note": "x"}],
+                            "future": "y"
+                        },
+                        "future": "z"
+                    },
+                    "future": 2
+                }],
+                "future": 3
+            },
+            "policy": {"levels": {"0": {"handshake": 4}}, "system": {"statsInboundUplink": true}},
+            "stats": {},
+            "observatory": {"subjectSelector": ["elux-"], "probeURL": "https://example.com", "enableConcurrency": true},
+            "burstObservatory": {"subjectSelector": ["elux-"], "pingConfig": {"destination": "https://example.com", "sampling": 3}},
+            "fakedns": [{"ipPool": "198.18.0.0/15", "poolSize": 65535}],
+            "reverse": {"bridges": []},
+            "transport": {"tcpSettings": {}}
+        });
+
+        let parsed: XrayConfig = serde_json::from_value(config.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), config);
+    }
+
+    #[test]
+    fn reads_and_writes_the_observatory_probe_url_as_xray_spells_it() {
+        let config: XrayConfig = serde_json::from_value(json!({
+            "observatory": {"probeURL": "https://example.com/204"}
+        }))
+        .unwrap();
+        let observatory = config.observatory.unwrap();
+
+        assert_eq!(
+            observatory.probe_url.as_deref(),
+            Some("https://example.com/204")
+        );
+        assert!(observatory.extra.is_empty());
+
+        let written = serde_json::to_value(&observatory).unwrap();
+        assert_eq!(written, json!({"probeURL": "https://example.com/204"}));
+    }
+}
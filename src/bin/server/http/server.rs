@@ -1,12 +1,21 @@
 use axum::{
     Router,
-    routing::{any, delete, get, post},
+    routing::{any, delete, get, post, put},
 };
-use elux::{DB_FILE_NAME, SOCKET};
+use elux::DB_FILE_NAME;
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use reqwest::Method;
-use std::sync::Arc;
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::Path,
+    sync::Arc,
+};
+use tokio::{net::UnixListener, sync::watch};
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
@@ -16,9 +25,26 @@ use crate::{
         frontend::static_handler,
         group::delete_all_groups,
         group_config::refresh_configs_by_group_id,
-        xray::{restart_xray, stop_xray, update_xray_config},
+        observatory::{get_observatory, get_observatory_status, update_observatory},
+        routing::{
+            create_balancer, create_routing_rule, delete_balancer, delete_routing_rule,
+            disable_routing_rule, enable_routing_rule, get_balancers, get_routing_rules,
+            reorder_routing_rules, simulate_route, update_balancer, update_routing_rule,
+        },
+        settings::{get_settings, update_settings},
+        tproxy::{
+            create_gateway_device, create_tproxy_bypass, delete_gateway_device,
+            delete_tproxy_bypass, disable_tproxy, enable_tproxy, get_gateway_devices,
+            get_tproxy_bypass, get_tproxy_rules, get_tproxy_settings, get_tproxy_status,
+            update_tproxy_settings,
+        },
+        xray::{
+            get_xray_config_events, get_xray_config_history, get_xray_logs,
+            lint_proposed_xray_config, lint_xray_config, restart_xray, rollback_xray_config,
+            stop_xray, update_xray_config,
+        },
     },
-    utils::config::AppPaths,
+    utils::{config::AppPaths, settings::Settings},
 };
 use crate::{
     http::handlers::{
@@ -26,13 +52,19 @@ use crate::{
         group::{create_group, delete_group, get_group_by_id, get_list_groups, update_group},
         xray::ws_xray_logs_handler,
     },
-    services::xray::service::XrayService,
+    services::{
+        tproxy::manager::TproxyManager,
+        xray::{
+            service::{SupervisorConfig, XrayService},
+            watcher::ConfigEvents,
+        },
+    },
 };
 
 use crate::{
     http::handlers::xray::{
-        delete_outbounds, get_outbounds, get_xray_config, get_xray_status, start_xray,
-        update_outbounds,
+        append_outbound, delete_outbounds, get_outbounds, get_xray_config, get_xray_status,
+        reorder_outbounds, start_xray, update_outbounds,
     },
     utils,
 };
@@ -40,6 +72,8 @@ use crate::{
 pub struct AppState {
     pub db_pool: Pool<SqliteConnectionManager>,
     pub xray_service: XrayService,
+    pub tproxy: TproxyManager,
+    pub config_events: ConfigEvents,
 }
 
 impl AppState {
@@ -52,7 +86,13 @@ impl AppState {
 
         AppState {
             db_pool: pool,
-            xray_service: XrayService::new(AppPaths::get().xray_config.clone(), AppPaths::get().xray_log.clone()),
+            xray_service: XrayService::new(
+                AppPaths::get().xray_config.clone(),
+                AppPaths::get().xray_log.clone(),
+                SupervisorConfig::from(&Settings::get().supervisor),
+            ),
+            tproxy: TproxyManager::default(),
+            config_events: ConfigEvents::default(),
         }
     }
 
@@ -61,15 +101,13 @@ impl AppState {
     }
 }
 
-pub fn init() -> tokio::task::JoinHandle<()> {
-    let state: Arc<AppState> = Arc::new(AppState::init());
-
+pub fn init(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
     let cors_layer = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers(Any);
 
-    tokio::spawn(async {
+    tokio::spawn(async move {
         let app = Router::new()
             .nest(
                 "/groups",
@@ -93,6 +131,26 @@ pub fn init() -> tokio::task::JoinHandle<()> {
                     )
                     .route("/{id}/refresh", post(refresh_configs_by_group_id)),
             )
+            .route("/settings", get(get_settings).put(update_settings))
+            .nest(
+                "/tproxy",
+                Router::new()
+                    .route("/", get(get_tproxy_status))
+                    .route("/on", post(enable_tproxy))
+                    .route("/off", post(disable_tproxy))
+                    .route(
+                        "/settings",
+                        get(get_tproxy_settings).put(update_tproxy_settings),
+                    )
+                    .route("/rules", get(get_tproxy_rules))
+                    .route("/bypass", get(get_tproxy_bypass).post(create_tproxy_bypass))
+                    .route("/bypass/{id}", delete(delete_tproxy_bypass))
+                    .route(
+                        "/gateway/devices",
+                        get(get_gateway_devices).post(create_gateway_device),
+                    )
+                    .route("/gateway/devices/{id}", delete(delete_gateway_device)),
+            )
             .nest(
                 "/xray",
                 Router::new()
@@ -103,20 +161,174 @@ pub fn init() -> tokio::task::JoinHandle<()> {
                             .post(update_outbounds)
                             .delete(delete_outbounds),
                     )
+                    .route("/outbounds/order", put(reorder_outbounds))
+                    .route("/outbounds/{id}", post(append_outbound))
                     .route("/on", post(start_xray))
                     .route("/off", post(stop_xray))
                     .route("/restart", post(restart_xray))
                     .route("/config", get(get_xray_config).post(update_xray_config))
+                    .route("/config/history", get(get_xray_config_history))
+                    .route("/config/events", get(get_xray_config_events))
+                    .route(
+                        "/config/lint",
+                        get(lint_xray_config).post(lint_proposed_xray_config),
+                    )
+                    .route("/config/rollback/{rev}", post(rollback_xray_config))
+                    .route("/observatory", get(get_observatory).put(update_observatory))
+                    .route("/observatory/status", get(get_observatory_status))
+                    .route(
+                        "/routing/rules",
+                        get(get_routing_rules).post(create_routing_rule),
+                    )
+                    .route("/routing/rules/order", put(reorder_routing_rules))
+                    .route("/routing/simulate", post(simulate_route))
+                    .route(
+                        "/routing/balancers",
+                        get(get_balancers).post(create_balancer),
+                    )
+                    .route(
+                        "/routing/balancers/{tag}",
+                        put(update_balancer).delete(delete_balancer),
+                    )
+                    .route(
+                        "/routing/rules/{id}",
+                        put(update_routing_rule).delete(delete_routing_rule),
+                    )
+                    .route("/routing/rules/{id}/enable", post(enable_routing_rule))
+                    .route("/routing/rules/{id}/disable", post(disable_routing_rule))
+                    .route("/logs", get(get_xray_logs))
                     .route("/logs/ws", any(ws_xray_logs_handler)),
             )
-            .with_state(state)
+            .with_state(state.clone())
             .fallback(static_handler)
             .layer(ServiceBuilder::new().layer(cors_layer));
 
-        let listener = tokio::net::TcpListener::bind(SOCKET).await.unwrap();
+        let settings = Settings::get().server;
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            let _ = stop_tx.send(true);
+        });
+
+        let socket = settings.socket_path();
+        let mode = settings.socket_mode().unwrap_or(0o660);
+        let unix_listener = match bind_socket(&socket, mode) {
+            Ok(listener) => {
+                println!("http server bind on {}", socket.display());
+                Some(listener)
+            }
+            Err(err) if settings.tcp => {
+                eprintln!("Failed to listen on {}: {}", socket.display(), err);
+                None
+            }
+            Err(err) => panic!("Failed to listen on {}: {}", socket.display(), err),
+        };
+
+        let unix_server = async {
+            if let Some(listener) = unix_listener {
+                axum::serve(listener, app.clone())
+                    .with_graceful_shutdown(stopped(stop_rx.clone()))
+                    .await
+                    .unwrap();
+                let _ = fs::remove_file(&socket);
+            }
+        };
+
+        let tcp_server = async {
+            if settings.tcp {
+                let listener = tokio::net::TcpListener::bind(&settings.listen)
+                    .await
+                    .unwrap();
 
-        println!("http server bind on {}", SOCKET);
+                println!("http server bind on {}", settings.listen);
 
-        axum::serve(listener, app).await.unwrap();
+                axum::serve(listener, app.clone())
+                    .with_graceful_shutdown(stopped(stop_rx.clone()))
+                    .await
+                    .unwrap();
+            }
+        };
+
+        tokio::join!(unix_server, tcp_server);
+
+        println!("http server stopped, shutting down xray");
+
+        let _ = state.xray_service.stop().await;
+
+        // Without xray the rules would send all traffic into a closed port.
+        if Settings::get().tproxy.cleanup_on_exit
+            && let Err(err) = state.tproxy.unload(&mut state.get_conn()).await
+        {
+            eprintln!("Failed to remove transparent proxy rules: {}", err);
+        }
     })
 }
+
+/// Binds the control socket with `mode` as its permissions. A socket file
+/// left behind by a crashed elux is replaced; one that still accepts
+/// connections belongs to a running instance and is not, and neither is
+/// anything at `path` that is not a socket.
+fn bind_socket(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file that is not a socket is in the way",
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another elux is listening on it",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    // Owner-only until `mode` is set, so nobody connects in between.
+    // SAFETY: umask only swaps the process file creation mask.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+
+    let listener = listener?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+    Ok(listener)
+}
+
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    let _ = stop.wait_for(|stop| *stop).await;
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                eprintln!("Failed to install SIGTERM handler: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,8 +1,20 @@
+use clap::Parser;
 use eyre::Error;
 use mimalloc::MiMalloc;
 
-use crate::{services::db::DbConnection, utils::config::AppPaths};
+use std::sync::Arc;
 
+use crate::{
+    cli::{Cli, Command, ServeArgs},
+    http::server::AppState,
+    services::{db::DbConnection, xray::binary::XrayBinary},
+    utils::{
+        config::{self, AppPaths},
+        settings::{ENV_PREFIX, Settings},
+    },
+};
+
+mod cli;
 mod common;
 mod handlers;
 mod http;
@@ -12,14 +24,59 @@ mod utils;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-#[tokio::main]
-async fn main() -> eyre::Result<(), Error> {
+async fn serve(args: ServeArgs) -> eyre::Result<(), Error> {
+    if let Some(dir) = args.config_dir {
+        config::set_config_dir(dir);
+    }
+
+    let flags = args
+        .listen
+        .map(|listen| (format!("{}SERVER_LISTEN", ENV_PREFIX), listen))
+        .into_iter()
+        .collect();
+
+    Settings::init(flags)?;
     AppPaths::init();
+    XrayBinary::init();
 
     let db = DbConnection::new()?;
     db.init_schema()?;
 
-    http::server::init().await.unwrap();
+    let state = Arc::new(AppState::init());
+
+    if let Err(err) =
+        services::xray::state::restore(&state.xray_service, &state.tproxy, &mut state.get_conn())
+            .await
+    {
+        eprintln!("Failed to restore xray state: {}", err);
+    }
+
+    services::subscriptions::watch(state.clone());
+    services::tproxy::route::watch(state.clone());
+    services::xray::watcher::watch(state.clone());
+
+    http::server::init(state).await.unwrap();
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> eyre::Result<(), Error> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => serve(ServeArgs::default()).await,
+        Some(Command::Serve(args)) => serve(args).await,
+        Some(_) => {
+            // Let `elux ... | head` end quietly instead of panicking on a
+            // closed stdout.
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+
+            if let Err(err) = cli::run(cli).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
@@ -32,8 +32,62 @@ impl DbConnection {
                 extra TEXT NOT NULL,
                 data TEXT NOT NULL,
                 FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE IF NOT EXISTS xray_state (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                running INTEGER NOT NULL DEFAULT 0,
+                outbound_ids TEXT NOT NULL DEFAULT '[]',
+                tproxy INTEGER NOT NULL DEFAULT 0
+            );
+
+            CREATE TABLE IF NOT EXISTS routing_rules (
+                id INTEGER PRIMARY KEY,
+                position INTEGER NOT NULL,
+                enabled INTEGER NOT NULL DEFAULT 1,
+                data TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS tproxy_settings (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                data TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS tproxy_bypass (
+                id INTEGER PRIMARY KEY,
+                kind TEXT NOT NULL,
+                value TEXT NOT NULL,
+                comment TEXT NULL,
+                UNIQUE (kind, value)
+            );
+
+            CREATE TABLE IF NOT EXISTS tproxy_devices (
+                id INTEGER PRIMARY KEY,
+                mac TEXT NOT NULL UNIQUE,
+                action TEXT NOT NULL,
+                comment TEXT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS tproxy_forwarding (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
             );",
         )?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A private in-memory database with the schema in place.
+    pub fn memory() -> Connection {
+        let db = DbConnection {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.init_schema().unwrap();
+
+        db.conn
+    }
+}
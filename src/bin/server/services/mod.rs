@@ -1,6 +1,7 @@
 pub mod common;
 pub mod db;
-pub mod nftables;
 pub mod repository;
+pub mod subscriptions;
+pub mod tproxy;
 pub mod transaction;
 pub mod xray;
@@ -1,2 +1,8 @@
 pub mod config;
 pub mod group;
+pub mod routing_rule;
+pub mod tproxy_bypass;
+pub mod tproxy_device;
+pub mod tproxy_forwarding;
+pub mod tproxy_settings;
+pub mod xray_state;
@@ -0,0 +1,56 @@
+use rusqlite::{Result as SqliteResult, Transaction, params};
+use serde::{Deserialize, Serialize};
+
+/// A routing rule as the user manages it. Only enabled rules end up in
+/// `routing.rules` of `xray.json`, in `position` order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRuleModel {
+    pub id: i32,
+    pub position: i32,
+    pub enabled: bool,
+    pub data: String,
+}
+
+pub struct RoutingRuleRepository;
+
+impl RoutingRuleRepository {
+    pub fn get_all(tx: &Transaction) -> SqliteResult<Vec<RoutingRuleModel>> {
+        let mut stmt = tx.prepare(
+            "SELECT id, position, enabled, data FROM routing_rules ORDER BY position, id",
+        )?;
+
+        let rules = stmt
+            .query_map([], |row| {
+                Ok(RoutingRuleModel {
+                    id: row.get(0)?,
+                    position: row.get(1)?,
+                    enabled: row.get(2)?,
+                    data: row.get(3)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+
+        Ok(rules)
+    }
+
+    /// Replaces the stored rules with `rules`, positioned in slice order.
+    /// Rules with `id == 0` get a new ID; the others keep theirs.
+    pub fn replace_all(tx: &Transaction, rules: &[RoutingRuleModel]) -> SqliteResult<()> {
+        tx.execute("DELETE FROM routing_rules", [])?;
+
+        // Existing IDs go in first so new rows cannot be handed one of them.
+        let (existing, new): (Vec<_>, Vec<_>) =
+            rules.iter().enumerate().partition(|(_, rule)| rule.id != 0);
+
+        for (position, rule) in existing.into_iter().chain(new) {
+            let id = (rule.id != 0).then_some(rule.id);
+
+            tx.execute(
+                "INSERT INTO routing_rules (id, position, enabled, data) VALUES (?1, ?2, ?3, ?4)",
+                params![id, position as i32, rule.enabled, &rule.data],
+            )?;
+        }
+
+        Ok(())
+    }
+}
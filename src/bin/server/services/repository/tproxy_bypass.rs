@@ -0,0 +1,48 @@
+use rusqlite::{Result as SqliteResult, Transaction, params};
+use serde::{Deserialize, Serialize};
+
+/// One entry of the transparent proxy bypass lists. `kind` tells how
+/// `value` is matched: `uid`, `gid`, `cgroup`, `cidr` or `domain`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TproxyBypassModel {
+    #[serde(default)]
+    pub id: i32,
+    pub kind: String,
+    pub value: String,
+    pub comment: Option<String>,
+}
+
+pub struct TproxyBypassRepository;
+
+impl TproxyBypassRepository {
+    pub fn get_all(tx: &Transaction) -> SqliteResult<Vec<TproxyBypassModel>> {
+        let mut stmt =
+            tx.prepare("SELECT id, kind, value, comment FROM tproxy_bypass ORDER BY kind, id")?;
+
+        let entries = stmt
+            .query_map([], |row| {
+                Ok(TproxyBypassModel {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    value: row.get(2)?,
+                    comment: row.get(3)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    pub fn create(tx: &Transaction, entry: &TproxyBypassModel) -> SqliteResult<i32> {
+        tx.execute(
+            "INSERT INTO tproxy_bypass (kind, value, comment) VALUES (?1, ?2, ?3)",
+            params![&entry.kind, &entry.value, &entry.comment],
+        )?;
+
+        Ok(tx.last_insert_rowid() as i32)
+    }
+
+    pub fn delete(tx: &Transaction, id: i32) -> SqliteResult<usize> {
+        tx.execute("DELETE FROM tproxy_bypass WHERE id = ?1", params![id])
+    }
+}
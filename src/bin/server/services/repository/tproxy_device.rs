@@ -0,0 +1,48 @@
+use rusqlite::{Result as SqliteResult, Transaction, params};
+use serde::{Deserialize, Serialize};
+
+/// A LAN device in gateway mode, by MAC address. `action` is `allow` to
+/// proxy it regardless of its subnet or `deny` to never proxy it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TproxyDeviceModel {
+    #[serde(default)]
+    pub id: i32,
+    pub mac: String,
+    pub action: String,
+    pub comment: Option<String>,
+}
+
+pub struct TproxyDeviceRepository;
+
+impl TproxyDeviceRepository {
+    pub fn get_all(tx: &Transaction) -> SqliteResult<Vec<TproxyDeviceModel>> {
+        let mut stmt =
+            tx.prepare("SELECT id, mac, action, comment FROM tproxy_devices ORDER BY id")?;
+
+        let devices = stmt
+            .query_map([], |row| {
+                Ok(TproxyDeviceModel {
+                    id: row.get(0)?,
+                    mac: row.get(1)?,
+                    action: row.get(2)?,
+                    comment: row.get(3)?,
+                })
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+
+        Ok(devices)
+    }
+
+    pub fn create(tx: &Transaction, device: &TproxyDeviceModel) -> SqliteResult<i32> {
+        tx.execute(
+            "INSERT INTO tproxy_devices (mac, action, comment) VALUES (?1, ?2, ?3)",
+            params![&device.mac, &device.action, &device.comment],
+        )?;
+
+        Ok(tx.last_insert_rowid() as i32)
+    }
+
+    pub fn delete(tx: &Transaction, id: i32) -> SqliteResult<usize> {
+        tx.execute("DELETE FROM tproxy_devices WHERE id = ?1", params![id])
+    }
+}
@@ -0,0 +1,32 @@
+use rusqlite::{Result as SqliteResult, Transaction, params};
+
+/// Forwarding sysctls as they were before gateway mode turned them on, kept
+/// so they can be put back after elux was killed or the machine crashed.
+pub struct TproxyForwardingRepository;
+
+impl TproxyForwardingRepository {
+    /// `(key, value)` pairs, e.g. `("net/ipv4/ip_forward", "0")`.
+    pub fn get_all(tx: &Transaction) -> SqliteResult<Vec<(String, String)>> {
+        let mut stmt = tx.prepare("SELECT key, value FROM tproxy_forwarding ORDER BY key")?;
+
+        let saved = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqliteResult<Vec<_>>>()?;
+
+        Ok(saved)
+    }
+
+    pub fn save(tx: &Transaction, key: &str, value: &str) -> SqliteResult<()> {
+        tx.execute(
+            "INSERT INTO tproxy_forwarding (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete(tx: &Transaction, key: &str) -> SqliteResult<usize> {
+        tx.execute("DELETE FROM tproxy_forwarding WHERE key = ?1", params![key])
+    }
+}
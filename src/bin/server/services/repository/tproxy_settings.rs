@@ -0,0 +1,23 @@
+use rusqlite::{OptionalExtension, Result as SqliteResult, Transaction, params};
+
+/// Transparent proxy settings, stored as one JSON document.
+pub struct TproxySettingsRepository;
+
+impl TproxySettingsRepository {
+    pub fn get(tx: &Transaction) -> SqliteResult<Option<String>> {
+        tx.query_row("SELECT data FROM tproxy_settings WHERE id = 1", [], |row| {
+            row.get(0)
+        })
+        .optional()
+    }
+
+    pub fn save(tx: &Transaction, data: &str) -> SqliteResult<()> {
+        tx.execute(
+            "INSERT INTO tproxy_settings (id, data) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET data = excluded.data",
+            params![data],
+        )?;
+
+        Ok(())
+    }
+}
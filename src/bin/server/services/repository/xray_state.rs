@@ -0,0 +1,66 @@
+use rusqlite::{OptionalExtension, Result as SqliteResult, Transaction, params};
+use serde::{Deserialize, Serialize};
+
+/// What the user last asked xray to be doing; restored when elux starts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct XrayStateModel {
+    pub running: bool,
+    pub outbound_ids: Vec<i32>,
+    pub tproxy: bool,
+}
+
+pub struct XrayStateRepository;
+
+impl XrayStateRepository {
+    pub fn get(tx: &Transaction) -> SqliteResult<XrayStateModel> {
+        let mut stmt =
+            tx.prepare("SELECT running, outbound_ids, tproxy FROM xray_state WHERE id = 1")?;
+
+        let state = stmt
+            .query_row([], |row| {
+                let outbound_ids: String = row.get(1)?;
+                Ok(XrayStateModel {
+                    running: row.get(0)?,
+                    outbound_ids: serde_json::from_str(&outbound_ids).unwrap_or_default(),
+                    tproxy: row.get(2)?,
+                })
+            })
+            .optional()?;
+
+        Ok(state.unwrap_or_default())
+    }
+
+    pub fn save(tx: &Transaction, state: &XrayStateModel) -> SqliteResult<()> {
+        let outbound_ids =
+            serde_json::to_string(&state.outbound_ids).unwrap_or_else(|_| "[]".to_string());
+
+        tx.execute(
+            "INSERT INTO xray_state (id, running, outbound_ids, tproxy) VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET
+                running = excluded.running,
+                outbound_ids = excluded.outbound_ids,
+                tproxy = excluded.tproxy",
+            params![state.running, outbound_ids, state.tproxy],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_running(tx: &Transaction, running: bool) -> SqliteResult<()> {
+        let mut state = Self::get(tx)?;
+        state.running = running;
+        Self::save(tx, &state)
+    }
+
+    pub fn set_outbound_ids(tx: &Transaction, outbound_ids: Vec<i32>) -> SqliteResult<()> {
+        let mut state = Self::get(tx)?;
+        state.outbound_ids = outbound_ids;
+        Self::save(tx, &state)
+    }
+
+    pub fn set_tproxy(tx: &Transaction, tproxy: bool) -> SqliteResult<()> {
+        let mut state = Self::get(tx)?;
+        state.tproxy = tproxy;
+        Self::save(tx, &state)
+    }
+}
@@ -0,0 +1,123 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use rusqlite::Connection;
+use tokio::task::JoinHandle;
+
+use crate::{
+    http::{models::xray_config::XrayOutboundClientConfigModel, server::AppState},
+    services::{
+        common::{convertors::config_model_to_xray_outbound, process_config},
+        db::TransactionManager,
+        repository::{
+            config::{ConfigModel, ConfigRepository},
+            group::GroupRepository,
+        },
+    },
+    utils::settings::Settings,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubscriptionError {
+    #[error("Group with ID {0} not found")]
+    GroupNotFound(i32),
+
+    #[error("Group {0} has no subscription URL")]
+    NoUrl(i32),
+
+    #[error("Failed to fetch the subscription: {0}")]
+    Fetch(std::io::Error),
+
+    #[error("Failed to store the configs: {0}")]
+    Db(#[from] rusqlite::Error),
+}
+
+/// Replaces the configs of a group with what its subscription serves now.
+pub async fn refresh(
+    conn: &mut Connection,
+    group_id: i32,
+) -> Result<Vec<XrayOutboundClientConfigModel>, SubscriptionError> {
+    let group = TransactionManager::execute_with_result(conn, |tx| {
+        GroupRepository::get_by_id(tx, group_id)
+    })?
+    .ok_or(SubscriptionError::GroupNotFound(group_id))?;
+    let url = group
+        .subscribe_url
+        .ok_or(SubscriptionError::NoUrl(group_id))?;
+
+    let configs = process_config(url.as_str())
+        .await
+        .map_err(SubscriptionError::Fetch)?;
+
+    let stored = TransactionManager::execute_with_result(conn, |tx| {
+        ConfigRepository::delete_by_group_id(tx, group_id)?;
+
+        let result = configs
+            .iter()
+            .map(|config| {
+                let data = serde_json::to_string(&config).unwrap();
+                let extra = config
+                    .extra()
+                    .and_then(|extra| serde_json::to_string(&extra).ok())
+                    .unwrap_or_default();
+
+                ConfigModel::new(group_id, data, extra)
+            })
+            .filter_map(|mut model| {
+                model.id = ConfigRepository::create(tx, &model).ok()?;
+                config_model_to_xray_outbound(model).ok()
+            })
+            .collect::<Vec<_>>();
+
+        Ok(result)
+    })?;
+
+    Ok(stored)
+}
+
+/// Refreshes every group with a subscription URL each
+/// `subscriptions.refresh-interval` minutes, `refresh-concurrency` at once.
+pub fn watch(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let settings = Settings::get().subscriptions;
+
+            // Looked at again every minute, so turning it on needs no restart.
+            if settings.refresh_interval == 0 {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+            tokio::time::sleep(Duration::from_secs(settings.refresh_interval * 60)).await;
+
+            let groups = TransactionManager::execute_with_result(
+                &mut state.get_conn(),
+                GroupRepository::get_all,
+            );
+            let ids: Vec<i32> = match groups {
+                Ok(groups) => groups
+                    .into_iter()
+                    .filter(|group| group.subscribe_url.is_some())
+                    .map(|group| group.id)
+                    .collect(),
+                Err(err) => {
+                    eprintln!("Failed to list groups to refresh: {}", err);
+                    continue;
+                }
+            };
+
+            futures::stream::iter(ids)
+                .for_each_concurrent(settings.refresh_concurrency, |id| {
+                    let state = state.clone();
+                    async move {
+                        match refresh(&mut state.get_conn(), id).await {
+                            Ok(configs) => {
+                                println!("Refreshed group {}: {} configs", id, configs.len())
+                            }
+                            Err(err) => eprintln!("Failed to refresh group {}: {}", id, err),
+                        }
+                    }
+                })
+                .await;
+        }
+    })
+}
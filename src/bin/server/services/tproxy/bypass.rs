@@ -0,0 +1,146 @@
+use std::{net::IpAddr, path::Path, time::Duration};
+
+use tokio::net::lookup_host;
+
+use crate::services::{repository::tproxy_bypass::TproxyBypassModel, tproxy::render};
+
+pub const KINDS: &[&str] = &["uid", "gid", "cgroup", "cidr", "domain"];
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The bypass lists as the renderer needs them: domains resolved to
+/// addresses, cgroups that do not exist (yet) left out.
+#[derive(Debug, Clone, Default)]
+pub struct Bypass {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+    pub cgroups: Vec<String>,
+    pub v4: Vec<String>,
+    pub v6: Vec<String>,
+}
+
+fn check_domain(domain: &str) -> Result<(), String> {
+    let valid = domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid domain", domain))
+    }
+}
+
+/// Paths relative to the cgroup v2 root, e.g. `user.slice/games.slice`.
+fn check_cgroup(path: &str) -> Result<(), String> {
+    let valid = !path.is_empty()
+        && path.split('/').all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.contains(['"', '\\'])
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid cgroup path", path))
+    }
+}
+
+/// Validates `entry` and brings its value into the stored form.
+pub fn prepare(mut entry: TproxyBypassModel) -> Result<TproxyBypassModel, String> {
+    let value = entry.value.trim();
+
+    entry.value = match entry.kind.as_str() {
+        "uid" | "gid" => value
+            .parse::<u32>()
+            .map_err(|_| format!("'{}' is not a numeric {}", value, entry.kind))?
+            .to_string(),
+        "cgroup" => {
+            let path = value.trim_start_matches(CGROUP_ROOT).trim_matches('/');
+            check_cgroup(path)?;
+            path.to_string()
+        }
+        "cidr" => {
+            render::check_cidr(value, value.contains(':'))?;
+            value.to_string()
+        }
+        "domain" => {
+            let domain = value.trim_end_matches('.').to_ascii_lowercase();
+            check_domain(&domain)?;
+            domain
+        }
+        kind => {
+            return Err(format!(
+                "Unknown bypass kind '{}', expected one of: {}",
+                kind,
+                KINDS.join(", ")
+            ));
+        }
+    };
+
+    Ok(entry)
+}
+
+async fn resolve_domain(domain: &str) -> Vec<IpAddr> {
+    match tokio::time::timeout(RESOLVE_TIMEOUT, lookup_host((domain, 0))).await {
+        Ok(Ok(addresses)) => addresses.map(|address| address.ip()).collect(),
+        Ok(Err(err)) => {
+            eprintln!("Failed to resolve bypass domain '{}': {}", domain, err);
+            Vec::new()
+        }
+        Err(_) => {
+            eprintln!("Timed out resolving bypass domain '{}'", domain);
+            Vec::new()
+        }
+    }
+}
+
+fn push_unique<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
+
+/// Collects the stored entries. Domains are resolved now, so their
+/// addresses are only as fresh as the last time the rules were loaded.
+pub async fn resolve(entries: &[TproxyBypassModel]) -> Bypass {
+    let mut bypass = Bypass::default();
+
+    for entry in entries {
+        match entry.kind.as_str() {
+            "uid" => {
+                if let Ok(uid) = entry.value.parse() {
+                    push_unique(&mut bypass.uids, uid);
+                }
+            }
+            "gid" => {
+                if let Ok(gid) = entry.value.parse() {
+                    push_unique(&mut bypass.gids, gid);
+                }
+            }
+            // nft refuses to load a rule for a cgroup that does not exist.
+            "cgroup" if Path::new(CGROUP_ROOT).join(&entry.value).is_dir() => {
+                push_unique(&mut bypass.cgroups, entry.value.clone());
+            }
+            "cgroup" => {
+                eprintln!("Skipping missing bypass cgroup '{}'", entry.value);
+            }
+            "cidr" if entry.value.contains(':') => push_unique(&mut bypass.v6, entry.value.clone()),
+            "cidr" => push_unique(&mut bypass.v4, entry.value.clone()),
+            "domain" => {
+                for address in resolve_domain(&entry.value).await {
+                    match address {
+                        IpAddr::V4(v4) => push_unique(&mut bypass.v4, v4.to_string()),
+                        IpAddr::V6(v6) => push_unique(&mut bypass.v6, v6.to_string()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bypass
+}
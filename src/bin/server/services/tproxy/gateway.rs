@@ -0,0 +1,84 @@
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::services::{repository::tproxy_device::TproxyDeviceModel, tproxy::render};
+
+pub const ACTIONS: &[&str] = &["allow", "deny"];
+
+/// Forwarding switches toggled together with gateway mode.
+pub const FORWARDING: &[&str] = &["net/ipv4/ip_forward", "net/ipv6/conf/all/forwarding"];
+
+/// Proxying of other LAN devices that use this machine as their gateway.
+/// Their traffic takes the same `prerouting` TPROXY path as the local one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GatewaySettings {
+    pub enabled: bool,
+
+    /// LAN networks whose forwarded traffic is proxied.
+    pub sources_v4: Vec<String>,
+    pub sources_v6: Vec<String>,
+}
+
+impl GatewaySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        for cidr in &self.sources_v4 {
+            render::check_cidr(cidr, false)?;
+        }
+        for cidr in &self.sources_v6 {
+            render::check_cidr(cidr, true)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts `aa:bb:cc:dd:ee:ff` and `AA-BB-CC-DD-EE-FF`, stores the former.
+fn normalize_mac(mac: &str) -> Option<String> {
+    let octets: Vec<&str> = mac.trim().split([':', '-']).collect();
+
+    let valid = octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()));
+
+    valid.then(|| octets.join(":").to_ascii_lowercase())
+}
+
+/// Validates `device` and brings its MAC address into the stored form.
+pub fn prepare(mut device: TproxyDeviceModel) -> Result<TproxyDeviceModel, String> {
+    device.mac = normalize_mac(&device.mac)
+        .ok_or_else(|| format!("'{}' is not a valid MAC address", device.mac))?;
+
+    if !ACTIONS.contains(&device.action.as_str()) {
+        return Err(format!(
+            "Unknown device action '{}', expected one of: {}",
+            device.action,
+            ACTIONS.join(", ")
+        ));
+    }
+
+    Ok(device)
+}
+
+/// MAC addresses of the devices with `action`.
+pub fn macs(devices: &[TproxyDeviceModel], action: &str) -> Vec<String> {
+    devices
+        .iter()
+        .filter(|device| device.action == action)
+        .map(|device| device.mac.clone())
+        .collect()
+}
+
+fn sysctl_path(key: &str) -> String {
+    format!("/proc/sys/{}", key)
+}
+
+pub fn read_sysctl(key: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(sysctl_path(key))?.trim().to_string())
+}
+
+pub fn write_sysctl(key: &str, value: &str) -> io::Result<()> {
+    fs::write(sysctl_path(key), value)
+}
@@ -0,0 +1,809 @@
+use std::sync::{Arc, Mutex as StdMutex};
+
+use elux::XRAY_CONFIG_FILE;
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio::{fs, sync::Mutex};
+
+use crate::{
+    services::{
+        db::TransactionManager,
+        repository::{
+            tproxy_bypass::{TproxyBypassModel, TproxyBypassRepository},
+            tproxy_device::{TproxyDeviceModel, TproxyDeviceRepository},
+            tproxy_forwarding::TproxyForwardingRepository,
+            tproxy_settings::TproxySettingsRepository,
+            xray_state::XrayStateRepository,
+        },
+        tproxy::{
+            bypass,
+            gateway::{self, FORWARDING},
+            nft::{Nft, NftCli, NftError},
+            policy::{IpCli, PolicyError, PolicyRouting},
+            render::{self, TABLE_FAMILY, TABLE_NAME, TproxySettings},
+            route,
+        },
+        xray::{
+            apply::write_and_apply,
+            file::{XrayFileCore, XrayFileError},
+            service::{XrayService, XrayServiceError},
+            sockopt::ensure_sockopt,
+        },
+    },
+    utils::{config::AppPaths, templates},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TproxyError {
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error("Bypass entry with ID {0} not found")]
+    BypassNotFound(i32),
+
+    #[error("Device with ID {0} not found")]
+    DeviceNotFound(i32),
+
+    #[error("xray.json has no inbound with sockopt.tproxy set to \"tproxy\"")]
+    NoInbound,
+
+    #[error("Failed to access tproxy settings: {0}")]
+    Db(#[from] rusqlite::Error),
+
+    #[error("Malformed stored tproxy settings: {0}")]
+    Stored(#[from] serde_json::Error),
+
+    #[error("Failed to write the ruleset: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error(transparent)]
+    Nft(#[from] NftError),
+
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+
+    #[error(transparent)]
+    Xray(#[from] XrayServiceError),
+
+    #[error("Failed to set {key}: {source}")]
+    Sysctl { key: String, source: std::io::Error },
+}
+
+fn is_duplicate(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == rusqlite::ErrorCode::ConstraintViolation
+    )
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TproxyStatus {
+    /// Whether the rules should be loaded; restored when elux starts.
+    pub enabled: bool,
+
+    /// Whether the `inet proxy` table is loaded right now.
+    pub active: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    /// Whether LAN devices are proxied too.
+    pub gateway: bool,
+
+    /// Interfaces whose outgoing traffic is proxied, configured or detected.
+    pub interfaces: Vec<String>,
+
+    /// Error of the last failed apply.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Renders `proxy.conf`, loads it into nftables and sets up the policy
+/// routing TPROXY depends on.
+pub struct TproxyManager {
+    nft: Arc<dyn Nft>,
+    policy: Arc<dyn PolicyRouting>,
+    lock: Mutex<()>,
+    last_error: StdMutex<Option<String>>,
+}
+
+impl Default for TproxyManager {
+    fn default() -> Self {
+        TproxyManager::new(Arc::new(NftCli), Arc::new(IpCli))
+    }
+}
+
+impl TproxyManager {
+    pub fn new(nft: Arc<dyn Nft>, policy: Arc<dyn PolicyRouting>) -> Self {
+        TproxyManager {
+            nft,
+            policy,
+            lock: Mutex::new(()),
+            last_error: StdMutex::new(None),
+        }
+    }
+
+    pub fn settings(&self, conn: &mut Connection) -> Result<TproxySettings, TproxyError> {
+        let stored = TransactionManager::execute_with_result(conn, TproxySettingsRepository::get)?;
+
+        Ok(match stored {
+            Some(data) => serde_json::from_str(&data)?,
+            None => TproxySettings::default(),
+        })
+    }
+
+    /// The configured interfaces, or the default-route ones if none are set.
+    pub fn interfaces(settings: &TproxySettings) -> Vec<String> {
+        if settings.interfaces.is_empty() {
+            route::default_interfaces()
+        } else {
+            settings.interfaces.clone()
+        }
+    }
+
+    pub fn bypass(&self, conn: &mut Connection) -> Result<Vec<TproxyBypassModel>, TproxyError> {
+        Ok(TransactionManager::execute_with_result(
+            conn,
+            TproxyBypassRepository::get_all,
+        )?)
+    }
+
+    /// Stores a bypass entry and reloads the rules if they are enabled. If
+    /// they fail to load, the entry is dropped again and the rules reloaded
+    /// without it.
+    pub async fn add_bypass(
+        &self,
+        conn: &mut Connection,
+        entry: TproxyBypassModel,
+    ) -> Result<TproxyBypassModel, TproxyError> {
+        let mut entry = bypass::prepare(entry).map_err(TproxyError::Invalid)?;
+
+        let created = TransactionManager::execute_with_result(conn, |tx| {
+            TproxyBypassRepository::create(tx, &entry)
+        });
+        entry.id = match created {
+            Ok(id) => id,
+            Err(err) if is_duplicate(&err) => {
+                return Err(TproxyError::Invalid(format!(
+                    "{} '{}' is already bypassed",
+                    entry.kind, entry.value
+                )));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Err(err) = self.reapply(conn).await {
+            TransactionManager::execute_with_result(conn, |tx| {
+                TproxyBypassRepository::delete(tx, entry.id)
+            })?;
+            let _ = self.reapply(conn).await;
+            return Err(err);
+        }
+
+        Ok(entry)
+    }
+
+    pub async fn delete_bypass(
+        &self,
+        conn: &mut Connection,
+        id: i32,
+    ) -> Result<Vec<TproxyBypassModel>, TproxyError> {
+        let deleted = TransactionManager::execute_with_result(conn, |tx| {
+            TproxyBypassRepository::delete(tx, id)
+        })?;
+        if deleted == 0 {
+            return Err(TproxyError::BypassNotFound(id));
+        }
+
+        self.reapply(conn).await?;
+
+        self.bypass(conn)
+    }
+
+    pub fn devices(&self, conn: &mut Connection) -> Result<Vec<TproxyDeviceModel>, TproxyError> {
+        Ok(TransactionManager::execute_with_result(
+            conn,
+            TproxyDeviceRepository::get_all,
+        )?)
+    }
+
+    /// Stores a gateway device and reloads the rules if they are enabled. If
+    /// they fail to load, the device is dropped again and the rules reloaded
+    /// without it.
+    pub async fn add_device(
+        &self,
+        conn: &mut Connection,
+        device: TproxyDeviceModel,
+    ) -> Result<TproxyDeviceModel, TproxyError> {
+        let mut device = gateway::prepare(device).map_err(TproxyError::Invalid)?;
+
+        let created = TransactionManager::execute_with_result(conn, |tx| {
+            TproxyDeviceRepository::create(tx, &device)
+        });
+        device.id = match created {
+            Ok(id) => id,
+            Err(err) if is_duplicate(&err) => {
+                return Err(TproxyError::Invalid(format!(
+                    "Device '{}' already exists",
+                    device.mac
+                )));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Err(err) = self.reapply(conn).await {
+            TransactionManager::execute_with_result(conn, |tx| {
+                TproxyDeviceRepository::delete(tx, device.id)
+            })?;
+            let _ = self.reapply(conn).await;
+            return Err(err);
+        }
+
+        Ok(device)
+    }
+
+    pub async fn delete_device(
+        &self,
+        conn: &mut Connection,
+        id: i32,
+    ) -> Result<Vec<TproxyDeviceModel>, TproxyError> {
+        let deleted = TransactionManager::execute_with_result(conn, |tx| {
+            TproxyDeviceRepository::delete(tx, id)
+        })?;
+        if deleted == 0 {
+            return Err(TproxyError::DeviceNotFound(id));
+        }
+
+        self.reapply(conn).await?;
+
+        self.devices(conn)
+    }
+
+    /// Turns IPv4 and IPv6 forwarding on for gateway mode, or puts back the
+    /// values from before. Those are stored, so a crash doesn't lose them.
+    fn set_forwarding(&self, conn: &mut Connection, enabled: bool) -> Result<(), TproxyError> {
+        let saved =
+            TransactionManager::execute_with_result(conn, TproxyForwardingRepository::get_all)?;
+        let sysctl_error = |key: &str| {
+            let key = key.to_string();
+            move |source| TproxyError::Sysctl { key, source }
+        };
+
+        if !enabled {
+            for (key, value) in saved {
+                gateway::write_sysctl(&key, &value).map_err(sysctl_error(&key))?;
+                TransactionManager::execute_with_result(conn, |tx| {
+                    TproxyForwardingRepository::delete(tx, &key)
+                })?;
+            }
+            return Ok(());
+        }
+
+        if saved.is_empty() {
+            for key in FORWARDING {
+                let value = gateway::read_sysctl(key).map_err(sysctl_error(key))?;
+                TransactionManager::execute_with_result(conn, |tx| {
+                    TproxyForwardingRepository::save(tx, key, &value)
+                })?;
+            }
+        }
+        for key in FORWARDING {
+            gateway::write_sysctl(key, "1").map_err(sysctl_error(key))?;
+        }
+
+        Ok(())
+    }
+
+    /// The ruleset as it would be loaded now.
+    pub async fn render(&self, conn: &mut Connection) -> Result<String, TproxyError> {
+        let settings = self.settings(conn)?;
+        let bypass = bypass::resolve(&self.bypass(conn)?).await;
+        let devices = self.devices(conn)?;
+        let config = XrayFileCore::new(XRAY_CONFIG_FILE).read_config()?;
+        let port = render::tproxy_port(&config).ok_or(TproxyError::NoInbound)?;
+
+        // xray runs as elux's user, its own traffic must not loop back.
+        let proxy_uid = unsafe { libc::geteuid() };
+
+        Ok(render::render(
+            &templates::get_nft_template(),
+            &settings,
+            &bypass,
+            &devices,
+            &Self::interfaces(&settings),
+            port,
+            proxy_uid,
+        ))
+    }
+
+    /// Renders and loads the rules. A failure past rendering takes down what
+    /// was set up, the rules of an earlier load included.
+    async fn load(&self, conn: &mut Connection) -> Result<(), TproxyError> {
+        let result = async {
+            let settings = self.settings(conn)?;
+            let ruleset = self.render(conn).await?;
+            let path = &AppPaths::get().nft_config;
+
+            fs::write(path, ruleset).await?;
+
+            let installed = async {
+                self.policy
+                    .install(settings.mark_proxy, settings.route_table)
+                    .await?;
+                self.nft.apply(path).await?;
+                self.set_forwarding(conn, settings.gateway.enabled)
+            }
+            .await;
+
+            // Half of it would leave marked traffic nowhere to go.
+            if installed.is_err() {
+                let _ = self.clear(conn, &settings).await;
+            }
+
+            installed
+        }
+        .await;
+
+        *self.last_error.lock().unwrap() = result.as_ref().err().map(ToString::to_string);
+
+        result
+    }
+
+    /// Puts `settings`' mark and socket options on the managed outbounds, or
+    /// takes them off with `None`. `xray.json` is only rewritten on change.
+    async fn mark_outbounds(
+        &self,
+        xray_service: &XrayService,
+        settings: Option<&TproxySettings>,
+    ) -> Result<(), TproxyError> {
+        let wanted = settings.map(TproxySettings::managed_sockopt);
+        xray_service.set_outbound_sockopt(wanted.clone());
+
+        let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+        let config = xray_config.read_config()?;
+
+        let mut marked = config.clone();
+        ensure_sockopt(&mut marked, wanted.as_ref());
+        if marked != config {
+            write_and_apply(xray_service, &xray_config, marked).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Brings the managed outbounds in line with the stored state, e.g. when
+    /// elux starts.
+    pub async fn sync_outbounds(
+        &self,
+        xray_service: &XrayService,
+        conn: &mut Connection,
+    ) -> Result<(), TproxyError> {
+        let enabled =
+            TransactionManager::execute_with_result(conn, XrayStateRepository::get)?.tproxy;
+        let settings = self.settings(conn)?;
+
+        self.mark_outbounds(xray_service, enabled.then_some(&settings))
+            .await
+    }
+
+    /// Marks the outbounds first: with the rules loaded, unmarked xray
+    /// traffic would loop back into xray.
+    pub async fn enable(
+        &self,
+        xray_service: &XrayService,
+        conn: &mut Connection,
+    ) -> Result<TproxyStatus, TproxyError> {
+        {
+            let _guard = self.lock.lock().await;
+            let settings = self.settings(conn)?;
+
+            self.mark_outbounds(xray_service, Some(&settings)).await?;
+            if let Err(err) = self.load(conn).await {
+                let _ = self.mark_outbounds(xray_service, None).await;
+                return Err(err);
+            }
+            TransactionManager::execute_with_result(conn, |tx| {
+                XrayStateRepository::set_tproxy(tx, true)
+            })?;
+        }
+
+        self.status(conn).await
+    }
+
+    pub async fn disable(
+        &self,
+        xray_service: &XrayService,
+        conn: &mut Connection,
+    ) -> Result<TproxyStatus, TproxyError> {
+        {
+            let _guard = self.lock.lock().await;
+
+            self.unload(conn).await?;
+            TransactionManager::execute_with_result(conn, |tx| {
+                XrayStateRepository::set_tproxy(tx, false)
+            })?;
+            self.mark_outbounds(xray_service, None).await?;
+        }
+
+        self.status(conn).await
+    }
+
+    /// Removes the nftables table and the policy routing but keeps them
+    /// enabled, so they come back when elux starts again.
+    pub async fn unload(&self, conn: &mut Connection) -> Result<(), TproxyError> {
+        let settings = self.settings(conn)?;
+
+        self.clear(conn, &settings).await?;
+        *self.last_error.lock().unwrap() = None;
+
+        Ok(())
+    }
+
+    /// Takes down the rules, the policy routing and forwarding, carrying on
+    /// past failures; the first one is returned.
+    async fn clear(
+        &self,
+        conn: &mut Connection,
+        settings: &TproxySettings,
+    ) -> Result<(), TproxyError> {
+        let table = self.nft.delete_table(TABLE_FAMILY, TABLE_NAME).await;
+        let routing = self
+            .policy
+            .remove(settings.mark_proxy, settings.route_table)
+            .await;
+        let forwarding = self.set_forwarding(conn, false);
+
+        table?;
+        routing?;
+        forwarding
+    }
+
+    /// Loads the rules again if they are enabled, e.g. after the settings or
+    /// the tproxy inbound changed.
+    pub async fn reapply(&self, conn: &mut Connection) -> Result<(), TproxyError> {
+        let _guard = self.lock.lock().await;
+
+        let enabled =
+            TransactionManager::execute_with_result(conn, XrayStateRepository::get)?.tproxy;
+        if enabled {
+            self.load(conn).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_settings(
+        &self,
+        xray_service: &XrayService,
+        conn: &mut Connection,
+        settings: TproxySettings,
+    ) -> Result<TproxySettings, TproxyError> {
+        settings.validate().map_err(TproxyError::Invalid)?;
+
+        let previous = self.settings(conn)?;
+        let data = serde_json::to_string(&settings)?;
+        TransactionManager::execute_with_result(conn, |tx| {
+            TproxySettingsRepository::save(tx, &data)
+        })?;
+
+        let moved = (previous.mark_proxy, previous.route_table)
+            != (settings.mark_proxy, settings.route_table);
+        let enabled =
+            TransactionManager::execute_with_result(conn, XrayStateRepository::get)?.tproxy;
+
+        let applied = async {
+            if moved && enabled {
+                self.policy
+                    .remove(previous.mark_proxy, previous.route_table)
+                    .await?;
+            }
+            if enabled {
+                self.mark_outbounds(xray_service, Some(&settings)).await?;
+            }
+
+            self.reapply(conn).await
+        }
+        .await;
+
+        // Back to the settings that worked.
+        if let Err(err) = applied {
+            let data = serde_json::to_string(&previous)?;
+            TransactionManager::execute_with_result(conn, |tx| {
+                TproxySettingsRepository::save(tx, &data)
+            })?;
+            if enabled {
+                let _ = self.mark_outbounds(xray_service, Some(&previous)).await;
+            }
+            let _ = self.reapply(conn).await;
+
+            return Err(err);
+        }
+
+        Ok(settings)
+    }
+
+    pub async fn status(&self, conn: &mut Connection) -> Result<TproxyStatus, TproxyError> {
+        let enabled =
+            TransactionManager::execute_with_result(conn, XrayStateRepository::get)?.tproxy;
+        let settings = self.settings(conn)?;
+        let config = XrayFileCore::new(XRAY_CONFIG_FILE).read_config()?;
+
+        let mut last_error = self.last_error.lock().unwrap().clone();
+        let active = match self.nft.has_table(TABLE_FAMILY, TABLE_NAME).await {
+            Ok(active) => active,
+            Err(err) => {
+                last_error.get_or_insert(err.to_string());
+                false
+            }
+        };
+
+        Ok(TproxyStatus {
+            enabled,
+            active,
+            port: render::tproxy_port(&config),
+            gateway: settings.gateway.enabled,
+            interfaces: Self::interfaces(&settings),
+            last_error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::{
+        services::{db::connection::tests::memory, xray::service::SupervisorConfig},
+        utils::{config, settings::Settings},
+    };
+
+    /// Stands in for both `nft` and `ip`, recording the calls in order.
+    #[derive(Default)]
+    struct Recorder {
+        calls: StdMutex<Vec<String>>,
+        loaded: StdMutex<bool>,
+        fail_apply: AtomicBool,
+    }
+
+    impl Recorder {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.calls.lock().unwrap())
+        }
+    }
+
+    impl Nft for Recorder {
+        fn apply<'a>(&'a self, _path: &'a Path) -> BoxFuture<'a, Result<(), NftError>> {
+            Box::pin(async move {
+                self.record("nft apply".to_string());
+                if self.fail_apply.load(Ordering::SeqCst) {
+                    return Err(NftError::Command {
+                        command: "-f".to_string(),
+                        output: "syntax error".to_string(),
+                    });
+                }
+
+                *self.loaded.lock().unwrap() = true;
+                Ok(())
+            })
+        }
+
+        fn has_table<'a>(
+            &'a self,
+            _family: &'a str,
+            _name: &'a str,
+        ) -> BoxFuture<'a, Result<bool, NftError>> {
+            Box::pin(async move { Ok(*self.loaded.lock().unwrap()) })
+        }
+
+        fn delete_table<'a>(
+            &'a self,
+            family: &'a str,
+            name: &'a str,
+        ) -> BoxFuture<'a, Result<(), NftError>> {
+            Box::pin(async move {
+                self.record(format!("nft delete {} {}", family, name));
+                *self.loaded.lock().unwrap() = false;
+                Ok(())
+            })
+        }
+    }
+
+    impl PolicyRouting for Recorder {
+        fn install(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>> {
+            Box::pin(async move {
+                self.record(format!("ip install {} {}", mark, table));
+                Ok(())
+            })
+        }
+
+        fn remove(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>> {
+            Box::pin(async move {
+                self.record(format!("ip remove {} {}", mark, table));
+                Ok(())
+            })
+        }
+    }
+
+    fn setup(recorder: Recorder) -> (Arc<Recorder>, TproxyManager, XrayService) {
+        config::tests::init();
+
+        let recorder = Arc::new(recorder);
+        let manager = TproxyManager::new(recorder.clone(), recorder.clone());
+        let paths = AppPaths::get();
+        let xray_service = XrayService::new(
+            paths.xray_config.clone(),
+            paths.xray_log.clone(),
+            SupervisorConfig::from(&Settings::get().supervisor),
+        );
+
+        (recorder, manager, xray_service)
+    }
+
+    fn tproxy_enabled(conn: &mut Connection) -> bool {
+        TransactionManager::execute_with_result(conn, XrayStateRepository::get)
+            .unwrap()
+            .tproxy
+    }
+
+    #[tokio::test]
+    async fn enable_routes_marked_traffic_before_loading_rules() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        let status = manager.enable(&xray_service, &mut conn).await.unwrap();
+
+        assert_eq!(recorder.take(), ["ip install 200 100", "nft apply"]);
+        assert!(status.enabled && status.active);
+        assert_eq!(status.port, Some(18889));
+        assert!(status.last_error.is_none());
+        assert!(tproxy_enabled(&mut conn));
+    }
+
+    #[tokio::test]
+    async fn disable_takes_down_rules_and_routing() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        manager.enable(&xray_service, &mut conn).await.unwrap();
+        recorder.take();
+        let status = manager.disable(&xray_service, &mut conn).await.unwrap();
+
+        assert_eq!(
+            recorder.take(),
+            ["nft delete inet proxy", "ip remove 200 100"]
+        );
+        assert!(!status.enabled && !status.active);
+        assert!(!tproxy_enabled(&mut conn));
+    }
+
+    #[tokio::test]
+    async fn unload_keeps_rules_enabled() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        manager.enable(&xray_service, &mut conn).await.unwrap();
+        recorder.take();
+        manager.unload(&mut conn).await.unwrap();
+
+        assert_eq!(
+            recorder.take(),
+            ["nft delete inet proxy", "ip remove 200 100"]
+        );
+        assert!(tproxy_enabled(&mut conn));
+
+        manager.reapply(&mut conn).await.unwrap();
+        assert_eq!(recorder.take(), ["ip install 200 100", "nft apply"]);
+    }
+
+    #[tokio::test]
+    async fn failed_load_removes_policy_routing() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        recorder.fail_apply.store(true, Ordering::SeqCst);
+        let err = manager.enable(&xray_service, &mut conn).await.unwrap_err();
+
+        assert!(matches!(err, TproxyError::Nft(_)));
+        assert_eq!(
+            recorder.take(),
+            [
+                "ip install 200 100",
+                "nft apply",
+                "nft delete inet proxy",
+                "ip remove 200 100"
+            ]
+        );
+        assert!(!tproxy_enabled(&mut conn));
+
+        let status = manager.status(&mut conn).await.unwrap();
+        assert!(!status.active);
+        assert!(
+            status
+                .last_error
+                .is_some_and(|err| err.contains("syntax error"))
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_bypass_entries_and_devices_the_rules_fail_with() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        manager.enable(&xray_service, &mut conn).await.unwrap();
+        recorder.fail_apply.store(true, Ordering::SeqCst);
+
+        let entry = TproxyBypassModel {
+            id: 0,
+            kind: "uid".to_string(),
+            value: "1000".to_string(),
+            comment: None,
+        };
+        assert!(manager.add_bypass(&mut conn, entry).await.is_err());
+        assert!(manager.bypass(&mut conn).unwrap().is_empty());
+
+        let device = TproxyDeviceModel {
+            id: 0,
+            mac: "aa:bb:cc:dd:ee:ff".to_string(),
+            action: "allow".to_string(),
+            comment: None,
+        };
+        assert!(manager.add_device(&mut conn, device).await.is_err());
+        assert!(manager.devices(&mut conn).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_previous_settings_when_the_new_ones_fail() {
+        let (recorder, manager, xray_service) = setup(Recorder::default());
+        let mut conn = memory();
+
+        manager.enable(&xray_service, &mut conn).await.unwrap();
+        recorder.fail_apply.store(true, Ordering::SeqCst);
+        recorder.take();
+
+        let settings = TproxySettings {
+            mark_proxy: 300,
+            route_table: 110,
+            ..TproxySettings::default()
+        };
+        let result = manager
+            .update_settings(&xray_service, &mut conn, settings)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            manager.settings(&mut conn).unwrap(),
+            TproxySettings::default()
+        );
+        // The old routing goes, the new one is tried and taken down, then the
+        // old one is brought back.
+        assert_eq!(
+            recorder.take(),
+            [
+                "ip remove 200 100",
+                "ip install 300 110",
+                "nft apply",
+                "nft delete inet proxy",
+                "ip remove 300 110",
+                "ip install 200 100",
+                "nft apply",
+                "nft delete inet proxy",
+                "ip remove 200 100"
+            ]
+        );
+    }
+}
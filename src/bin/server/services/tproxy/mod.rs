@@ -0,0 +1,7 @@
+pub mod bypass;
+pub mod gateway;
+pub mod manager;
+pub mod nft;
+pub mod policy;
+pub mod render;
+pub mod route;
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use tokio::process::Command;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NftError {
+    #[error("Failed to run nft: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("nft {command} failed: {output}")]
+    Command { command: String, output: String },
+}
+
+/// The bits of nftables elux needs. A stand-in can replace the real `nft`
+/// binary where it isn't available or must not be touched.
+pub trait Nft: Send + Sync {
+    /// Loads a ruleset file (`nft -f`).
+    fn apply<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), NftError>>;
+
+    fn has_table<'a>(
+        &'a self,
+        family: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<bool, NftError>>;
+
+    fn delete_table<'a>(
+        &'a self,
+        family: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<(), NftError>>;
+}
+
+/// Runs the `nft` binary from `PATH`.
+pub struct NftCli;
+
+impl NftCli {
+    async fn run(&self, args: &[&str]) -> Result<std::process::Output, NftError> {
+        Ok(Command::new("nft").args(args).output().await?)
+    }
+
+    fn failure(args: &[&str], output: std::process::Output) -> NftError {
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        NftError::Command {
+            command: args.join(" "),
+            output: text.trim().to_string(),
+        }
+    }
+}
+
+impl Nft for NftCli {
+    fn apply<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), NftError>> {
+        Box::pin(async move {
+            let path = path.to_string_lossy();
+            let args = ["-f", path.as_ref()];
+            let output = self.run(&args).await?;
+
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(Self::failure(&args, output))
+            }
+        })
+    }
+
+    fn has_table<'a>(
+        &'a self,
+        family: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<bool, NftError>> {
+        Box::pin(async move {
+            let args = ["list", "tables", family];
+            let output = self.run(&args).await?;
+
+            if !output.status.success() {
+                return Err(Self::failure(&args, output));
+            }
+
+            let wanted = format!("table {} {}", family, name);
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|line| line.trim() == wanted))
+        })
+    }
+
+    fn delete_table<'a>(
+        &'a self,
+        family: &'a str,
+        name: &'a str,
+    ) -> BoxFuture<'a, Result<(), NftError>> {
+        Box::pin(async move {
+            if !self.has_table(family, name).await? {
+                return Ok(());
+            }
+
+            let args = ["delete", "table", family, name];
+            let output = self.run(&args).await?;
+
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(Self::failure(&args, output))
+            }
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use futures::future::BoxFuture;
+use serde_json::Value;
+use tokio::process::Command;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("Failed to run ip: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("ip {command} failed: {output}")]
+    Command { command: String, output: String },
+}
+
+/// Routes marked packets to the local stack, which TPROXY needs:
+/// `ip rule add fwmark <mark> table <table>` and
+/// `ip route add local default dev lo table <table>`, for IPv4 and IPv6.
+pub trait PolicyRouting: Send + Sync {
+    /// Adds whatever is missing; calling it again changes nothing.
+    fn install(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>>;
+
+    /// Removes the rules and routes; missing ones are not an error.
+    fn remove(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>>;
+}
+
+/// Runs the `ip` binary from `PATH`.
+pub struct IpCli;
+
+const FAMILIES: [&str; 2] = ["-4", "-6"];
+
+impl IpCli {
+    async fn run(&self, args: &[String]) -> Result<String, PolicyError> {
+        let output = Command::new("ip").args(args).output().await?;
+
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+        }
+
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        Err(PolicyError::Command {
+            command: args.join(" "),
+            output: text.trim().to_string(),
+        })
+    }
+
+    /// How many `fwmark <mark> lookup <table>` rules exist.
+    async fn rule_count(&self, family: &str, mark: u32, table: u32) -> Result<usize, PolicyError> {
+        let output = self
+            .run(&[
+                family.to_string(),
+                "-j".to_string(),
+                "rule".to_string(),
+                "list".to_string(),
+            ])
+            .await?;
+
+        let rules: Vec<Value> = serde_json::from_str(&output).unwrap_or_default();
+        let mark = format!("{:#x}", mark);
+        let table = table.to_string();
+
+        Ok(rules
+            .iter()
+            .filter(|rule| {
+                rule.get("fwmark").and_then(Value::as_str) == Some(mark.as_str())
+                    && rule.get("table").and_then(Value::as_str) == Some(table.as_str())
+            })
+            .count())
+    }
+
+    fn rule_args(family: &str, action: &str, mark: u32, table: u32) -> Vec<String> {
+        [
+            family,
+            "rule",
+            action,
+            "fwmark",
+            &mark.to_string(),
+            "table",
+            &table.to_string(),
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    fn route_args(family: &str, action: &str, table: u32) -> Vec<String> {
+        [
+            family,
+            "route",
+            action,
+            "local",
+            "default",
+            "dev",
+            "lo",
+            "table",
+            &table.to_string(),
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+}
+
+impl PolicyRouting for IpCli {
+    fn install(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>> {
+        Box::pin(async move {
+            for family in FAMILIES {
+                if self.rule_count(family, mark, table).await? == 0 {
+                    self.run(&Self::rule_args(family, "add", mark, table))
+                        .await?;
+                }
+
+                self.run(&Self::route_args(family, "replace", table))
+                    .await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn remove(&self, mark: u32, table: u32) -> BoxFuture<'_, Result<(), PolicyError>> {
+        Box::pin(async move {
+            for family in FAMILIES {
+                for _ in 0..self.rule_count(family, mark, table).await? {
+                    self.run(&Self::rule_args(family, "del", mark, table))
+                        .await?;
+                }
+
+                // Fails when the route is already gone.
+                let _ = self.run(&Self::route_args(family, "del", table)).await;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,498 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http::models::xray_file::XrayConfig,
+    services::{
+        repository::tproxy_device::TproxyDeviceModel,
+        tproxy::{
+            bypass::Bypass,
+            gateway::{self, GatewaySettings},
+        },
+        xray::sockopt::{ManagedSockopt, OutboundSockopt},
+    },
+};
+
+pub const TABLE_FAMILY: &str = "inet";
+pub const TABLE_NAME: &str = "proxy";
+
+/// User-tunable parts of the transparent proxy ruleset. Everything else
+/// comes from the `proxy.conf` template as is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TproxySettings {
+    /// Set on packets that have to go through xray.
+    pub mark_proxy: u32,
+
+    /// Set by xray on its own outgoing traffic (`sockopt.mark`).
+    pub mark_done: u32,
+
+    /// Routing table that delivers `mark_proxy` packets to the local stack.
+    pub route_table: u32,
+
+    /// Destinations that are never proxied.
+    pub exclude_v4: Vec<String>,
+    pub exclude_v6: Vec<String>,
+
+    /// Local users and groups whose traffic is never proxied.
+    pub exclude_uids: Vec<u32>,
+    pub exclude_gids: Vec<u32>,
+
+    /// Only traffic leaving through these interfaces is proxied; empty means
+    /// the interfaces of the default routes, detected when rendering.
+    pub interfaces: Vec<String>,
+
+    pub gateway: GatewaySettings,
+
+    /// Socket options managed outbounds get next to `mark_done`.
+    pub outbound: OutboundSockopt,
+}
+
+impl Default for TproxySettings {
+    fn default() -> Self {
+        TproxySettings {
+            mark_proxy: 200,
+            mark_done: 201,
+            route_table: 100,
+            exclude_v4: [
+                "127.0.0.0/8",
+                "192.168.0.0/16",
+                "172.16.0.0/12",
+                "100.64.0.0/12",
+                "224.0.0.0/12",
+            ]
+            .map(String::from)
+            .to_vec(),
+            exclude_v6: ["::1", "fd7a:115c:a1e0::/48"].map(String::from).to_vec(),
+            exclude_uids: vec![0],
+            exclude_gids: vec![0],
+            interfaces: Vec::new(),
+            gateway: GatewaySettings::default(),
+            outbound: OutboundSockopt::default(),
+        }
+    }
+}
+
+pub fn check_cidr(cidr: &str, v6: bool) -> Result<(), String> {
+    let (address, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+
+    let valid = match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) if !v6 => {
+            prefix.is_empty() || prefix.parse::<u8>().is_ok_and(|p| p <= 32)
+        }
+        Ok(IpAddr::V6(_)) if v6 => {
+            prefix.is_empty() || prefix.parse::<u8>().is_ok_and(|p| p <= 128)
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' is not an IPv{} address or network",
+            cidr,
+            if v6 { 6 } else { 4 }
+        ))
+    }
+}
+
+/// Interface names as the kernel accepts them (`IFNAMSIZ` is 16 with NUL).
+fn check_interface(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() < 16
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '@'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid interface name", name))
+    }
+}
+
+impl TproxySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mark_proxy == 0 || self.mark_done == 0 || self.mark_proxy == self.mark_done {
+            return Err("markProxy and markDone must be distinct and non-zero".to_string());
+        }
+
+        // 0 is unspecified, 253-255 are the kernel's default, main and local.
+        if matches!(self.route_table, 0 | 253..=255) {
+            return Err(format!(
+                "routeTable {} is reserved by the kernel",
+                self.route_table
+            ));
+        }
+
+        for cidr in &self.exclude_v4 {
+            check_cidr(cidr, false)?;
+        }
+        for cidr in &self.exclude_v6 {
+            check_cidr(cidr, true)?;
+        }
+        for name in &self.interfaces {
+            check_interface(name)?;
+        }
+        if let Some(name) = &self.outbound.interface {
+            check_interface(name)?;
+        }
+
+        self.outbound.validate()?;
+        self.gateway.validate()
+    }
+
+    /// `mark_done` is the one mark both nftables and xray's outbounds use.
+    pub fn managed_sockopt(&self) -> ManagedSockopt {
+        ManagedSockopt {
+            mark: self.mark_done,
+            options: self.outbound.clone(),
+        }
+    }
+}
+
+/// Port of the inbound xray accepts TPROXY traffic on.
+pub fn tproxy_port(config: &XrayConfig) -> Option<u16> {
+    config
+        .inbounds
+        .iter()
+        .flatten()
+        .filter(|inbound| {
+            inbound
+                .stream_settings
+                .as_ref()
+                .and_then(|s| s.pointer("/sockopt/tproxy"))
+                .is_some_and(|mode| mode == "tproxy")
+        })
+        .find_map(|inbound| u16::try_from(inbound.port.as_ref()?.as_u64()?).ok())
+}
+
+/// Placeholder in the template replaced by one rule per bypassed cgroup.
+const CGROUPS_MARKER: &str = "# _BYPASS_CGROUPS";
+
+/// First address and prefix length of a network, as bits of a `u128`.
+fn network(cidr: &str) -> Option<(u128, u32, u32)> {
+    let (address, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+
+    let (address, width) = match address.parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => (u128::from(u32::from(v4)), 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    };
+    let prefix = if prefix.is_empty() {
+        width
+    } else {
+        prefix.parse().ok()?
+    };
+
+    Some((address, prefix, width))
+}
+
+/// True if `outer` covers all of `inner`.
+fn contains(outer: &str, inner: &str) -> bool {
+    let (Some((a, a_prefix, width)), Some((b, b_prefix, b_width))) =
+        (network(outer), network(inner))
+    else {
+        return false;
+    };
+
+    let shift = width - a_prefix;
+    width == b_width
+        && a_prefix <= b_prefix
+        && a.checked_shr(shift).unwrap_or(0) == b.checked_shr(shift).unwrap_or(0)
+}
+
+/// `base` followed by the `extra` networks it does not already cover. nft
+/// rejects overlapping intervals in a set, and networks either nest or are
+/// disjoint, so dropping the covered ones is enough.
+fn merge_networks(base: &[String], extra: &[String]) -> Vec<String> {
+    let mut merged = base.to_vec();
+
+    for cidr in extra {
+        if merged.iter().any(|known| contains(known, cidr)) {
+            continue;
+        }
+
+        merged.retain(|known| !contains(cidr, known));
+        merged.push(cidr.clone());
+    }
+
+    merged
+}
+
+fn merge_ids(base: &[u32], extra: &[u32]) -> Vec<u32> {
+    let mut merged = base.to_vec();
+    merged.extend(extra.iter().filter(|id| !base.contains(id)));
+
+    merged
+}
+
+fn set<T: ToString>(items: &[T]) -> Option<String> {
+    if items.is_empty() {
+        return None;
+    }
+
+    let items: Vec<String> = items.iter().map(T::to_string).collect();
+    Some(format!("{{ {} }}", items.join(", ")))
+}
+
+/// True if `line` uses `$name` as a whole variable.
+fn uses(line: &str, name: &str) -> bool {
+    let variable = format!("${}", name);
+
+    line.match_indices(&variable).any(|(at, _)| {
+        !line[at + variable.len()..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Fills the `define`s of the `proxy.conf` template. A value of `None`
+/// removes the define together with every line that uses it, since nft
+/// rejects empty sets, so no `interfaces` means every interface is proxied.
+/// The `bypass` lists are added to the excluded users, groups and networks.
+/// LAN sources and `devices` are only filled in with gateway mode enabled.
+/// The result replaces the `inet proxy` table as one transaction when passed
+/// to `nft -f`.
+pub fn render(
+    template: &str,
+    settings: &TproxySettings,
+    bypass: &Bypass,
+    devices: &[TproxyDeviceModel],
+    interfaces: &[String],
+    port: u16,
+    proxy_uid: u32,
+) -> String {
+    let interfaces: Vec<String> = interfaces
+        .iter()
+        .map(|name| format!("\"{}\"", name))
+        .collect();
+
+    let lan = &settings.gateway;
+    let lan_set = |items: &[String]| if lan.enabled { set(items) } else { None };
+
+    let values: Vec<(&str, Option<String>)> = vec![
+        ("MARK_PROXY", Some(settings.mark_proxy.to_string())),
+        ("MARK_DONE", Some(settings.mark_done.to_string())),
+        (
+            "EXCLUDES_GID",
+            set(&merge_ids(&settings.exclude_gids, &bypass.gids)),
+        ),
+        (
+            "EXCLUDES_UID",
+            set(&merge_ids(&settings.exclude_uids, &bypass.uids)),
+        ),
+        (
+            "EXCLUDES_PROXY_V4",
+            set(&merge_networks(&settings.exclude_v4, &bypass.v4)),
+        ),
+        (
+            "EXCLUDES_PROXY_V6",
+            set(&merge_networks(&settings.exclude_v6, &bypass.v6)),
+        ),
+        ("PROXY_PORT", Some(port.to_string())),
+        ("PROXY_UID", Some(proxy_uid.to_string())),
+        ("OUTGOING_IFACES", set(&interfaces)),
+        ("GATEWAY_SOURCES_V4", lan_set(&lan.sources_v4)),
+        ("GATEWAY_SOURCES_V6", lan_set(&lan.sources_v6)),
+        (
+            "GATEWAY_ALLOW_MACS",
+            lan_set(&gateway::macs(devices, "allow")),
+        ),
+        (
+            "GATEWAY_DENY_MACS",
+            lan_set(&gateway::macs(devices, "deny")),
+        ),
+    ];
+
+    let mut output = Vec::new();
+
+    for line in template.lines() {
+        if let Some(indent) = line.strip_suffix(CGROUPS_MARKER)
+            && indent.trim().is_empty()
+        {
+            for path in &bypass.cgroups {
+                let level = path.split('/').count();
+                output.push(format!(
+                    "{}socket cgroupv2 level {} \"{}\" return;",
+                    indent, level, path
+                ));
+            }
+            continue;
+        }
+
+        let defined = line
+            .trim_start()
+            .strip_prefix("define ")
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|name| values.iter().find(|(n, _)| *n == name));
+
+        match defined {
+            Some((name, Some(value))) => output.push(format!("define {} = {};", name, value)),
+            Some((_, None)) => {}
+            None if values
+                .iter()
+                .any(|(name, value)| value.is_none() && uses(line, name)) => {}
+            None => output.push(line.to_string()),
+        }
+    }
+
+    // Creating and then deleting the table first makes reapplying idempotent.
+    let at = usize::from(output.first().is_some_and(|line| line.starts_with("#!")));
+    output.insert(at, format!("table {} {}", TABLE_FAMILY, TABLE_NAME));
+    output.insert(
+        at + 1,
+        format!("delete table {} {}", TABLE_FAMILY, TABLE_NAME),
+    );
+
+    output.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::templates;
+
+    fn device(mac: &str, action: &str) -> TproxyDeviceModel {
+        TproxyDeviceModel {
+            id: 0,
+            mac: mac.to_string(),
+            action: action.to_string(),
+            comment: None,
+        }
+    }
+
+    fn rendered(settings: &TproxySettings, bypass: &Bypass, interfaces: &[&str]) -> String {
+        let interfaces: Vec<String> = interfaces.iter().map(|name| name.to_string()).collect();
+
+        render(
+            &templates::get_nft_template(),
+            settings,
+            bypass,
+            &[device("aa:bb:cc:dd:ee:ff", "allow")],
+            &interfaces,
+            12345,
+            1000,
+        )
+    }
+
+    fn defines(ruleset: &str) -> Vec<&str> {
+        ruleset
+            .lines()
+            .filter(|line| line.starts_with("define "))
+            .collect()
+    }
+
+    #[test]
+    fn fills_in_port_marks_and_interfaces() {
+        let settings = TproxySettings {
+            mark_proxy: 300,
+            mark_done: 301,
+            ..TproxySettings::default()
+        };
+        let ruleset = rendered(&settings, &Bypass::default(), &["eth0", "wlan0"]);
+        let defines = defines(&ruleset);
+
+        for define in [
+            "define PROXY_PORT = 12345;",
+            "define PROXY_UID = 1000;",
+            "define MARK_PROXY = 300;",
+            "define MARK_DONE = 301;",
+            "define OUTGOING_IFACES = { \"eth0\", \"wlan0\" };",
+        ] {
+            assert!(defines.contains(&define), "{}", define);
+        }
+        assert!(ruleset.contains("oifname != $OUTGOING_IFACES return;"));
+    }
+
+    #[test]
+    fn replaces_the_table_in_one_transaction() {
+        let ruleset = rendered(&TproxySettings::default(), &Bypass::default(), &["eth0"]);
+        let lines: Vec<&str> = ruleset.lines().take(3).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "#!/usr/sbin/nft -f",
+                "table inet proxy",
+                "delete table inet proxy"
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_bypass_into_the_excludes() {
+        let settings = TproxySettings {
+            exclude_v4: vec!["10.0.0.0/8".to_string()],
+            exclude_v6: vec!["fd00::/8".to_string()],
+            exclude_uids: vec![0],
+            exclude_gids: vec![0, 50],
+            ..TproxySettings::default()
+        };
+        let bypass = Bypass {
+            uids: vec![0, 1001],
+            gids: vec![100],
+            cgroups: vec!["user.slice/games.slice".to_string()],
+            // The first ones are inside the configured networks already.
+            v4: vec!["10.1.2.3".to_string(), "192.168.0.0/16".to_string()],
+            v6: vec!["fd00::1".to_string(), "2001:db8::/32".to_string()],
+        };
+        let ruleset = rendered(&settings, &bypass, &["eth0"]);
+        let defines = defines(&ruleset);
+
+        for define in [
+            "define EXCLUDES_UID = { 0, 1001 };",
+            "define EXCLUDES_GID = { 0, 50, 100 };",
+            "define EXCLUDES_PROXY_V4 = { 10.0.0.0/8, 192.168.0.0/16 };",
+            "define EXCLUDES_PROXY_V6 = { fd00::/8, 2001:db8::/32 };",
+        ] {
+            assert!(defines.contains(&define), "{}", define);
+        }
+        assert!(
+            ruleset.contains("    socket cgroupv2 level 2 \"user.slice/games.slice\" return;\n")
+        );
+        assert!(!ruleset.contains(CGROUPS_MARKER));
+    }
+
+    #[test]
+    fn drops_empty_sets_with_the_lines_using_them() {
+        let settings = TproxySettings {
+            exclude_uids: Vec::new(),
+            exclude_gids: Vec::new(),
+            ..TproxySettings::default()
+        };
+        let ruleset = rendered(&settings, &Bypass::default(), &[]);
+
+        for gone in ["EXCLUDES_UID", "EXCLUDES_GID", "OUTGOING_IFACES"] {
+            assert!(!ruleset.contains(gone), "{}", gone);
+        }
+        // Variables sharing a prefix with a dropped one are kept.
+        assert!(ruleset.contains("define EXCLUDES_PROXY_V4 = "));
+        assert!(ruleset.contains("meta mark $MARK_DONE meta mark set 0;"));
+    }
+
+    #[test]
+    fn fills_in_lan_sources_only_in_gateway_mode() {
+        let mut settings = TproxySettings::default();
+        settings.gateway.sources_v4 = vec!["192.168.1.0/24".to_string()];
+        settings.gateway.sources_v6 = vec!["fd00::/64".to_string()];
+
+        let ruleset = rendered(&settings, &Bypass::default(), &["eth0"]);
+        assert!(!ruleset.contains("GATEWAY_"));
+
+        settings.gateway.enabled = true;
+        let ruleset = rendered(&settings, &Bypass::default(), &["eth0"]);
+        let defines = defines(&ruleset);
+
+        for define in [
+            "define GATEWAY_SOURCES_V4 = { 192.168.1.0/24 };",
+            "define GATEWAY_SOURCES_V6 = { fd00::/64 };",
+            "define GATEWAY_ALLOW_MACS = { aa:bb:cc:dd:ee:ff };",
+        ] {
+            assert!(defines.contains(&define), "{}", define);
+        }
+        assert!(!ruleset.contains("GATEWAY_DENY_MACS"));
+    }
+}
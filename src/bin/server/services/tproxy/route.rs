@@ -0,0 +1,141 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::{http::server::AppState, utils::settings::Settings};
+
+const ROUTE_V4: &str = "/proc/net/route";
+const ROUTE_V6: &str = "/proc/net/ipv6_route";
+
+const RTF_UP: u32 = 0x1;
+const RTF_REJECT: u32 = 0x200;
+
+fn usable(iface: &str, flags: &str) -> bool {
+    let flags = u32::from_str_radix(flags, 16).unwrap_or(0);
+
+    iface != "lo" && flags & RTF_UP != 0 && flags & RTF_REJECT == 0
+}
+
+/// Interfaces of `0.0.0.0/0` routes in `/proc/net/route` format.
+fn parse_v4(table: &str) -> Vec<String> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields[..] {
+                [iface, "00000000", _, flags, _, _, _, "00000000", ..] if usable(iface, flags) => {
+                    Some(iface.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Interfaces of `::/0` routes in `/proc/net/ipv6_route` format.
+fn parse_v6(table: &str) -> Vec<String> {
+    let any = "0".repeat(32);
+
+    table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields[..] {
+                [dest, "00", _, _, _, _, _, _, flags, iface]
+                    if dest == any && usable(iface, flags) =>
+                {
+                    Some(iface.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Interfaces that currently carry a default route, IPv4 and IPv6 together,
+/// sorted and without duplicates. Missing tables (no IPv6) count as empty.
+pub fn default_interfaces() -> Vec<String> {
+    let v4 = fs::read_to_string(ROUTE_V4).unwrap_or_default();
+    let v6 = fs::read_to_string(ROUTE_V6).unwrap_or_default();
+
+    let mut interfaces = parse_v4(&v4);
+    interfaces.extend(parse_v6(&v6));
+    interfaces.sort();
+    interfaces.dedup();
+
+    interfaces
+}
+
+/// Reloads the transparent proxy rules whenever the default route moves to
+/// another interface. Only matters while the interfaces are auto-detected.
+pub fn watch(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut known = default_interfaces();
+
+        loop {
+            let interval = Settings::get().tproxy.route_poll_interval;
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+
+            let current = default_interfaces();
+            if current == known {
+                continue;
+            }
+
+            println!(
+                "default route changed: [{}] -> [{}]",
+                known.join(", "),
+                current.join(", ")
+            );
+            known = current;
+
+            let mut conn = state.get_conn();
+            let detected = match state.tproxy.settings(&mut conn) {
+                Ok(settings) => settings.interfaces.is_empty(),
+                Err(err) => {
+                    eprintln!("Failed to read transparent proxy settings: {}", err);
+                    continue;
+                }
+            };
+
+            if detected && let Err(err) = state.tproxy.reapply(&mut conn).await {
+                eprintln!("Failed to reapply transparent proxy rules: {}", err);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0102A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0002A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+wg0\t00000000\t00000000\t0001\t0\t0\t50\t00000000\t0\t0\t0
+lo\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0
+dummy0\t00000000\t00000000\t0201\t0\t0\t0\t00000000\t0\t0\t0
+eth1\t00000000\t0101A8C0\t0002\t0\t0\t200\t00000000\t0\t0\t0
+";
+
+    const IPV6_ROUTE: &str = "\
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003     eth0
+fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+
+    #[test]
+    fn finds_ipv4_default_routes() {
+        assert_eq!(parse_v4(ROUTE), ["eth0", "wg0"]);
+        assert!(parse_v4("").is_empty());
+    }
+
+    #[test]
+    fn finds_ipv6_default_routes() {
+        assert_eq!(parse_v6(IPV6_ROUTE), ["eth0"]);
+        assert!(parse_v6("garbage line").is_empty());
+    }
+}
@@ -0,0 +1,450 @@
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::{fs, process::Command};
+
+use crate::{
+    http::models::xray_file::{ApiConfig, Outbound, RoutingConfig, XrayConfig},
+    services::xray::{
+        binary::XrayBinary,
+        proto::{ProtoReader, ProtoValue},
+    },
+    utils::config::AppPaths,
+};
+
+pub const API_TAG: &str = "api";
+pub const API_LISTEN: &str = "127.0.0.1:10085";
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrayApiError {
+    #[error("xray api {command} failed: {output}")]
+    Command { command: String, output: String },
+
+    #[error("Failed to call xray api: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to serialize api request: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Failed to reach xray api: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("xray api {method} failed: {message}")]
+    Grpc { method: String, message: String },
+}
+
+/// Probe result of one outbound as reported by the observatory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundStatus {
+    pub tag: String,
+    pub alive: bool,
+
+    /// Round trip of the last successful probe, in milliseconds.
+    pub delay: u64,
+
+    /// Unix time of the last successful probe.
+    pub last_seen_time: u64,
+
+    /// Unix time of the last probe.
+    pub last_try_time: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_reason: Option<String>,
+}
+
+/// Runtime control of a running xray instance (HandlerService/RoutingService).
+pub trait XrayApi: Send + Sync {
+    fn add_outbounds<'a>(
+        &'a self,
+        server: &'a str,
+        outbounds: &'a [Outbound],
+    ) -> BoxFuture<'a, Result<(), XrayApiError>>;
+
+    fn remove_outbounds<'a>(
+        &'a self,
+        server: &'a str,
+        tags: &'a [String],
+    ) -> BoxFuture<'a, Result<(), XrayApiError>>;
+
+    /// Replaces all routing rules and balancers.
+    fn replace_routing<'a>(
+        &'a self,
+        server: &'a str,
+        routing: &'a RoutingConfig,
+    ) -> BoxFuture<'a, Result<(), XrayApiError>>;
+
+    /// Latest probe results of `observatory` or `burstObservatory`.
+    fn observatory_status<'a>(
+        &'a self,
+        server: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<OutboundStatus>, XrayApiError>>;
+}
+
+/// Talks to the xray gRPC API through the `xray api` subcommands, which do
+/// the JSON to protobuf conversion for us.
+pub struct XrayCliApi;
+
+impl XrayCliApi {
+    async fn run(&self, command: &str, server: &str, args: &[String]) -> Result<(), XrayApiError> {
+        let output = Command::new(&XrayBinary::get().path)
+            .arg("api")
+            .arg(command)
+            .arg(format!("--server={}", server))
+            .args(args)
+            .output()
+            .await?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        Err(XrayApiError::Command {
+            command: command.to_string(),
+            output: text.trim().to_string(),
+        })
+    }
+
+    async fn run_with_file(
+        &self,
+        command: &str,
+        server: &str,
+        payload: &Value,
+    ) -> Result<(), XrayApiError> {
+        let path = AppPaths::get()
+            .config_dir
+            .join(format!("xray.api.{}.json", rand::random::<u32>()));
+
+        fs::write(&path, serde_json::to_vec(payload)?).await?;
+
+        let result = self
+            .run(command, server, &[path.to_string_lossy().to_string()])
+            .await;
+
+        let _ = fs::remove_file(&path).await;
+
+        result
+    }
+
+    /// Calls a gRPC method that takes an empty request and returns the
+    /// response message. Used where `xray api` has no subcommand.
+    async fn grpc(&self, server: &str, method: &str) -> Result<Vec<u8>, XrayApiError> {
+        let error = |message: String| XrayApiError::Grpc {
+            method: method.to_string(),
+            message,
+        };
+
+        let response = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .timeout(std::time::Duration::from_secs(5))
+            .build()?
+            .post(format!("http://{}/{}", server, method))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(vec![0u8; 5])
+            .send()
+            .await?;
+
+        let headers = response.headers();
+        if let Some(code) = headers.get("grpc-status").filter(|code| *code != "0") {
+            let message = headers
+                .get("grpc-message")
+                .and_then(|message| message.to_str().ok())
+                .unwrap_or_default();
+            return Err(error(format!(
+                "status {}: {}",
+                code.to_str().unwrap_or_default(),
+                message
+            )));
+        }
+
+        if !response.status().is_success() {
+            return Err(error(format!("HTTP {}", response.status())));
+        }
+
+        let body = response.bytes().await?;
+
+        // Length-prefixed message: compression flag, u32 length, payload.
+        let length = body
+            .get(1..5)
+            .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .ok_or_else(|| error("empty response".to_string()))?;
+
+        body.get(5..5 + length)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| error("truncated response".to_string()))
+    }
+}
+
+fn decode_outbound_status(data: &[u8]) -> OutboundStatus {
+    let mut status = OutboundStatus {
+        tag: String::new(),
+        alive: false,
+        delay: 0,
+        last_seen_time: 0,
+        last_try_time: 0,
+        last_error_reason: None,
+    };
+
+    let mut reader = ProtoReader::new(data);
+    while let Some(field) = reader.field() {
+        match field {
+            (1, ProtoValue::Varint(alive)) => status.alive = alive != 0,
+            (2, ProtoValue::Varint(delay)) => status.delay = delay,
+            (3, ProtoValue::Bytes(reason)) => {
+                status.last_error_reason =
+                    Some(String::from_utf8_lossy(reason).into_owned()).filter(|r| !r.is_empty())
+            }
+            (4, ProtoValue::Bytes(tag)) => status.tag = String::from_utf8_lossy(tag).into_owned(),
+            (5, ProtoValue::Varint(time)) => status.last_seen_time = time,
+            (6, ProtoValue::Varint(time)) => status.last_try_time = time,
+            _ => {}
+        }
+    }
+
+    status
+}
+
+/// Embedded messages of field 1, i.e. `GetOutboundStatusResponse.status` and
+/// then `ObservationResult.status`.
+fn first_fields(data: &[u8]) -> Vec<&[u8]> {
+    let mut fields = Vec::new();
+
+    let mut reader = ProtoReader::new(data);
+    while let Some(field) = reader.field() {
+        if let (1, ProtoValue::Bytes(bytes)) = field {
+            fields.push(bytes);
+        }
+    }
+
+    fields
+}
+
+impl XrayApi for XrayCliApi {
+    fn add_outbounds<'a>(
+        &'a self,
+        server: &'a str,
+        outbounds: &'a [Outbound],
+    ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+        Box::pin(async move {
+            if outbounds.is_empty() {
+                return Ok(());
+            }
+            self.run_with_file("ado", server, &json!({ "outbounds": outbounds }))
+                .await
+        })
+    }
+
+    fn remove_outbounds<'a>(
+        &'a self,
+        server: &'a str,
+        tags: &'a [String],
+    ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+        Box::pin(async move {
+            if tags.is_empty() {
+                return Ok(());
+            }
+            self.run("rmo", server, tags).await
+        })
+    }
+
+    fn replace_routing<'a>(
+        &'a self,
+        server: &'a str,
+        routing: &'a RoutingConfig,
+    ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+        Box::pin(async move {
+            self.run_with_file("adrules", server, &json!({ "routing": routing }))
+                .await
+        })
+    }
+
+    /// `xray api` has no observatory subcommand, so this one speaks gRPC to
+    /// the API directly.
+    fn observatory_status<'a>(
+        &'a self,
+        server: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<OutboundStatus>, XrayApiError>> {
+        Box::pin(async move {
+            let response = self
+                .grpc(
+                    server,
+                    "xray.core.app.observatory.command.ObservatoryService/GetOutboundStatus",
+                )
+                .await?;
+
+            Ok(first_fields(&response)
+                .into_iter()
+                .flat_map(first_fields)
+                .map(decode_outbound_status)
+                .collect())
+        })
+    }
+}
+
+/// Makes sure the managed config exposes the API elux needs for live changes.
+pub fn ensure_api(config: &mut XrayConfig) {
+    let api = config.api.get_or_insert_with(|| ApiConfig {
+        tag: Some(API_TAG.to_string()),
+        ..Default::default()
+    });
+
+    if api.listen.is_none() {
+        api.listen = Some(API_LISTEN.to_string());
+    }
+
+    let services = api.services.get_or_insert_with(Vec::new);
+
+    let mut wanted = vec!["HandlerService", "RoutingService"];
+    if config.observatory.is_some() || config.burst_observatory.is_some() {
+        wanted.push("ObservatoryService");
+    }
+
+    for service in wanted {
+        if !services.iter().any(|s| s == service) {
+            services.push(service.to_string());
+        }
+    }
+}
+
+/// Address the API listens on, if the config enables it.
+pub fn api_listen(config: &XrayConfig) -> Option<&str> {
+    config.api.as_ref()?.listen.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, http::header, response::IntoResponse, routing::post};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::services::xray::proto::tests::{bytes_field, varint_field};
+
+    fn outbound_status(tag: &str, alive: bool, delay: u64, error: &str) -> Vec<u8> {
+        let mut data = varint_field(1, alive as u64);
+        data.extend(varint_field(2, delay));
+        data.extend(bytes_field(3, error.as_bytes()));
+        data.extend(bytes_field(4, tag.as_bytes()));
+        data.extend(varint_field(5, 1_700_000_000));
+        data.extend(varint_field(6, 1_700_000_060));
+        data
+    }
+
+    /// `GetOutboundStatusResponse` holding one `ObservationResult`.
+    fn observation(statuses: &[Vec<u8>]) -> Vec<u8> {
+        let result = statuses
+            .iter()
+            .flat_map(|status| bytes_field(1, status))
+            .collect::<Vec<_>>();
+        bytes_field(1, &result)
+    }
+
+    /// Serves `body` as the response to every gRPC call, with `status` as
+    /// `grpc-status`, and returns the address.
+    async fn stand_in(body: Vec<u8>, status: &'static str) -> String {
+        let mut framed = vec![0];
+        framed.extend((body.len() as u32).to_be_bytes());
+        framed.extend(body);
+
+        let app = Router::new().route(
+            "/{*method}",
+            post(move || async move {
+                (
+                    [
+                        (header::CONTENT_TYPE, "application/grpc"),
+                        (header::HeaderName::from_static("grpc-status"), status),
+                        (
+                            header::HeaderName::from_static("grpc-message"),
+                            "observatory not enabled",
+                        ),
+                    ],
+                    framed,
+                )
+                    .into_response()
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        addr.to_string()
+    }
+
+    #[test]
+    fn decodes_outbound_status() {
+        let status = decode_outbound_status(&outbound_status("elux-1-2", true, 120, ""));
+
+        assert_eq!(
+            status,
+            OutboundStatus {
+                tag: "elux-1-2".to_string(),
+                alive: true,
+                delay: 120,
+                last_seen_time: 1_700_000_000,
+                last_try_time: 1_700_000_060,
+                last_error_reason: None,
+            }
+        );
+
+        let status = decode_outbound_status(&outbound_status("elux-1-3", false, 0, "timeout"));
+        assert!(!status.alive);
+        assert_eq!(status.last_error_reason.as_deref(), Some("timeout"));
+    }
+
+    #[tokio::test]
+    async fn reads_observatory_status_from_the_api() {
+        let server = stand_in(
+            observation(&[
+                outbound_status("elux-1-2", true, 120, ""),
+                outbound_status("elux-1-3", false, 0, "timeout"),
+            ]),
+            "0",
+        )
+        .await;
+
+        let statuses = XrayCliApi.observatory_status(&server).await.unwrap();
+
+        assert_eq!(
+            statuses
+                .iter()
+                .map(|status| (status.tag.as_str(), status.alive, status.delay))
+                .collect::<Vec<_>>(),
+            vec![("elux-1-2", true, 120), ("elux-1-3", false, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_grpc_errors() {
+        let server = stand_in(Vec::new(), "12").await;
+
+        let err = XrayCliApi.observatory_status(&server).await.unwrap_err();
+
+        assert!(
+            matches!(&err, XrayApiError::Grpc { message, .. } if message == "status 12: observatory not enabled"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn enables_the_api_services_elux_needs() {
+        let mut config = XrayConfig {
+            observatory: Some(Default::default()),
+            ..Default::default()
+        };
+
+        ensure_api(&mut config);
+
+        let api = config.api.as_ref().unwrap();
+        assert_eq!(api.tag.as_deref(), Some(API_TAG));
+        assert_eq!(api_listen(&config), Some(API_LISTEN));
+        assert_eq!(
+            api.services.as_deref().unwrap(),
+            ["HandlerService", "RoutingService", "ObservatoryService"]
+        );
+    }
+}
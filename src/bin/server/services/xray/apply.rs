@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    http::models::xray_file::{Outbound, RoutingConfig, XrayConfig},
+    services::xray::{
+        api::{XrayApi, XrayApiError, api_listen, ensure_api},
+        file::XrayFileCore,
+        history,
+        lint::{self, Severity},
+        service::{XrayService, XrayServiceError},
+        sockopt::ensure_sockopt,
+        validator::{XrayConfigError, validate_config},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApplyOutcome {
+    /// xray is not running, the file change is picked up on next start.
+    NotRunning,
+    Unchanged,
+    /// Applied through the xray API without dropping connections.
+    Live,
+    Restarted,
+}
+
+/// Changes that can be pushed to a running xray through its API.
+#[derive(Debug, Default)]
+pub struct LivePlan {
+    pub server: String,
+    pub remove: Vec<String>,
+    pub add: Vec<Outbound>,
+    pub routing: Option<RoutingConfig>,
+}
+
+impl LivePlan {
+    pub async fn execute(&self, api: &dyn XrayApi) -> Result<(), XrayApiError> {
+        api.remove_outbounds(&self.server, &self.remove).await?;
+        api.add_outbounds(&self.server, &self.add).await?;
+
+        if let Some(routing) = &self.routing {
+            api.replace_routing(&self.server, routing).await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn tagged(outbounds: &[Outbound]) -> Option<HashMap<&str, &Outbound>> {
+    let mut by_tag = HashMap::new();
+
+    for outbound in outbounds {
+        let tag = outbound.tag.as_deref()?;
+        if by_tag.insert(tag, outbound).is_some() {
+            return None;
+        }
+    }
+
+    Some(by_tag)
+}
+
+/// Everything a live plan cannot touch.
+fn restart_only(config: &XrayConfig) -> XrayConfig {
+    XrayConfig {
+        outbounds: None,
+        routing: None,
+        ..config.clone()
+    }
+}
+
+/// Works out how to move a running xray from `previous` to `next` through
+/// the API. Returns `None` when only a restart can do it: the API is off,
+/// something besides outbounds/routing changed, the default (first) outbound
+/// changed, or outbounds cannot be addressed by a unique tag.
+pub fn plan(previous: &XrayConfig, next: &XrayConfig) -> Option<LivePlan> {
+    let server = api_listen(next)?;
+    if api_listen(previous) != Some(server) {
+        return None;
+    }
+
+    if restart_only(previous) != restart_only(next) {
+        return None;
+    }
+
+    let previous_outbounds = previous.outbounds();
+    let next_outbounds = next.outbounds();
+
+    let mut live = LivePlan {
+        server: server.to_string(),
+        ..Default::default()
+    };
+
+    if previous_outbounds != next_outbounds {
+        if previous_outbounds.first() != next_outbounds.first() {
+            return None;
+        }
+
+        let previous_tags = tagged(previous_outbounds)?;
+        let next_tags = tagged(next_outbounds)?;
+
+        live.remove = previous_outbounds
+            .iter()
+            .filter_map(|o| o.tag.as_deref())
+            .filter(|tag| next_tags.get(tag) != previous_tags.get(tag))
+            .map(str::to_string)
+            .collect();
+
+        live.add = next_outbounds
+            .iter()
+            .filter(|o| {
+                let tag = o.tag.as_deref().unwrap_or_default();
+                previous_tags.get(tag) != next_tags.get(tag)
+            })
+            .cloned()
+            .collect();
+    }
+
+    if previous.routing != next.routing {
+        live.routing = Some(next.routing.clone().unwrap_or_default());
+    }
+
+    Some(live)
+}
+
+/// The checks every change of `xray.json` has to pass, made by elux or by
+/// hand: no lint errors beyond the ones `previous` already has, warnings
+/// are only logged, and `xray run -test`.
+pub async fn check_candidate(
+    previous: &XrayConfig,
+    candidate: &XrayConfig,
+) -> Result<(), XrayConfigError> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = lint::introduced(previous, candidate)
+        .into_iter()
+        .partition(|issue| issue.severity == Severity::Error);
+
+    for warning in &warnings {
+        eprintln!("xray config {}: {}", warning.path, warning.message);
+    }
+
+    if !errors.is_empty() {
+        return Err(XrayConfigError::Lint(errors));
+    }
+
+    validate_config(candidate).await
+}
+
+/// Validates `candidate`, backs up the current file and writes the new one.
+/// Returns the replaced config and the one written. Nothing is touched on
+/// disk unless xray accepts the candidate and it adds no lint errors;
+/// problems the current file already has don't block unrelated changes.
+pub async fn write_checked(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    candidate: XrayConfig,
+) -> Result<(XrayConfig, XrayConfig), XrayServiceError> {
+    write_checked_against(xray_service, xray_config, candidate, None).await
+}
+
+/// Like [`write_checked`], for putting back a config that was in use
+/// before. Lint problems it already had don't block going back to it; only
+/// ones added along the way (the API and socket options elux manages) do.
+pub async fn write_restored(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    candidate: XrayConfig,
+) -> Result<(XrayConfig, XrayConfig), XrayServiceError> {
+    let baseline = candidate.clone();
+
+    write_checked_against(xray_service, xray_config, candidate, Some(&baseline)).await
+}
+
+/// Lint errors are the ones `candidate` adds to `lint_baseline`, or to the
+/// current file without one.
+async fn write_checked_against(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    mut candidate: XrayConfig,
+    lint_baseline: Option<&XrayConfig>,
+) -> Result<(XrayConfig, XrayConfig), XrayServiceError> {
+    ensure_api(&mut candidate);
+    ensure_sockopt(&mut candidate, xray_service.outbound_sockopt().as_ref());
+
+    let previous = xray_config.read_config().map_err(XrayConfigError::from)?;
+
+    check_candidate(lint_baseline.unwrap_or(&previous), &candidate).await?;
+
+    if previous != candidate {
+        history::backup(xray_config).map_err(XrayConfigError::from)?;
+    }
+
+    xray_config
+        .write_config(&candidate)
+        .map_err(XrayConfigError::from)?;
+
+    Ok((previous, candidate))
+}
+
+/// The single path for changing the managed `xray.json`: validates the
+/// candidate, writes it and brings the running xray in line with it.
+pub async fn write_and_apply(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    candidate: XrayConfig,
+) -> Result<ApplyOutcome, XrayServiceError> {
+    let (previous, candidate) = write_checked(xray_service, xray_config, candidate).await?;
+
+    xray_service.apply(&previous, &candidate).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::future::BoxFuture;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::xray::api::OutboundStatus;
+
+    /// Records calls instead of talking to xray; fails the call named in
+    /// `fail`.
+    #[derive(Default)]
+    struct FakeApi {
+        calls: Mutex<Vec<String>>,
+        fail: Option<&'static str>,
+    }
+
+    impl FakeApi {
+        fn call(&self, call: String) -> Result<(), XrayApiError> {
+            let name = call
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            self.calls.lock().unwrap().push(call);
+
+            match self.fail {
+                Some(fail) if fail == name => Err(XrayApiError::Command {
+                    command: name,
+                    output: "failed".to_string(),
+                }),
+                _ => Ok(()),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl XrayApi for FakeApi {
+        fn add_outbounds<'a>(
+            &'a self,
+            server: &'a str,
+            outbounds: &'a [Outbound],
+        ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+            let tags = outbounds
+                .iter()
+                .filter_map(|o| o.tag.as_deref())
+                .collect::<Vec<_>>();
+            let result = self.call(format!("ado {} {}", server, tags.join(",")));
+            Box::pin(async move { result })
+        }
+
+        fn remove_outbounds<'a>(
+            &'a self,
+            server: &'a str,
+            tags: &'a [String],
+        ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+            let result = self.call(format!("rmo {} {}", server, tags.join(",")));
+            Box::pin(async move { result })
+        }
+
+        fn replace_routing<'a>(
+            &'a self,
+            server: &'a str,
+            routing: &'a RoutingConfig,
+        ) -> BoxFuture<'a, Result<(), XrayApiError>> {
+            let rules = routing.rules.as_deref().unwrap_or_default().len();
+            let result = self.call(format!("adrules {} {}", server, rules));
+            Box::pin(async move { result })
+        }
+
+        fn observatory_status<'a>(
+            &'a self,
+            _server: &'a str,
+        ) -> BoxFuture<'a, Result<Vec<OutboundStatus>, XrayApiError>> {
+            Box::pin(async move { Ok(Vec::new()) })
+        }
+    }
+
+    fn config(outbounds: serde_json::Value, rules: serde_json::Value) -> XrayConfig {
+        serde_json::from_value(json!({
+            "api": {"tag": "api", "listen": "127.0.0.1:10085"},
+            "outbounds": outbounds,
+            "routing": {"rules": rules}
+        }))
+        .unwrap()
+    }
+
+    fn previous() -> XrayConfig {
+        config(
+            json!([
+                {"tag": "elux-1-1", "protocol": "vless"},
+                {"tag": "elux-1-2", "protocol": "vless"},
+                {"tag": "direct", "protocol": "freedom"}
+            ]),
+            json!([{"outboundTag": "direct", "domain": ["geosite:private"]}]),
+        )
+    }
+
+    #[tokio::test]
+    async fn pushes_changed_outbounds_and_routing() {
+        let next = config(
+            json!([
+                {"tag": "elux-1-1", "protocol": "vless"},
+                {"tag": "elux-1-2", "protocol": "trojan"},
+                {"tag": "elux-1-3", "protocol": "vless"},
+                {"tag": "direct", "protocol": "freedom"}
+            ]),
+            json!([
+                {"outboundTag": "direct", "domain": ["geosite:private"]},
+                {"outboundTag": "elux-1-3", "domain": ["example.com"]}
+            ]),
+        );
+
+        let live = plan(&previous(), &next).unwrap();
+        let api = FakeApi::default();
+        live.execute(&api).await.unwrap();
+
+        assert_eq!(
+            api.calls(),
+            vec![
+                "rmo 127.0.0.1:10085 elux-1-2",
+                "ado 127.0.0.1:10085 elux-1-2,elux-1-3",
+                "adrules 127.0.0.1:10085 2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_routing_alone_when_it_did_not_change() {
+        let mut next = previous();
+        next.outbounds.as_mut().unwrap().remove(1);
+
+        let live = plan(&previous(), &next).unwrap();
+        let api = FakeApi::default();
+        live.execute(&api).await.unwrap();
+
+        assert_eq!(
+            api.calls(),
+            vec!["rmo 127.0.0.1:10085 elux-1-2", "ado 127.0.0.1:10085 "]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_failed_call() {
+        let mut next = previous();
+        next.outbounds.as_mut().unwrap().remove(1);
+
+        let api = FakeApi {
+            fail: Some("rmo"),
+            ..Default::default()
+        };
+        let err = plan(&previous(), &next)
+            .unwrap()
+            .execute(&api)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, XrayApiError::Command { .. }));
+        assert_eq!(api.calls(), vec!["rmo 127.0.0.1:10085 elux-1-2"]);
+    }
+
+    #[test]
+    fn needs_a_restart_for_anything_else() {
+        // The first outbound is the default route.
+        let mut next = previous();
+        next.outbounds.as_mut().unwrap().swap(0, 1);
+        assert!(plan(&previous(), &next).is_none());
+
+        // Sections the API cannot change.
+        let mut next = previous();
+        next.log = Some(Default::default());
+        assert!(plan(&previous(), &next).is_none());
+
+        // Outbounds that cannot be addressed by tag.
+        let mut next = previous();
+        next.outbounds.as_mut().unwrap()[2].tag = Some("elux-1-2".to_string());
+        assert!(plan(&previous(), &next).is_none());
+
+        // No API to talk to.
+        let mut without_api = previous();
+        without_api.api = None;
+        assert!(plan(&without_api, &without_api).is_none());
+    }
+}
@@ -0,0 +1,391 @@
+use elux::XRAY_CONFIG_FILE;
+use regex::Regex;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http::models::xray_file::{
+        Balancer, BurstObservatoryConfig, ObservatoryConfig, PingConfig, RoutingConfig, XrayConfig,
+    },
+    services::{
+        db::TransactionManager,
+        repository::group::GroupRepository,
+        xray::{
+            apply::write_and_apply,
+            file::{XrayFileCore, XrayFileError, group_selector, selector_group},
+            service::{XrayService, XrayServiceError},
+            validator::XrayConfigError,
+        },
+    },
+    utils::settings::Settings,
+};
+
+pub const STRATEGIES: &[&str] = &["random", "roundRobin", "leastPing", "leastLoad"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum BalancerError {
+    #[error("Balancer '{0}' not found")]
+    NotFound(String),
+
+    #[error("Failed to access groups: {0}")]
+    Db(#[from] rusqlite::Error),
+
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error(transparent)]
+    Service(#[from] XrayServiceError),
+}
+
+impl From<XrayConfigError> for BalancerError {
+    fn from(err: XrayConfigError) -> Self {
+        BalancerError::Service(err.into())
+    }
+}
+
+/// A balancer together with the group it follows, if any. A bound balancer
+/// selects the group's managed outbounds by their shared tag prefix, so it
+/// tracks whatever part of the group is applied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancerEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<i32>,
+
+    #[serde(flatten)]
+    pub balancer: Balancer,
+}
+
+impl From<&Balancer> for BalancerEntry {
+    fn from(balancer: &Balancer) -> Self {
+        let group_id = match balancer.selector.as_deref() {
+            Some([selector]) => selector_group(selector),
+            _ => None,
+        };
+
+        BalancerEntry {
+            group_id,
+            balancer: balancer.clone(),
+        }
+    }
+}
+
+fn entries(config: &XrayConfig) -> Vec<BalancerEntry> {
+    config
+        .routing
+        .iter()
+        .flat_map(|routing| routing.balancers.iter().flatten())
+        .map(BalancerEntry::from)
+        .collect()
+}
+
+fn invalid(message: String) -> XrayConfigError {
+    XrayConfigError::Invalid(message)
+}
+
+fn validate(config: &XrayConfig, balancer: &Balancer) -> Result<(), XrayConfigError> {
+    if balancer.tag.is_empty() {
+        return Err(invalid("Balancer tag must not be empty".to_string()));
+    }
+
+    if let Some(strategy) = &balancer.strategy {
+        if !STRATEGIES.contains(&strategy.strategy_type.as_str()) {
+            return Err(invalid(format!(
+                "Unknown balancer strategy '{}', expected one of: {}",
+                strategy.strategy_type,
+                STRATEGIES.join(", ")
+            )));
+        }
+
+        let costs = strategy
+            .settings
+            .iter()
+            .flat_map(|s| s.costs.iter().flatten());
+        for cost in costs {
+            if cost.regexp == Some(true)
+                && let Err(err) = Regex::new(&cost.matcher)
+            {
+                return Err(invalid(format!(
+                    "Invalid cost regexp '{}': {}",
+                    cost.matcher, err
+                )));
+            }
+        }
+    }
+
+    if let Some(tag) = &balancer.fallback_tag
+        && !config
+            .outbounds()
+            .iter()
+            .any(|outbound| outbound.tag.as_ref() == Some(tag))
+    {
+        return Err(invalid(format!("Unknown fallback outbound '{}'", tag)));
+    }
+
+    Ok(())
+}
+
+/// Selectors of the balancers that use `strategy`, in order of appearance.
+fn strategy_selectors(entries: &[BalancerEntry], strategy: &str) -> Vec<String> {
+    let mut selectors: Vec<String> = Vec::new();
+
+    let balancers = entries
+        .iter()
+        .map(|entry| &entry.balancer)
+        .filter(|balancer| {
+            balancer
+                .strategy
+                .as_ref()
+                .is_some_and(|s| s.strategy_type == strategy)
+        });
+    for selector in balancers.flat_map(|balancer| balancer.selector.iter().flatten()) {
+        if !selectors.contains(selector) {
+            selectors.push(selector.clone());
+        }
+    }
+
+    selectors
+}
+
+/// Drops group selectors that are no longer `wanted` and appends the missing
+/// ones; selectors added by hand are left alone.
+fn merge_selectors(current: Option<Vec<String>>, wanted: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = current
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|s| selector_group(s).is_none() || wanted.contains(s))
+        .collect();
+
+    for selector in wanted {
+        if !merged.contains(selector) {
+            merged.push(selector.clone());
+        }
+    }
+
+    merged
+}
+
+/// Keeps the observatory subjects in line with the balancers that need
+/// them: `leastPing` reads `observatory`, `leastLoad` reads
+/// `burstObservatory`. New observatories probe as `observatory` in
+/// `elux.kdl` says.
+pub fn sync_observatories(config: &mut XrayConfig) {
+    let entries = entries(config);
+    let ping = strategy_selectors(&entries, "leastPing");
+    let load = strategy_selectors(&entries, "leastLoad");
+
+    let defaults = Settings::get().observatory;
+
+    if !ping.is_empty() || config.observatory.is_some() {
+        let observatory = config.observatory.get_or_insert_with(|| ObservatoryConfig {
+            probe_url: Some(defaults.url.clone()),
+            probe_interval: Some(defaults.interval.clone()),
+            enable_concurrency: Some(defaults.enable_concurrency),
+            ..Default::default()
+        });
+        observatory.subject_selector =
+            Some(merge_selectors(observatory.subject_selector.take(), &ping));
+    }
+
+    if !load.is_empty() || config.burst_observatory.is_some() {
+        let observatory = config
+            .burst_observatory
+            .get_or_insert_with(|| BurstObservatoryConfig {
+                ping_config: Some(PingConfig {
+                    destination: Some(defaults.url.clone()),
+                    interval: Some(defaults.interval.clone()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        observatory.subject_selector =
+            Some(merge_selectors(observatory.subject_selector.take(), &load));
+    }
+}
+
+fn ensure_group(conn: &mut Connection, group_id: i32) -> Result<(), BalancerError> {
+    let group = TransactionManager::execute_with_result(conn, |tx| {
+        GroupRepository::get_by_id(tx, group_id)
+    })?;
+
+    match group {
+        Some(_) => Ok(()),
+        None => Err(invalid(format!("Group with ID {} not found", group_id)).into()),
+    }
+}
+
+async fn save(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    mut config: XrayConfig,
+) -> Result<Vec<BalancerEntry>, BalancerError> {
+    sync_observatories(&mut config);
+
+    let result = entries(&config);
+
+    write_and_apply(xray_service, xray_config, config).await?;
+
+    Ok(result)
+}
+
+fn balancers_mut(config: &mut XrayConfig) -> &mut Vec<Balancer> {
+    config
+        .routing
+        .get_or_insert_with(RoutingConfig::default)
+        .balancers
+        .get_or_insert_with(Vec::new)
+}
+
+/// Binding to a group replaces the selector with the group's tag prefix.
+fn prepare(conn: &mut Connection, entry: BalancerEntry) -> Result<Balancer, BalancerError> {
+    let mut balancer = entry.balancer;
+
+    if let Some(group_id) = entry.group_id {
+        ensure_group(conn, group_id)?;
+        balancer.selector = Some(vec![group_selector(group_id)]);
+    }
+
+    Ok(balancer)
+}
+
+pub fn list_balancers() -> Result<Vec<BalancerEntry>, BalancerError> {
+    let config = XrayFileCore::new(XRAY_CONFIG_FILE).read_config()?;
+
+    Ok(entries(&config))
+}
+
+pub async fn create_balancer(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    entry: BalancerEntry,
+) -> Result<Vec<BalancerEntry>, BalancerError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let mut config = xray_config.read_config()?;
+
+    let balancer = prepare(conn, entry)?;
+    validate(&config, &balancer)?;
+
+    if entries(&config)
+        .iter()
+        .any(|e| e.balancer.tag == balancer.tag)
+    {
+        return Err(invalid(format!("Balancer '{}' already exists", balancer.tag)).into());
+    }
+
+    balancers_mut(&mut config).push(balancer);
+
+    save(xray_service, &xray_config, config).await
+}
+
+/// Replaces the balancer named `tag`. Renaming is allowed as long as the new
+/// tag is free; routing rules pointing at the old tag are updated.
+pub async fn update_balancer(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    tag: &str,
+    entry: BalancerEntry,
+) -> Result<Vec<BalancerEntry>, BalancerError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let mut config = xray_config.read_config()?;
+
+    let balancer = prepare(conn, entry)?;
+    validate(&config, &balancer)?;
+
+    if balancer.tag != tag
+        && entries(&config)
+            .iter()
+            .any(|e| e.balancer.tag == balancer.tag)
+    {
+        return Err(invalid(format!("Balancer '{}' already exists", balancer.tag)).into());
+    }
+
+    let balancers = balancers_mut(&mut config);
+    let Some(current) = balancers.iter_mut().find(|b| b.tag == tag) else {
+        return Err(BalancerError::NotFound(tag.to_string()));
+    };
+    let new_tag = balancer.tag.clone();
+    *current = balancer;
+
+    if new_tag != tag {
+        let rules = config
+            .routing
+            .iter_mut()
+            .flat_map(|routing| routing.rules.iter_mut().flatten());
+        for rule in rules {
+            if rule.balancer_tag.as_deref() == Some(tag) {
+                rule.balancer_tag = Some(new_tag.clone());
+            }
+        }
+    }
+
+    save(xray_service, &xray_config, config).await
+}
+
+/// Refuses to delete a balancer that routing rules still send traffic to.
+pub async fn delete_balancer(
+    xray_service: &XrayService,
+    tag: &str,
+) -> Result<Vec<BalancerEntry>, BalancerError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let mut config = xray_config.read_config()?;
+
+    let used_by = config
+        .routing
+        .iter()
+        .flat_map(|routing| routing.rules.iter().flatten())
+        .position(|rule| rule.balancer_tag.as_deref() == Some(tag));
+
+    if let Some(index) = used_by {
+        return Err(invalid(format!(
+            "Balancer '{}' is used by routing rule {}",
+            tag, index
+        ))
+        .into());
+    }
+
+    let balancers = balancers_mut(&mut config);
+    let before = balancers.len();
+    balancers.retain(|b| b.tag != tag);
+
+    if balancers.len() == before {
+        return Err(BalancerError::NotFound(tag.to_string()));
+    }
+
+    save(xray_service, &xray_config, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::config;
+
+    #[test]
+    fn adds_an_observatory_that_xray_reads_for_least_ping_balancers() {
+        config::tests::init();
+
+        let selector = group_selector(3);
+        let mut config: XrayConfig = serde_json::from_value(serde_json::json!({
+            "routing": {
+                "balancers": [{
+                    "tag": "fast",
+                    "selector": [selector],
+                    "strategy": { "type": "leastPing" }
+                }]
+            }
+        }))
+        .unwrap();
+
+        sync_observatories(&mut config);
+
+        let observatory = serde_json::to_value(&config).unwrap()["observatory"].clone();
+        let defaults = Settings::get().observatory;
+
+        assert_eq!(observatory["probeURL"], defaults.url.as_str());
+        assert_eq!(observatory["probeInterval"], defaults.interval.as_str());
+        assert_eq!(
+            observatory["subjectSelector"],
+            serde_json::json!([selector])
+        );
+        assert!(observatory.get("probeUrl").is_none());
+    }
+}
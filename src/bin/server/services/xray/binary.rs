@@ -0,0 +1,356 @@
+use std::{fmt, path::PathBuf, process::Command, sync::OnceLock};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::utils::settings::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct XrayVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl XrayVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        XrayVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the first line of `xray version`, e.g.
+    /// `Xray 25.3.6 (Xray, Penetrates Everything.) 2cba2c4 (go1.24.1 linux/amd64)`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let raw = output.lines().next()?.split_whitespace().nth(1)?;
+        let mut parts = raw.trim_start_matches('v').split('.');
+
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().unwrap_or("0").parse().ok()?;
+        let patch = parts
+            .next()
+            .unwrap_or("0")
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .unwrap_or("0")
+            .parse()
+            .unwrap_or(0);
+
+        Some(XrayVersion::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for XrayVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Serialize for XrayVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    VisionFlow,
+    Xhttp,
+    RealityMldsa65,
+    HysteriaOutbound,
+}
+
+/// Oldest xray-core release that understands each feature elux may generate.
+const CAPABILITIES: &[(Capability, XrayVersion)] = &[
+    (Capability::VisionFlow, XrayVersion::new(1, 8, 0)),
+    (Capability::Xhttp, XrayVersion::new(24, 11, 5)),
+    (Capability::RealityMldsa65, XrayVersion::new(25, 7, 26)),
+    (Capability::HysteriaOutbound, XrayVersion::new(25, 12, 8)),
+];
+
+impl Capability {
+    pub fn min_version(self) -> XrayVersion {
+        CAPABILITIES
+            .iter()
+            .find(|(capability, _)| *capability == self)
+            .map(|(_, version)| *version)
+            .unwrap_or(XrayVersion::new(0, 0, 0))
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::VisionFlow => "VLESS Vision flow",
+            Capability::Xhttp => "XHTTP transport",
+            Capability::RealityMldsa65 => "REALITY ML-DSA-65 verification",
+            Capability::HysteriaOutbound => "Hysteria outbound",
+        };
+        write!(f, "{} (xray >= {})", name, self.min_version())
+    }
+}
+
+/// Lists the capabilities a config relies on, judged by its outbounds.
+pub fn required_capabilities(config: &Value) -> Vec<Capability> {
+    let mut required = Vec::new();
+
+    let outbounds = config
+        .get("outbounds")
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    for outbound in outbounds {
+        let protocol = outbound.get("protocol").and_then(|v| v.as_str());
+        let stream = outbound.get("streamSettings");
+        let network = stream
+            .and_then(|s| s.get("network"))
+            .and_then(|v| v.as_str());
+
+        let uses_vision = outbound
+            .pointer("/settings/vnext")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|vnext| vnext.get("users").and_then(|v| v.as_array()))
+            .flatten()
+            .any(|user| {
+                user.get("flow")
+                    .and_then(|v| v.as_str())
+                    .is_some_and(|flow| flow.starts_with("xtls-rprx-vision"))
+            });
+
+        let checks = [
+            (uses_vision, Capability::VisionFlow),
+            (
+                matches!(network, Some("xhttp") | Some("splithttp")),
+                Capability::Xhttp,
+            ),
+            (
+                stream
+                    .and_then(|s| s.pointer("/realitySettings/mldsa65Verify"))
+                    .is_some(),
+                Capability::RealityMldsa65,
+            ),
+            (
+                matches!(protocol, Some("hysteria") | Some("hysteria2")),
+                Capability::HysteriaOutbound,
+            ),
+        ];
+
+        for (used, capability) in checks {
+            if used && !required.contains(&capability) {
+                required.push(capability);
+            }
+        }
+    }
+
+    required
+}
+
+#[derive(Debug)]
+pub struct XrayBinary {
+    pub path: PathBuf,
+    pub version: Option<XrayVersion>,
+}
+
+static INSTANCE: OnceLock<XrayBinary> = OnceLock::new();
+
+impl XrayBinary {
+    /// Resolves the xray executable (`xray.bin` in `elux.kdl`, `xray` from
+    /// `PATH` by default) and asks it for its version.
+    pub fn init() {
+        Self::init_with(PathBuf::from(Settings::get().xray.bin));
+    }
+
+    pub fn init_with(path: PathBuf) {
+        let version = match Command::new(&path).arg("version").output() {
+            Ok(output) => XrayVersion::parse(&String::from_utf8_lossy(&output.stdout)),
+            Err(err) => {
+                eprintln!("Failed to run {} version: {}", path.display(), err);
+                None
+            }
+        };
+
+        match version {
+            Some(version) => println!("using xray {} ({})", version, path.display()),
+            None => eprintln!(
+                "Could not detect xray version of {}, capability checks are disabled",
+                path.display()
+            ),
+        }
+
+        INSTANCE.set(XrayBinary { path, version }).ok();
+    }
+
+    pub fn get() -> &'static XrayBinary {
+        INSTANCE.get().expect("XrayBinary is not initialized")
+    }
+
+    /// Capabilities used by `config` that the installed core does not have.
+    /// Without a known version nothing is reported, only warned about.
+    pub fn unsupported(&self, config: &Value) -> Vec<Capability> {
+        let required = required_capabilities(config);
+
+        let Some(version) = self.version else {
+            if !required.is_empty() {
+                eprintln!(
+                    "xray version unknown, cannot verify support for: {:?}",
+                    required
+                );
+            }
+            return Vec::new();
+        };
+
+        required
+            .into_iter()
+            .filter(|capability| capability.min_version() > version)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path, sync::OnceLock};
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Points [`XrayBinary`] at a stand-in that accepts every config, once
+    /// per test run. It reports no version, so capability checks are off.
+    pub fn fake() -> &'static Path {
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+        DIR.get_or_init(|| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("xray");
+
+            fs::write(&path, "#!/bin/sh\n[ \"$2\" = -test ]\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            XrayBinary::init_with(path);
+
+            dir
+        })
+        .path()
+    }
+
+    #[test]
+    fn parses_release_versions() {
+        let output = "Xray 25.3.6 (Xray, Penetrates Everything.) 2cba2c4 (go1.24.1 linux/amd64)\n\
+                      A unified platform for anti-censorship.\n";
+        assert_eq!(XrayVersion::parse(output), Some(XrayVersion::new(25, 3, 6)));
+
+        let output = "Xray 1.8.24 (Xray, Penetrates Everything.) Custom (go1.22.5 linux/arm64)";
+        assert_eq!(XrayVersion::parse(output), Some(XrayVersion::new(1, 8, 24)));
+    }
+
+    #[test]
+    fn parses_prefixed_and_pre_release_versions() {
+        let output = "Xray v24.11.30 (Xray, Penetrates Everything.) 4d9e4b4 (go1.23.3 linux/amd64)";
+        assert_eq!(
+            XrayVersion::parse(output),
+            Some(XrayVersion::new(24, 11, 30))
+        );
+
+        let output =
+            "Xray 25.7.26-beta.1 (Xray, Penetrates Everything.) 8d2bd43 (go1.24.5 linux/amd64)";
+        assert_eq!(
+            XrayVersion::parse(output),
+            Some(XrayVersion::new(25, 7, 26))
+        );
+
+        assert_eq!(
+            XrayVersion::parse("Xray 26.1 (Xray, Penetrates Everything.)"),
+            Some(XrayVersion::new(26, 1, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_unrelated_output() {
+        assert_eq!(XrayVersion::parse(""), None);
+        assert_eq!(XrayVersion::parse("xray: command not found"), None);
+        assert_eq!(XrayVersion::parse("Xray dev-build"), None);
+    }
+
+    #[test]
+    fn orders_versions_numerically() {
+        assert!(XrayVersion::new(25, 10, 1) > XrayVersion::new(25, 9, 30));
+        assert!(XrayVersion::new(1, 8, 24) < XrayVersion::new(24, 11, 5));
+        assert_eq!(XrayVersion::new(25, 3, 6).to_string(), "25.3.6");
+    }
+
+    #[test]
+    fn detects_each_capability() {
+        let config = json!({
+            "outbounds": [
+                {
+                    "protocol": "vless",
+                    "settings": {"vnext": [{"users": [{"flow": "xtls-rprx-vision-udp443"}]}]},
+                    "streamSettings": {
+                        "network": "tcp",
+                        "realitySettings": {"mldsa65Verify": "abc"}
+                    }
+                },
+                {"protocol": "vless", "streamSettings": {"network": "splithttp"}},
+                {"protocol": "hysteria2"},
+                {"protocol": "freedom", "tag": "direct"}
+            ]
+        });
+
+        assert_eq!(
+            required_capabilities(&config),
+            vec![
+                Capability::VisionFlow,
+                Capability::RealityMldsa65,
+                Capability::Xhttp,
+                Capability::HysteriaOutbound,
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_configs_need_nothing() {
+        let config = json!({
+            "outbounds": [
+                {
+                    "protocol": "vless",
+                    "settings": {"vnext": [{"users": [{"flow": ""}]}]},
+                    "streamSettings": {"network": "ws"}
+                },
+                {"protocol": "trojan"}
+            ]
+        });
+
+        assert!(required_capabilities(&config).is_empty());
+        assert!(required_capabilities(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn reports_capabilities_newer_than_the_core() {
+        let config = json!({
+            "outbounds": [
+                {"protocol": "vless", "streamSettings": {"network": "xhttp"}},
+                {"protocol": "hysteria"}
+            ]
+        });
+
+        let binary = XrayBinary {
+            path: PathBuf::from("xray"),
+            version: Some(XrayVersion::new(25, 3, 6)),
+        };
+        assert_eq!(
+            binary.unsupported(&config),
+            vec![Capability::HysteriaOutbound]
+        );
+
+        let unknown = XrayBinary {
+            path: PathBuf::from("xray"),
+            version: None,
+        };
+        assert!(unknown.unsupported(&config).is_empty());
+    }
+}
@@ -1,12 +1,16 @@
 use anyhow::Context;
-use dirs::config_dir;
-use elux::{CONFIG_DIR, XRAY_CHECKER_CONFIG_FILE};
+use elux::XRAY_CHECKER_CONFIG_FILE;
+use serde_json::json;
 use std::sync::Mutex;
 use tokio::process::{Child, Command};
 
 use crate::{
-    http::models::xray_config::XrayOutboundClientConfig,
-    services::xray::file::{XrayFileCore, XrayInboundClientConfig, XrayInboundSettings},
+    http::models::{xray_config::XrayOutboundClientConfig, xray_file::Inbound},
+    services::xray::{
+        binary::XrayBinary,
+        file::{XrayFileCore, to_outbound},
+    },
+    utils::config,
 };
 
 static XRAY_CHILD: Mutex<Option<Child>> = Mutex::new(None);
@@ -24,35 +28,36 @@ fn craft_xray_checker_file(mut configs: Vec<XrayOutboundClientConfig>) {
         el.tag = Some(format!("test-outbound-{}", idx.to_string()));
     });
 
-    let outbounds = configs.clone();
-
-    xray_config
-        .write_xray_outbounds(outbounds)
+    let outbounds = configs
+        .iter()
+        .map(to_outbound)
+        .collect::<Result<Vec<_>, _>>()
         .expect("Failed to write Xray checker outbounds config");
 
-    let inbound = XrayInboundClientConfig {
-        tag: "inbound".to_string(),
-        listen: "127.0.0.1".to_string(),
+    let inbound = Inbound {
+        tag: Some("inbound".to_string()),
+        listen: Some("127.0.0.1".to_string()),
         protocol: "socks".to_string(),
-        port: 2050,
-        settings: XrayInboundSettings {
-            auth: "noauth".to_string(),
-            udp: true,
-        },
+        port: Some(json!(2050)),
+        settings: Some(json!({ "auth": "noauth", "udp": true })),
+        ..Default::default()
     };
 
+    let mut config = xray_config
+        .read_config()
+        .expect("Failed to read Xray checker config");
+    config.outbounds = Some(outbounds);
+    config.inbounds = Some(vec![inbound]);
+
     xray_config
-        .write_xray_inbounds(vec![inbound])
-        .expect("Failed to write Xray checker inbounds config");
+        .write_config(&config)
+        .expect("Failed to write Xray checker config");
 }
 
 fn spawn_xray() -> Result<(), anyhow::Error> {
-    let config_path = config_dir()
-        .ok_or_else(|| anyhow::anyhow!("Failed to get config directory"))?
-        .join(CONFIG_DIR)
-        .join(XRAY_CHECKER_CONFIG_FILE);
+    let config_path = config::config_dir().join(XRAY_CHECKER_CONFIG_FILE);
 
-    let child = Command::new("xray")
+    let child = Command::new(&XrayBinary::get().path)
         .args(["run", "-c", config_path.to_str().context("Invalid path")?])
         .spawn()
         .context("Failed to spawn Xray command")?;
@@ -1,27 +1,163 @@
-use elux::CONFIG_DIR;
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use serde_json::{Value, json};
+use elux::MANAGED_OUTBOUND_PREFIX;
+use serde::Deserialize;
 use std::{
+    collections::HashMap,
     fs::{self, File},
-    io::Write,
-    path::PathBuf,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use crate::http::models::xray_config::XrayOutboundClientConfig;
+use crate::{
+    http::models::{
+        xray_config::XrayOutboundClientConfig,
+        xray_file::{Outbound, XrayConfig},
+    },
+    utils::{atomic, config},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrayFileError {
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Malformed {path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Malformed outbound {tag}: {source}")]
+    Outbound {
+        tag: String,
+        source: serde_json::Error,
+    },
+}
+
+/// Tag prefix shared by all managed outbounds of a group. xray selectors
+/// match by prefix, so this picks out exactly that group's outbounds.
+pub fn group_selector(group_id: i32) -> String {
+    format!("{}{}-", MANAGED_OUTBOUND_PREFIX, group_id)
+}
+
+/// Group behind a selector built by [`group_selector`].
+pub fn selector_group(selector: &str) -> Option<i32> {
+    selector
+        .strip_prefix(MANAGED_OUTBOUND_PREFIX)?
+        .strip_suffix('-')?
+        .parse()
+        .ok()
+}
+
+pub fn managed_tag(group_id: i32, id: i32) -> String {
+    format!("{}{}", group_selector(group_id), id)
+}
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct XrayInboundSettings {
-    pub auth: String,
-    pub udp: bool,
+/// Config ID behind a managed outbound tag, `elux-<group>-<id>` or the
+/// `elux-<id>` of earlier versions. Other tags belong to the user.
+pub fn managed_id(tag: &str) -> Option<i32> {
+    let rest = tag.strip_prefix(MANAGED_OUTBOUND_PREFIX)?;
+
+    let id = match rest.split_once('-') {
+        Some((group, id)) if group.parse::<i32>().is_ok() => id,
+        Some(_) => return None,
+        None => rest,
+    };
+
+    id.parse().ok()
+}
+
+/// Group behind a managed outbound tag; `None` for `elux-<id>` tags.
+pub fn managed_group(tag: &str) -> Option<i32> {
+    let (prefix, _) = tag.rsplit_once('-')?;
+
+    selector_group(&tag[..prefix.len() + 1])
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct XrayInboundClientConfig {
-    pub tag: String,
-    pub port: u16,
-    pub listen: String,
-    pub protocol: String,
-    pub settings: XrayInboundSettings,
+/// Points routing rules and balancer fallbacks at renamed outbounds.
+fn rename_references(config: &mut XrayConfig, renames: &HashMap<String, String>) {
+    let Some(routing) = config.routing.as_mut() else {
+        return;
+    };
+
+    let rename = |tag: &mut Option<String>| {
+        if let Some(new) = tag.as_ref().and_then(|t| renames.get(t)) {
+            *tag = Some(new.clone());
+        }
+    };
+
+    for rule in routing.rules.iter_mut().flatten() {
+        rename(&mut rule.outbound_tag);
+    }
+
+    for balancer in routing.balancers.iter_mut().flatten() {
+        rename(&mut balancer.fallback_tag);
+    }
+}
+
+fn outbound_managed_id(outbound: &Outbound) -> Option<i32> {
+    outbound.tag.as_deref().and_then(managed_id)
+}
+
+/// Config IDs of the managed outbounds in `config`, in file order.
+pub fn managed_outbound_ids(config: &XrayConfig) -> Vec<i32> {
+    config
+        .outbounds()
+        .iter()
+        .filter_map(outbound_managed_id)
+        .collect()
+}
+
+pub fn to_outbound(config: &XrayOutboundClientConfig) -> Result<Outbound, XrayFileError> {
+    let source = |source| XrayFileError::Outbound {
+        tag: config.tag.clone().unwrap_or_default(),
+        source,
+    };
+
+    serde_json::to_value(config)
+        .and_then(serde_json::from_value)
+        .map_err(source)
+}
+
+fn from_outbound(outbound: &Outbound) -> Result<XrayOutboundClientConfig, XrayFileError> {
+    let source = |source| XrayFileError::Outbound {
+        tag: outbound.tag.clone().unwrap_or_default(),
+        source,
+    };
+
+    serde_json::to_value(outbound)
+        .and_then(serde_json::from_value)
+        .map_err(source)
+}
+
+/// Fingerprint of the last content elux wrote to each file, so the config
+/// watcher can tell its own writes from outside edits.
+static WRITTEN: Mutex<Vec<(PathBuf, u64)>> = Mutex::new(Vec::new());
+
+fn fingerprint(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn remember_written(path: &Path, content: &str) {
+    let mut written = WRITTEN.lock().unwrap();
+    written.retain(|(known, _)| known != path);
+    written.push((path.to_path_buf(), fingerprint(content)));
+}
+
+/// Whether `content` is what elux last wrote to `path`. A match is consumed,
+/// so the same content coming back later counts as an outside edit.
+pub fn take_written(path: &Path, content: &str) -> bool {
+    let mut written = WRITTEN.lock().unwrap();
+    let before = written.len();
+    written.retain(|(known, hash)| known != path || *hash != fingerprint(content));
+
+    written.len() != before
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,7 +167,7 @@ pub struct XrayFileCore {
 
 impl XrayFileCore {
     pub fn new(xray_config_file: &str) -> Self {
-        let config_dir = dirs::config_dir().unwrap().join(CONFIG_DIR);
+        let config_dir = config::config_dir();
         if !config_dir.exists() {
             let _ = fs::create_dir_all(&config_dir);
         }
@@ -40,99 +176,281 @@ impl XrayFileCore {
         }
     }
 
-    pub fn get_path_string(&self) -> String {
-        self.xray_config_path.to_string_lossy().to_string()
+    fn io_error(&self, source: std::io::Error) -> XrayFileError {
+        XrayFileError::Io {
+            path: self.xray_config_path.clone(),
+            source,
+        }
     }
 
-    fn load_json(&self) -> Value {
-        File::open(&self.xray_config_path)
-            .ok()
-            .and_then(|f| serde_json::from_reader(f).ok())
-            .unwrap_or_else(|| json!({}))
-    }
+    /// Reads the config file. A missing file is an empty config; anything
+    /// unreadable or malformed is an error.
+    pub fn read_config(&self) -> Result<XrayConfig, XrayFileError> {
+        let file = match File::open(&self.xray_config_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(XrayConfig::default()),
+            Err(err) => return Err(self.io_error(err)),
+        };
 
-    fn save_json(&self, data: &Value) -> Result<(), Box<dyn std::error::Error>> {
-        let content = serde_json::to_string_pretty(data)?;
+        serde_json::from_reader(file).map_err(|source| XrayFileError::Json {
+            path: self.xray_config_path.clone(),
+            source,
+        })
+    }
 
-        let mut file = File::create(&self.xray_config_path)?;
-        file.write_all(content.as_bytes())?;
+    /// Replaces the config file atomically, see `atomic::write`.
+    pub fn write_config(&self, config: &XrayConfig) -> Result<(), XrayFileError> {
+        let content =
+            serde_json::to_string_pretty(config).map_err(|source| XrayFileError::Json {
+                path: self.xray_config_path.clone(),
+                source,
+            })?;
 
-        file.sync_all()?;
+        atomic::write(&self.xray_config_path, content.as_bytes()).map_err(|e| self.io_error(e))?;
+        remember_written(&self.xray_config_path, &content);
 
         Ok(())
     }
 
-    pub fn write_full_config(&self, config: &Value) -> Result<(), Box<dyn std::error::Error>> {
-        self.save_json(config)
+    pub fn without_xray_outbounds(&self, ids: &[i32]) -> Result<XrayConfig, XrayFileError> {
+        let mut config = self.read_config()?;
+        if let Some(outbounds) = config.outbounds.as_mut() {
+            outbounds.retain(|item| outbound_managed_id(item).is_none_or(|id| !ids.contains(&id)));
+        }
+        Ok(config)
     }
 
-    pub fn read_xray_file(&self) -> Result<Value, Box<dyn std::error::Error>> {
-        Ok(self.load_json())
+    /// Outbounds owned by elux, in file order.
+    pub fn read_managed_outbounds(&self) -> Result<Vec<XrayOutboundClientConfig>, XrayFileError> {
+        self.read_config()?
+            .outbounds()
+            .iter()
+            .filter(|item| outbound_managed_id(item).is_some())
+            .map(from_outbound)
+            .collect()
     }
 
-    pub fn get_section<T: DeserializeOwned>(
-        &self,
-        key: &str,
-    ) -> Result<Vec<T>, Box<dyn std::error::Error>> {
-        let root = self.load_json();
-        let section = root.get(key).cloned().unwrap_or(json!([]));
-        Ok(serde_json::from_value(section).unwrap_or_default())
+    /// Config IDs of the managed outbounds, in file order.
+    pub fn managed_outbound_ids(&self) -> Result<Vec<i32>, XrayFileError> {
+        Ok(managed_outbound_ids(&self.read_config()?))
     }
 
-    pub fn set_section<T: Serialize>(
+    /// Gives the outbounds in `renames` (old tag to new) their new tags and
+    /// points references at them. Writes the file only if anything changed.
+    pub fn retag_outbounds(
         &self,
-        key: &str,
-        data: &Vec<T>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut root = self.load_json();
-        root[key] = serde_json::to_value(data)?;
-        self.save_json(&root)
-    }
-
-    pub fn delete_section(&self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut root = self.load_json();
-        if let Some(obj) = root.as_object_mut() {
-            obj.remove(key);
+        renames: &HashMap<String, String>,
+    ) -> Result<bool, XrayFileError> {
+        let mut config = self.read_config()?;
+
+        let mut changed = false;
+        for outbound in config.outbounds.iter_mut().flatten() {
+            if let Some(new) = outbound.tag.as_ref().and_then(|tag| renames.get(tag)) {
+                outbound.tag = Some(new.clone());
+                changed = true;
+            }
         }
-        self.save_json(&root)
-    }
-
-    pub fn delete_xray_outbound_by_id(&self, id: &i32) -> Result<(), Box<dyn std::error::Error>> {
-        let mut root = self.load_json();
-        if let Some(outbounds) = root.get_mut("outbounds").and_then(|v| v.as_array_mut()) {
-            outbounds.retain(|item| {
-                item.get("tag")
-                    .and_then(|t| t.as_str())
-                    .and_then(|s| s.parse::<i32>().ok())
-                    .map_or(true, |tag_id| tag_id != *id)
-            });
+
+        if changed {
+            rename_references(&mut config, renames);
+            self.write_config(&config)?;
         }
-        self.save_json(&root)
+
+        Ok(changed)
     }
 
-    pub fn read_xray_inbounds(
+    /// Replaces the managed outbounds with `data` and keeps user-defined ones
+    /// where they are. The new managed block goes where the first managed
+    /// outbound used to be, or first if there was none, since the first
+    /// outbound is where xray sends unmatched traffic.
+    pub fn with_managed_outbounds(
         &self,
-    ) -> Result<Vec<XrayInboundClientConfig>, Box<dyn std::error::Error>> {
-        self.get_section("inbounds")
+        data: &[XrayOutboundClientConfig],
+    ) -> Result<XrayConfig, XrayFileError> {
+        let mut config = self.read_config()?;
+
+        let current = config.outbounds.take().unwrap_or_default();
+
+        let insert_at = current
+            .iter()
+            .position(|item| outbound_managed_id(item).is_some());
+
+        let mut outbounds: Vec<Outbound> = current
+            .iter()
+            .filter(|item| outbound_managed_id(item).is_none())
+            .cloned()
+            .collect();
+
+        let managed = data
+            .iter()
+            .map(to_outbound)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Outbounds tagged `elux-<id>` keep their references.
+        let renames: HashMap<String, String> = current
+            .iter()
+            .filter_map(|old| {
+                let id = outbound_managed_id(old)?;
+                let new = managed
+                    .iter()
+                    .find(|m| outbound_managed_id(m) == Some(id))?;
+                Some((old.tag.clone()?, new.tag.clone()?))
+            })
+            .filter(|(old, new)| old != new)
+            .collect();
+        rename_references(&mut config, &renames);
+
+        let insert_at = insert_at.unwrap_or(0).min(outbounds.len());
+        outbounds.splice(insert_at..insert_at, managed);
+
+        config.outbounds = Some(outbounds);
+        Ok(config)
     }
+}
 
-    pub fn write_xray_inbounds(
-        &self,
-        data: Vec<XrayInboundClientConfig>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.set_section("inbounds", &data)
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn file(config: serde_json::Value) -> (tempfile::TempDir, XrayFileCore) {
+        let dir = tempfile::tempdir().unwrap();
+        let core = XrayFileCore {
+            xray_config_path: dir.path().join("xray.json"),
+        };
+        fs::write(&core.xray_config_path, config.to_string()).unwrap();
+
+        (dir, core)
     }
 
-    pub fn read_xray_outbounds(
-        &self,
-    ) -> Result<Vec<XrayOutboundClientConfig>, Box<dyn std::error::Error>> {
-        self.get_section("outbounds")
+    fn managed(group_id: i32, id: i32) -> XrayOutboundClientConfig {
+        serde_json::from_value(json!({
+            "tag": managed_tag(group_id, id),
+            "protocol": "vless",
+            "settings": {},
+            "streamSettings": {}
+        }))
+        .unwrap()
     }
 
-    pub fn write_xray_outbounds(
-        &self,
-        data: Vec<XrayOutboundClientConfig>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.set_section("outbounds", &data)
+    fn tags(config: &XrayConfig) -> Vec<&str> {
+        config
+            .outbounds()
+            .iter()
+            .filter_map(|outbound| outbound.tag.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn recognises_only_elux_tags() {
+        assert_eq!(managed_id("elux-3-12"), Some(12));
+        assert_eq!(managed_id("elux-12"), Some(12));
+        assert_eq!(managed_group("elux-3-12"), Some(3));
+        assert_eq!(managed_group("elux-12"), None);
+
+        for tag in ["443", "1-2", "direct", "elux-direct", "elux-a-1", "elux-"] {
+            assert_eq!(managed_id(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn puts_the_first_managed_block_in_front() {
+        let (_dir, core) = file(json!({
+            "outbounds": [
+                {"tag": "direct-outbound", "protocol": "freedom"},
+                {"tag": "443", "protocol": "freedom"}
+            ]
+        }));
+
+        let config = core
+            .with_managed_outbounds(&[managed(1, 5), managed(1, 6)])
+            .unwrap();
+
+        assert_eq!(
+            tags(&config),
+            ["elux-1-5", "elux-1-6", "direct-outbound", "443"]
+        );
+    }
+
+    #[test]
+    fn replaces_the_managed_block_in_place() {
+        let (_dir, core) = file(json!({
+            "outbounds": [
+                {"tag": "direct-outbound", "protocol": "freedom"},
+                {"tag": "elux-1-5", "protocol": "vless"},
+                {"tag": "1-2", "protocol": "freedom"},
+                {"tag": "elux-1-6", "protocol": "vless"}
+            ]
+        }));
+
+        let config = core.with_managed_outbounds(&[managed(2, 7)]).unwrap();
+        assert_eq!(tags(&config), ["direct-outbound", "elux-2-7", "1-2"]);
+
+        let config = core.without_xray_outbounds(&[5, 6]).unwrap();
+        assert_eq!(tags(&config), ["direct-outbound", "1-2"]);
+    }
+
+    #[test]
+    fn renames_references_of_old_style_tags() {
+        let (_dir, core) = file(json!({
+            "outbounds": [{"tag": "elux-5", "protocol": "vless"}],
+            "routing": {
+                "rules": [{"outboundTag": "elux-5", "domain": ["example.com"]}],
+                "balancers": [{"tag": "b", "selector": ["elux-"], "fallbackTag": "elux-5"}]
+            }
+        }));
+
+        let config = core.with_managed_outbounds(&[managed(1, 5)]).unwrap();
+        let routing = config.routing.unwrap();
+
+        assert_eq!(
+            routing.rules.unwrap()[0].outbound_tag.as_deref(),
+            Some("elux-1-5")
+        );
+        assert_eq!(
+            routing.balancers.unwrap()[0].fallback_tag.as_deref(),
+            Some("elux-1-5")
+        );
+    }
+
+    #[test]
+    fn retags_outbounds_and_their_references() {
+        let (_dir, core) = file(json!({
+            "outbounds": [
+                {"tag": "5", "protocol": "vless"},
+                {"tag": "443", "protocol": "freedom"}
+            ],
+            "routing": {"rules": [{"outboundTag": "5", "port": 443}]}
+        }));
+
+        let renames = HashMap::from([("5".to_string(), managed_tag(1, 5))]);
+        assert!(core.retag_outbounds(&renames).unwrap());
+        assert!(!core.retag_outbounds(&renames).unwrap());
+
+        let config = core.read_config().unwrap();
+        assert_eq!(tags(&config), ["elux-1-5", "443"]);
+        assert_eq!(
+            config.routing.unwrap().rules.unwrap()[0]
+                .outbound_tag
+                .as_deref(),
+            Some("elux-1-5")
+        );
+    }
+
+    #[test]
+    fn tells_own_writes_from_outside_edits() {
+        let (_dir, core) = file(json!({"outbounds": []}));
+        let path = &core.xray_config_path;
+
+        let mut config = core.read_config().unwrap();
+        config.outbounds = Some(vec![to_outbound(&managed(1, 2)).unwrap()]);
+        core.write_config(&config).unwrap();
+        let written = fs::read_to_string(path).unwrap();
+
+        assert!(!take_written(path, "{\"outbounds\": []}"));
+        assert!(take_written(path, &written));
+        // The same content again is someone putting it back.
+        assert!(!take_written(path, &written));
     }
 }
@@ -0,0 +1,400 @@
+use std::{
+    collections::HashSet,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+};
+
+use crate::services::xray::{
+    binary::XrayBinary,
+    proto::{ProtoReader, ProtoValue},
+};
+
+/// Same variable xray itself reads to locate `geosite.dat`/`geoip.dat`.
+pub const XRAY_ASSET_ENV: &str = "XRAY_LOCATION_ASSET";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoKind {
+    Site,
+    Ip,
+}
+
+impl GeoKind {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            GeoKind::Site => "geosite.dat",
+            GeoKind::Ip => "geoip.dat",
+        }
+    }
+}
+
+/// Directories searched for data files, in the order xray uses.
+fn asset_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(dir) = std::env::var_os(XRAY_ASSET_ENV) {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    let binary = &XrayBinary::get().path;
+    let binary = if binary.components().count() > 1 {
+        Some(binary.clone())
+    } else {
+        std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(binary))
+                .find(|candidate| candidate.is_file())
+        })
+    };
+    if let Some(dir) = binary
+        .and_then(|path| fs::canonicalize(path).ok())
+        .and_then(|path| path.parent().map(PathBuf::from))
+    {
+        dirs.push(dir);
+    }
+
+    dirs.push(PathBuf::from("/usr/local/share/xray"));
+    dirs.push(PathBuf::from("/usr/share/xray"));
+
+    dirs
+}
+
+pub fn find(kind: GeoKind) -> Option<PathBuf> {
+    find_file(kind.file_name())
+}
+
+/// Looks up a data file by name, as used by `ext:<file>:<category>` matchers.
+pub fn find_file(file_name: &str) -> Option<PathBuf> {
+    asset_dirs()
+        .into_iter()
+        .map(|dir| dir.join(file_name))
+        .find(|path| path.is_file())
+}
+
+/// Raw `GeoSite`/`GeoIP` entries of a list file.
+fn entries(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut reader = ProtoReader::new(data);
+    std::iter::from_fn(move || {
+        loop {
+            match reader.field()? {
+                (1, ProtoValue::Bytes(entry)) => return Some(entry),
+                _ => continue,
+            }
+        }
+    })
+}
+
+/// `country_code` of an entry; field 1 in both `GeoSite` and `GeoIP`.
+fn entry_code(entry: &[u8]) -> Option<String> {
+    let mut reader = ProtoReader::new(entry);
+    while let Some(field) = reader.field() {
+        if let (1, ProtoValue::Bytes(code)) = field {
+            return Some(String::from_utf8_lossy(code).to_lowercase());
+        }
+    }
+    None
+}
+
+/// Category names in a data file, lowercased. `None` if the file is missing.
+pub fn categories(kind: GeoKind) -> Option<HashSet<String>> {
+    let data = fs::read(find(kind)?).ok()?;
+
+    Some(entries(&data).filter_map(entry_code).collect())
+}
+
+/// Category referenced by a `geosite:`/`geoip:` matcher, without attribute
+/// filters (`@cn`) or negation (`!`).
+pub fn category(kind: GeoKind, matcher: &str) -> Option<String> {
+    let prefix = match kind {
+        GeoKind::Site => "geosite:",
+        GeoKind::Ip => "geoip:",
+    };
+
+    let name = matcher.strip_prefix(prefix)?.trim_start_matches('!');
+    let name = name.split('@').next().unwrap_or(name);
+
+    Some(name.to_lowercase())
+}
+
+/// How a `geosite` domain entry matches, as in xray's `Domain.Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainKind {
+    /// Substring of the domain.
+    Keyword,
+    Regex,
+    /// The domain itself or any of its subdomains.
+    Domain,
+    Full,
+}
+
+#[derive(Debug, Clone)]
+pub struct GeoDomain {
+    pub kind: DomainKind,
+    pub value: String,
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GeoCidr {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GeoIp {
+    pub cidrs: Vec<GeoCidr>,
+    /// Matches every address outside `cidrs`.
+    pub reverse: bool,
+}
+
+fn decode_domain(data: &[u8]) -> GeoDomain {
+    let mut domain = GeoDomain {
+        kind: DomainKind::Keyword,
+        value: String::new(),
+        attributes: Vec::new(),
+    };
+
+    let mut reader = ProtoReader::new(data);
+    while let Some(field) = reader.field() {
+        match field {
+            (1, ProtoValue::Varint(kind)) => {
+                domain.kind = match kind {
+                    1 => DomainKind::Regex,
+                    2 => DomainKind::Domain,
+                    3 => DomainKind::Full,
+                    _ => DomainKind::Keyword,
+                }
+            }
+            (2, ProtoValue::Bytes(value)) => {
+                domain.value = String::from_utf8_lossy(value).to_lowercase()
+            }
+            (3, ProtoValue::Bytes(attribute)) => {
+                if let Some(key) = entry_code(attribute) {
+                    domain.attributes.push(key);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    domain
+}
+
+fn decode_cidr(data: &[u8]) -> Option<GeoCidr> {
+    let (mut ip, mut prefix) = (None, 0);
+
+    let mut reader = ProtoReader::new(data);
+    while let Some(field) = reader.field() {
+        match field {
+            (1, ProtoValue::Bytes(bytes)) => {
+                ip = match bytes.len() {
+                    4 => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?))),
+                    16 => Some(IpAddr::V6(Ipv6Addr::from(
+                        <[u8; 16]>::try_from(bytes).ok()?,
+                    ))),
+                    _ => None,
+                }
+            }
+            (2, ProtoValue::Varint(value)) => prefix = value as u8,
+            _ => {}
+        }
+    }
+
+    Some(GeoCidr { ip: ip?, prefix })
+}
+
+/// A loaded `geosite`/`geoip` list file.
+pub struct GeoData {
+    data: Vec<u8>,
+}
+
+impl GeoData {
+    pub fn load(file_name: &str) -> Option<Self> {
+        let data = fs::read(find_file(file_name)?).ok()?;
+        Some(GeoData { data })
+    }
+
+    fn entry(&self, code: &str) -> Option<&[u8]> {
+        entries(&self.data).find(|entry| entry_code(entry).is_some_and(|c| c == code))
+    }
+
+    /// Domains of a `geosite` category; `code` must be lowercase.
+    pub fn site(&self, code: &str) -> Option<Vec<GeoDomain>> {
+        let mut domains = Vec::new();
+
+        let mut reader = ProtoReader::new(self.entry(code)?);
+        while let Some(field) = reader.field() {
+            if let (2, ProtoValue::Bytes(domain)) = field {
+                domains.push(decode_domain(domain));
+            }
+        }
+
+        Some(domains)
+    }
+
+    /// Networks of a `geoip` category; `code` must be lowercase.
+    pub fn ip(&self, code: &str) -> Option<GeoIp> {
+        let mut geoip = GeoIp::default();
+
+        let mut reader = ProtoReader::new(self.entry(code)?);
+        while let Some(field) = reader.field() {
+            match field {
+                (2, ProtoValue::Bytes(cidr)) => geoip.cidrs.extend(decode_cidr(cidr)),
+                (3, ProtoValue::Varint(reverse)) => geoip.reverse = reverse != 0,
+                _ => {}
+            }
+        }
+
+        Some(geoip)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{path::Path, sync::OnceLock};
+
+    use super::*;
+    use crate::services::xray::{
+        binary,
+        proto::tests::{bytes_field, varint_field},
+    };
+
+    fn domain(kind: u64, value: &str, attributes: &[&str]) -> Vec<u8> {
+        let mut data = varint_field(1, kind);
+        data.extend(bytes_field(2, value.as_bytes()));
+        for attribute in attributes {
+            data.extend(bytes_field(3, &bytes_field(1, attribute.as_bytes())));
+        }
+        bytes_field(2, &data)
+    }
+
+    fn cidr(ip: &[u8], prefix: u64) -> Vec<u8> {
+        let mut data = bytes_field(1, ip);
+        data.extend(varint_field(2, prefix));
+        bytes_field(2, &data)
+    }
+
+    fn entry(code: &str, items: &[Vec<u8>], reverse: bool) -> Vec<u8> {
+        let mut data = bytes_field(1, code.as_bytes());
+        data.extend(items.concat());
+        if reverse {
+            data.extend(varint_field(3, 1));
+        }
+        bytes_field(1, &data)
+    }
+
+    /// Writes `geosite.dat` and `geoip.dat` with a few categories into a
+    /// directory xray's asset lookup finds, once per test run.
+    pub fn assets() -> &'static Path {
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+        DIR.get_or_init(|| {
+            let dir = tempfile::tempdir().unwrap();
+
+            let geosite = [
+                entry(
+                    "EXAMPLE",
+                    &[
+                        domain(2, "example.com", &[]),
+                        domain(3, "full.example.org", &["ads"]),
+                        domain(0, "tracker", &["ads"]),
+                        domain(1, r"^cdn\d+\.example\.net$", &[]),
+                        domain(1, "(unclosed", &[]),
+                    ],
+                    false,
+                ),
+                entry("OTHER", &[domain(2, "other.test", &[])], false),
+            ]
+            .concat();
+
+            let geoip = [
+                entry(
+                    "PRIVATE",
+                    &[
+                        cidr(&[10, 0, 0, 0], 8),
+                        cidr(&[192, 168, 0, 0], 16),
+                        cidr(&Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0).octets(), 7),
+                    ],
+                    false,
+                ),
+                entry("NOTLAN", &[cidr(&[10, 0, 0, 0], 8)], true),
+            ]
+            .concat();
+
+            fs::write(dir.path().join("geosite.dat"), geosite).unwrap();
+            fs::write(dir.path().join("geoip.dat"), geoip).unwrap();
+
+            // SAFETY: set once, before any test reads it.
+            unsafe { std::env::set_var(XRAY_ASSET_ENV, dir.path()) };
+            binary::tests::fake();
+
+            dir
+        })
+        .path()
+    }
+
+    #[test]
+    fn finds_files_in_the_asset_directory() {
+        let dir = assets();
+
+        assert_eq!(find(GeoKind::Site), Some(dir.join("geosite.dat")));
+        assert_eq!(find_file("missing.dat"), None);
+        assert_eq!(
+            categories(GeoKind::Site),
+            Some(HashSet::from(["example".to_string(), "other".to_string()]))
+        );
+    }
+
+    #[test]
+    fn decodes_site_categories() {
+        assets();
+        let data = GeoData::load("geosite.dat").unwrap();
+
+        let domains = data.site("example").unwrap();
+        let kinds = domains
+            .iter()
+            .map(|d| (d.kind, d.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (DomainKind::Domain, "example.com"),
+                (DomainKind::Full, "full.example.org"),
+                (DomainKind::Keyword, "tracker"),
+                (DomainKind::Regex, r"^cdn\d+\.example\.net$"),
+                (DomainKind::Regex, "(unclosed"),
+            ]
+        );
+        assert_eq!(domains[1].attributes, ["ads"]);
+        assert!(data.site("missing").is_none());
+    }
+
+    #[test]
+    fn decodes_ip_categories() {
+        assets();
+        let data = GeoData::load("geoip.dat").unwrap();
+
+        let private = data.ip("private").unwrap();
+        assert!(!private.reverse);
+        assert_eq!(
+            private
+                .cidrs
+                .iter()
+                .map(|c| format!("{}/{}", c.ip, c.prefix))
+                .collect::<Vec<_>>(),
+            ["10.0.0.0/8", "192.168.0.0/16", "fc00::/7"]
+        );
+
+        assert!(data.ip("notlan").unwrap().reverse);
+    }
+
+    #[test]
+    fn extracts_categories_from_matchers() {
+        assert_eq!(
+            category(GeoKind::Site, "geosite:Google@cn"),
+            Some("google".to_string())
+        );
+        assert_eq!(category(GeoKind::Ip, "geoip:!CN"), Some("cn".to_string()));
+        assert_eq!(category(GeoKind::Ip, "geosite:cn"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::Connection;
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::{
+    http::models::xray_file::XrayConfig,
+    services::xray::{
+        apply::{ApplyOutcome, write_restored},
+        file::{XrayFileCore, XrayFileError, managed_outbound_ids},
+        service::{XrayService, XrayServiceError},
+        state::remember_outbounds,
+    },
+    utils::config::AppPaths,
+};
+
+/// How many previous versions of `xray.json` are kept.
+pub const MAX_BACKUPS: usize = 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrayHistoryError {
+    #[error("Revision {0} not found")]
+    NotFound(u64),
+
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error(transparent)]
+    Service(#[from] XrayServiceError),
+}
+
+/// A backed up `xray.json`, as it was before being overwritten.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XrayRevision {
+    /// Milliseconds since the epoch at which the file was replaced.
+    pub rev: u64,
+    /// Unified diff from this revision to the version that replaced it.
+    pub diff: String,
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> XrayFileError {
+    XrayFileError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn backup_path(rev: u64) -> PathBuf {
+    AppPaths::get()
+        .xray_backups
+        .join(format!("xray.{}.json", rev))
+}
+
+fn parse_rev(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("xray.")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+/// Revisions on disk, oldest first.
+fn revisions() -> Result<Vec<u64>, XrayFileError> {
+    let dir = &AppPaths::get().xray_backups;
+
+    let mut revs: Vec<u64> = fs::read_dir(dir)
+        .map_err(|e| io_error(dir, e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| parse_rev(&entry.path()))
+        .collect();
+
+    revs.sort_unstable();
+    Ok(revs)
+}
+
+/// Copies the current file into the backups directory and drops the oldest
+/// backups beyond [`MAX_BACKUPS`].
+pub fn backup(xray_config: &XrayFileCore) -> Result<(), XrayFileError> {
+    let source = &xray_config.xray_config_path;
+    if !source.exists() {
+        return Ok(());
+    }
+
+    let revs = revisions()?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let rev = revs.last().map_or(now, |last| now.max(last + 1));
+
+    let target = backup_path(rev);
+    fs::copy(source, &target).map_err(|e| io_error(&target, e))?;
+
+    let excess = (revs.len() + 1).saturating_sub(MAX_BACKUPS);
+    for old in revs.into_iter().take(excess) {
+        let path = backup_path(old);
+        fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Backed up revisions, newest first, each with the change that followed it.
+pub fn list(xray_config: &XrayFileCore) -> Result<Vec<XrayRevision>, XrayFileError> {
+    let current = &xray_config.xray_config_path;
+    let read = |path: &Path| match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(io_error(path, err)),
+    };
+
+    let mut newer = read(current)?;
+    let mut history = Vec::new();
+
+    for rev in revisions()?.into_iter().rev() {
+        let content = read(&backup_path(rev))?;
+
+        let diff = TextDiff::from_lines(&content, &newer)
+            .unified_diff()
+            .header(&format!("xray.{}.json", rev), "next")
+            .to_string();
+
+        history.push(XrayRevision { rev, diff });
+        newer = content;
+    }
+
+    Ok(history)
+}
+
+/// Puts revision `rev` back in place (backing up the current file first),
+/// records its managed outbounds as the applied set and restarts xray if it
+/// is running. Lint errors the revision already had don't block it.
+pub async fn rollback(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    conn: &mut Connection,
+    rev: u64,
+) -> Result<ApplyOutcome, XrayHistoryError> {
+    let path = backup_path(rev);
+    if !path.exists() {
+        return Err(XrayHistoryError::NotFound(rev));
+    }
+
+    let content = fs::read(&path).map_err(|e| io_error(&path, e))?;
+    let candidate: XrayConfig =
+        serde_json::from_slice(&content).map_err(|source| XrayFileError::Json {
+            path: path.clone(),
+            source,
+        })?;
+
+    let (_, written) = write_restored(xray_service, xray_config, candidate).await?;
+    remember_outbounds(conn, managed_outbound_ids(&written));
+
+    if !xray_service.status().await.running {
+        return Ok(ApplyOutcome::NotRunning);
+    }
+
+    xray_service.restart().await?;
+
+    Ok(ApplyOutcome::Restarted)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        services::{
+            db::{TransactionManager, connection::tests::memory},
+            repository::xray_state::XrayStateRepository,
+            xray::{binary, service::SupervisorConfig},
+        },
+        utils::{config, settings::Settings},
+    };
+
+    #[tokio::test]
+    async fn rollback_records_the_outbounds_it_puts_back() {
+        config::tests::init();
+        binary::tests::fake();
+
+        let dir = tempfile::tempdir().unwrap();
+        let xray_config = XrayFileCore {
+            xray_config_path: dir.path().join("xray.json"),
+        };
+        let current = json!({"outbounds": [{"tag": "elux-1-2", "protocol": "freedom"}]});
+        fs::write(&xray_config.xray_config_path, current.to_string()).unwrap();
+
+        let rev = 1;
+        let restored = json!({
+            "outbounds": [
+                {"tag": "elux-1-5", "protocol": "freedom"},
+                {"tag": "direct", "protocol": "freedom"},
+                {"tag": "elux-2-3", "protocol": "freedom"}
+            ]
+        });
+        fs::write(backup_path(rev), restored.to_string()).unwrap();
+
+        let paths = AppPaths::get();
+        let xray_service = XrayService::new(
+            paths.xray_config.clone(),
+            paths.xray_log.clone(),
+            SupervisorConfig::from(&Settings::get().supervisor),
+        );
+        let mut conn = memory();
+
+        let outcome = rollback(&xray_service, &xray_config, &mut conn, rev)
+            .await
+            .unwrap();
+
+        let state =
+            TransactionManager::execute_with_result(&mut conn, XrayStateRepository::get).unwrap();
+        assert_eq!(outcome, ApplyOutcome::NotRunning);
+        assert_eq!(state.outbound_ids, [5, 3]);
+        assert_eq!(xray_config.managed_outbound_ids().unwrap(), [5, 3]);
+    }
+}
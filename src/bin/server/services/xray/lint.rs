@@ -0,0 +1,526 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    http::models::xray_file::{RoutingRule, XrayConfig},
+    services::xray::geodata::{self, GeoKind},
+    utils::config::AppPaths,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Breaks routing or keeps xray from starting.
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LintIssue {
+    pub severity: Severity,
+    pub code: &'static str,
+    /// Location in `xray.json`, e.g. `routing.rules[4].outboundTag`.
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Lints(Vec<LintIssue>);
+
+impl Lints {
+    fn error(&mut self, code: &'static str, path: String, message: String) {
+        self.0.push(LintIssue {
+            severity: Severity::Error,
+            code,
+            path,
+            message,
+        });
+    }
+
+    fn warning(&mut self, code: &'static str, path: String, message: String) {
+        self.0.push(LintIssue {
+            severity: Severity::Warning,
+            code,
+            path,
+            message,
+        });
+    }
+}
+
+fn duplicates<'a>(
+    lints: &mut Lints,
+    section: &str,
+    tags: impl Iterator<Item = (usize, Option<&'a str>)>,
+) {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+
+    for (index, tag) in tags {
+        let Some(tag) = tag else { continue };
+
+        if let Some(first) = seen.get(tag) {
+            lints.error(
+                "duplicate-tag",
+                format!("{}[{}].tag", section, index),
+                format!(
+                    "Tag '{}' is already used by {}[{}]; give each entry a unique tag",
+                    tag, section, first
+                ),
+            );
+        } else {
+            seen.insert(tag, index);
+        }
+    }
+}
+
+fn check_tags(config: &XrayConfig, lints: &mut Lints) {
+    let outbounds: Vec<&str> = config
+        .outbounds()
+        .iter()
+        .filter_map(|outbound| outbound.tag.as_deref())
+        .collect();
+
+    let mut inbounds: Vec<&str> = config
+        .inbounds
+        .iter()
+        .flatten()
+        .filter_map(|inbound| inbound.tag.as_deref())
+        .collect();
+    inbounds.extend(config.api.as_ref().and_then(|api| api.tag.as_deref()));
+
+    let routing = config.routing.as_ref();
+    let balancers = routing
+        .and_then(|routing| routing.balancers.as_deref())
+        .unwrap_or_default();
+    let rules = routing
+        .and_then(|routing| routing.rules.as_deref())
+        .unwrap_or_default();
+
+    duplicates(
+        lints,
+        "inbounds",
+        config
+            .inbounds
+            .iter()
+            .flatten()
+            .map(|inbound| inbound.tag.as_deref())
+            .enumerate(),
+    );
+    duplicates(
+        lints,
+        "outbounds",
+        config
+            .outbounds()
+            .iter()
+            .map(|outbound| outbound.tag.as_deref())
+            .enumerate(),
+    );
+    duplicates(
+        lints,
+        "routing.balancers",
+        balancers.iter().map(|b| Some(b.tag.as_str())).enumerate(),
+    );
+
+    for (index, rule) in rules.iter().enumerate() {
+        let path = format!("routing.rules[{}]", index);
+
+        if let Some(tag) = &rule.outbound_tag
+            && !outbounds.contains(&tag.as_str())
+        {
+            lints.error(
+                "dangling-outbound",
+                format!("{}.outboundTag", path),
+                format!(
+                    "Outbound '{}' does not exist; traffic matching this rule is dropped",
+                    tag
+                ),
+            );
+        }
+
+        if let Some(tag) = &rule.balancer_tag
+            && !balancers.iter().any(|b| b.tag == *tag)
+        {
+            lints.error(
+                "dangling-balancer",
+                format!("{}.balancerTag", path),
+                format!("Balancer '{}' does not exist", tag),
+            );
+        }
+
+        for tag in rule.inbound_tag.iter().flatten() {
+            if !inbounds.contains(&tag.as_str()) {
+                lints.warning(
+                    "dangling-inbound",
+                    format!("{}.inboundTag", path),
+                    format!(
+                        "Inbound '{}' does not exist; the rule never matches it",
+                        tag
+                    ),
+                );
+            }
+        }
+    }
+
+    for (index, balancer) in balancers.iter().enumerate() {
+        let path = format!("routing.balancers[{}]", index);
+
+        if let Some(tag) = &balancer.fallback_tag
+            && !outbounds.contains(&tag.as_str())
+        {
+            lints.error(
+                "dangling-outbound",
+                format!("{}.fallbackTag", path),
+                format!("Fallback outbound '{}' does not exist", tag),
+            );
+        }
+
+        for selector in balancer.selector.iter().flatten() {
+            if !outbounds
+                .iter()
+                .any(|tag| tag.starts_with(selector.as_str()))
+            {
+                lints.warning(
+                    "empty-selector",
+                    format!("{}.selector", path),
+                    format!(
+                        "No outbound tag starts with '{}'; apply the group or fix the selector",
+                        selector
+                    ),
+                );
+            }
+        }
+    }
+
+    for (index, outbound) in config.outbounds().iter().enumerate() {
+        let proxies = [
+            (
+                "proxySettings.tag",
+                outbound.proxy_settings.as_ref().and_then(|p| p.get("tag")),
+            ),
+            (
+                "streamSettings.sockopt.dialerProxy",
+                outbound
+                    .stream_settings
+                    .as_ref()
+                    .and_then(|s| s.pointer("/sockopt/dialerProxy")),
+            ),
+        ];
+
+        for (field, tag) in proxies {
+            if let Some(tag) = tag.and_then(Value::as_str)
+                && !outbounds.contains(&tag)
+            {
+                lints.error(
+                    "dangling-outbound",
+                    format!("outbounds[{}].{}", index, field),
+                    format!("Outbound '{}' does not exist", tag),
+                );
+            }
+        }
+    }
+}
+
+/// Port ranges of an inbound `port` or rule `port` value; `None` for values
+/// resolved by xray at start (`env:`) or malformed ones.
+fn port_ranges(port: &Value) -> Option<Vec<(u16, u16)>> {
+    match port {
+        Value::Number(number) => {
+            let port = u16::try_from(number.as_u64()?).ok()?;
+            Some(vec![(port, port)])
+        }
+        Value::String(ports) => ports
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (from, to) = part.split_once('-').unwrap_or((part, part));
+                Some((from.trim().parse().ok()?, to.trim().parse().ok()?))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Index, listen address and port ranges of an inbound.
+type ListeningInbound<'a> = (usize, Option<&'a str>, Vec<(u16, u16)>);
+
+fn check_ports(config: &XrayConfig, lints: &mut Lints) {
+    let wildcard = |listen: Option<&str>| matches!(listen, None | Some("" | "0.0.0.0" | "::"));
+
+    let inbounds: Vec<ListeningInbound> = config
+        .inbounds
+        .iter()
+        .flatten()
+        .enumerate()
+        .filter(|(_, inbound)| {
+            // Unix sockets have no port.
+            !inbound
+                .listen
+                .as_deref()
+                .is_some_and(|listen| listen.starts_with('/') || listen.starts_with('@'))
+        })
+        .filter_map(|(index, inbound)| {
+            Some((
+                index,
+                inbound.listen.as_deref(),
+                port_ranges(inbound.port.as_ref()?)?,
+            ))
+        })
+        .collect();
+
+    for (i, (index, listen, ranges)) in inbounds.iter().enumerate() {
+        let clash = inbounds[..i]
+            .iter()
+            .find(|(_, other_listen, other_ranges)| {
+                (wildcard(*listen) || wildcard(*other_listen) || listen == other_listen)
+                    && ranges.iter().any(|(from, to)| {
+                        other_ranges
+                            .iter()
+                            .any(|(other_from, other_to)| from <= other_to && other_from <= to)
+                    })
+            });
+
+        if let Some((other, _, _)) = clash {
+            lints.error(
+                "duplicate-port",
+                format!("inbounds[{}].port", index),
+                format!(
+                    "Port overlaps with inbounds[{}]; xray cannot bind both",
+                    other
+                ),
+            );
+        }
+    }
+}
+
+fn covers_all_ports(port: &Value) -> bool {
+    let Some(mut ranges) = port_ranges(port) else {
+        return false;
+    };
+    ranges.sort_unstable();
+
+    let mut next = 1u32;
+    for (from, to) in ranges {
+        if u32::from(from) > next {
+            return false;
+        }
+        next = next.max(u32::from(to) + 1);
+    }
+
+    next > 65535
+}
+
+/// A rule that matches every connection: nothing but a full `network` or
+/// `port` condition.
+fn is_catch_all(rule: &RoutingRule) -> bool {
+    let narrowed = [
+        &rule.domain,
+        &rule.ip,
+        &rule.source,
+        &rule.user,
+        &rule.inbound_tag,
+        &rule.protocol,
+    ]
+    .iter()
+    .any(|list| list.as_ref().is_some_and(|list| !list.is_empty()))
+        || rule.source_port.is_some()
+        || rule.attrs.is_some();
+
+    let network = rule.network.as_deref().is_none_or(|network| {
+        let networks: Vec<&str> = network.split(',').map(str::trim).collect();
+        networks.contains(&"tcp") && networks.contains(&"udp")
+    });
+    let port = rule.port.as_ref().is_none_or(covers_all_ports);
+
+    !narrowed && network && port && (rule.network.is_some() || rule.port.is_some())
+}
+
+fn check_rule_order(config: &XrayConfig, lints: &mut Lints) {
+    let rules = config
+        .routing
+        .as_ref()
+        .and_then(|routing| routing.rules.as_deref())
+        .unwrap_or_default();
+
+    let Some(catch_all) = rules.iter().position(is_catch_all) else {
+        return;
+    };
+
+    for index in catch_all + 1..rules.len() {
+        lints.warning(
+            "unreachable-rule",
+            format!("routing.rules[{}]", index),
+            format!(
+                "Never reached: routing.rules[{}] already matches all traffic; move it to the end",
+                catch_all
+            ),
+        );
+    }
+}
+
+fn check_log_paths(config: &XrayConfig, lints: &mut Lints) {
+    let Some(log) = &config.log else {
+        return;
+    };
+
+    let config_dir = &AppPaths::get().config_dir;
+
+    for (field, path) in [("access", &log.access), ("error", &log.error)] {
+        let Some(path) = path.as_deref().filter(|p| !p.is_empty() && *p != "none") else {
+            continue;
+        };
+
+        let message = if Path::new(path).is_relative() {
+            format!(
+                "'{}' is relative to xray's working directory; use a path inside {}",
+                path,
+                config_dir.display()
+            )
+        } else if !Path::new(path).starts_with(config_dir) {
+            format!(
+                "'{}' is outside {}; elux cannot show or rotate this log",
+                path,
+                config_dir.display()
+            )
+        } else {
+            continue;
+        };
+
+        lints.warning("log-path", format!("log.{}", field), message);
+    }
+}
+
+fn check_marks(config: &XrayConfig, lints: &mut Lints) {
+    let tproxy = config.inbounds.iter().flatten().any(|inbound| {
+        inbound
+            .stream_settings
+            .as_ref()
+            .and_then(|s| s.pointer("/sockopt/tproxy"))
+            .and_then(Value::as_str)
+            .is_some_and(|mode| mode != "off")
+    });
+
+    if !tproxy {
+        return;
+    }
+
+    for (index, outbound) in config.outbounds().iter().enumerate() {
+        if matches!(outbound.protocol.as_str(), "blackhole" | "loopback") {
+            continue;
+        }
+
+        let sockopt = outbound
+            .stream_settings
+            .as_ref()
+            .and_then(|s| s.get("sockopt"));
+
+        // Chained outbounds leave through the outbound they dial through.
+        let chained = outbound.proxy_settings.is_some()
+            || sockopt.is_some_and(|s| s.get("dialerProxy").is_some());
+        let marked = sockopt
+            .and_then(|s| s.get("mark"))
+            .and_then(Value::as_u64)
+            .is_some_and(|mark| mark != 0);
+
+        if !chained && !marked {
+            lints.warning(
+                "missing-mark",
+                format!("outbounds[{}].streamSettings.sockopt.mark", index),
+                format!(
+                    "Outbound '{}' has no sockopt mark while a tproxy inbound is used; its traffic loops back into xray",
+                    outbound.tag.as_deref().unwrap_or_default()
+                ),
+            );
+        }
+    }
+}
+
+fn check_geo(config: &XrayConfig, lints: &mut Lints) {
+    let rules = config
+        .routing
+        .as_ref()
+        .and_then(|routing| routing.rules.as_deref())
+        .unwrap_or_default();
+
+    for kind in [GeoKind::Site, GeoKind::Ip] {
+        let used: Vec<(usize, String)> = rules
+            .iter()
+            .enumerate()
+            .flat_map(|(index, rule)| {
+                let matchers: Vec<&String> = match kind {
+                    GeoKind::Site => rule.domain.iter().flatten().collect(),
+                    GeoKind::Ip => rule
+                        .ip
+                        .iter()
+                        .flatten()
+                        .chain(rule.source.iter().flatten())
+                        .collect(),
+                };
+                matchers
+                    .into_iter()
+                    .filter_map(move |matcher| Some((index, geodata::category(kind, matcher)?)))
+            })
+            .collect();
+
+        if used.is_empty() {
+            continue;
+        }
+
+        let Some(known) = geodata::categories(kind) else {
+            lints.warning(
+                "geodata-missing",
+                "routing.rules".to_string(),
+                format!(
+                    "{} not found, categories are not checked; set {}",
+                    kind.file_name(),
+                    geodata::XRAY_ASSET_ENV
+                ),
+            );
+            continue;
+        };
+
+        for (index, category) in used {
+            if !known.contains(&category) {
+                lints.error(
+                    "unknown-category",
+                    format!("routing.rules[{}]", index),
+                    format!(
+                        "Category '{}' is not in {}; xray refuses to start",
+                        category,
+                        kind.file_name()
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Looks for mistakes xray itself accepts or only reports at run time.
+pub fn lint(config: &XrayConfig) -> Vec<LintIssue> {
+    let mut lints = Lints::default();
+
+    check_tags(config, &mut lints);
+    check_ports(config, &mut lints);
+    check_rule_order(config, &mut lints);
+    check_log_paths(config, &mut lints);
+    check_marks(config, &mut lints);
+    check_geo(config, &mut lints);
+
+    lints.0
+}
+
+/// Issues of `candidate` that `previous` does not have. Paths shift when
+/// entries move, so issues are compared by code and message.
+pub fn introduced(previous: &XrayConfig, candidate: &XrayConfig) -> Vec<LintIssue> {
+    let existing = lint(previous);
+
+    lint(candidate)
+        .into_iter()
+        .filter(|issue| {
+            !existing
+                .iter()
+                .any(|old| old.code == issue.code && old.message == issue.message)
+        })
+        .collect()
+}
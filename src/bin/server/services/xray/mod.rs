@@ -1,5 +1,20 @@
+pub mod api;
+pub mod apply;
+pub mod balancers;
+pub mod binary;
 pub mod checker;
 pub mod fetcher;
 pub mod file;
+pub mod geodata;
+pub mod history;
+pub mod lint;
+pub mod observatory;
 pub mod outbounds;
+pub mod proto;
+pub mod routing;
 pub mod service;
+pub mod simulator;
+pub mod sockopt;
+pub mod state;
+pub mod validator;
+pub mod watcher;
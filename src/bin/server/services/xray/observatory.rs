@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use elux::XRAY_CONFIG_FILE;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    http::models::xray_file::{BurstObservatoryConfig, ObservatoryConfig, XrayConfig},
+    services::xray::{
+        api::{OutboundStatus, XrayApiError, api_listen},
+        apply::write_and_apply,
+        balancers::sync_observatories,
+        file::{XrayFileCore, XrayFileError, managed_group, managed_id},
+        service::{XrayService, XrayServiceError},
+        validator::XrayConfigError,
+    },
+    utils::duration::is_duration,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ObservatoryError {
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error(transparent)]
+    Service(#[from] XrayServiceError),
+
+    #[error(transparent)]
+    Api(#[from] XrayApiError),
+}
+
+impl From<XrayConfigError> for ObservatoryError {
+    fn from(err: XrayConfigError) -> Self {
+        ObservatoryError::Service(err.into())
+    }
+}
+
+/// The two probing sections of `xray.json`. `leastPing` balancers read
+/// `observatory`, `leastLoad` ones read `burstObservatory`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObservatorySettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observatory: Option<ObservatoryConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst_observatory: Option<BurstObservatoryConfig>,
+}
+
+impl From<&XrayConfig> for ObservatorySettings {
+    fn from(config: &XrayConfig) -> Self {
+        ObservatorySettings {
+            observatory: config.observatory.clone(),
+            burst_observatory: config.burst_observatory.clone(),
+        }
+    }
+}
+
+/// Probe result together with the config behind a managed outbound.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObservedOutbound {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_id: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<i32>,
+
+    #[serde(flatten)]
+    pub status: OutboundStatus,
+}
+
+fn invalid(message: String) -> XrayConfigError {
+    XrayConfigError::Invalid(message)
+}
+
+/// Durations are Go `time.Duration` strings such as `30s` or `1m30s`.
+fn check_duration(field: &str, value: Option<&String>) -> Result<(), XrayConfigError> {
+    let Some(value) = value else {
+        return Ok(());
+    };
+
+    if is_duration(value) {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "Invalid duration in {}: '{}'",
+            field, value
+        )))
+    }
+}
+
+fn check_url(field: &str, value: Option<&String>) -> Result<(), XrayConfigError> {
+    let Some(value) = value.filter(|value| !value.is_empty()) else {
+        return Ok(());
+    };
+
+    match Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(invalid(format!("Invalid URL in {}: '{}'", field, value))),
+    }
+}
+
+fn validate(settings: &ObservatorySettings) -> Result<(), XrayConfigError> {
+    if let Some(observatory) = &settings.observatory {
+        check_url("observatory.probeURL", observatory.probe_url.as_ref())?;
+        check_duration(
+            "observatory.probeInterval",
+            observatory.probe_interval.as_ref(),
+        )?;
+    }
+
+    let ping = settings
+        .burst_observatory
+        .as_ref()
+        .and_then(|burst| burst.ping_config.as_ref());
+
+    if let Some(ping) = ping {
+        check_url(
+            "burstObservatory.pingConfig.destination",
+            ping.destination.as_ref(),
+        )?;
+        check_url(
+            "burstObservatory.pingConfig.connectivity",
+            ping.connectivity.as_ref(),
+        )?;
+        check_duration(
+            "burstObservatory.pingConfig.interval",
+            ping.interval.as_ref(),
+        )?;
+        check_duration("burstObservatory.pingConfig.timeout", ping.timeout.as_ref())?;
+
+        if ping.sampling == Some(0) {
+            return Err(invalid(
+                "burstObservatory.pingConfig.sampling must be at least 1".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn get_settings() -> Result<ObservatorySettings, ObservatoryError> {
+    let config = XrayFileCore::new(XRAY_CONFIG_FILE).read_config()?;
+
+    Ok(ObservatorySettings::from(&config))
+}
+
+/// Replaces both sections. Subject selectors of balancers bound to groups
+/// are added back, so a section a balancer depends on cannot be dropped.
+pub async fn update_settings(
+    xray_service: &XrayService,
+    settings: ObservatorySettings,
+) -> Result<ObservatorySettings, ObservatoryError> {
+    validate(&settings)?;
+
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let mut config = xray_config.read_config()?;
+
+    config.observatory = settings.observatory;
+    config.burst_observatory = settings.burst_observatory;
+    sync_observatories(&mut config);
+
+    let result = ObservatorySettings::from(&config);
+
+    write_and_apply(xray_service, &xray_config, config).await?;
+
+    Ok(result)
+}
+
+/// Current probe results. Empty when xray is stopped or nothing is probed.
+pub async fn status(xray_service: &XrayService) -> Result<Vec<ObservedOutbound>, ObservatoryError> {
+    let config = XrayFileCore::new(XRAY_CONFIG_FILE).read_config()?;
+
+    if config.observatory.is_none() && config.burst_observatory.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let Some(server) = api_listen(&config) else {
+        return Ok(Vec::new());
+    };
+
+    if !xray_service.status().await.running {
+        return Ok(Vec::new());
+    }
+
+    let statuses = xray_service.observatory_status(server).await?;
+
+    Ok(statuses
+        .into_iter()
+        .map(|status| ObservedOutbound {
+            config_id: managed_id(&status.tag),
+            group_id: managed_group(&status.tag),
+            status,
+        })
+        .collect())
+}
+
+/// Probe results of managed outbounds keyed by config ID. Errors are logged
+/// and yield an empty map, so listings still work without the observatory.
+pub async fn status_by_config(xray_service: &XrayService) -> HashMap<i32, OutboundStatus> {
+    match status(xray_service).await {
+        Ok(statuses) => statuses
+            .into_iter()
+            .filter_map(|observed| Some((observed.config_id?, observed.status)))
+            .collect(),
+        Err(err) => {
+            eprintln!("Failed to read observatory status: {}", err);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_a_probe_url_that_is_not_http() {
+        let settings: ObservatorySettings =
+            serde_json::from_str(r#"{"observatory":{"probeURL":"ftp://x"}}"#).unwrap();
+
+        let err = validate(&settings).unwrap_err();
+
+        assert!(matches!(
+            err,
+            XrayConfigError::Invalid(message) if message == "Invalid URL in observatory.probeURL: 'ftp://x'"
+        ));
+    }
+
+    #[test]
+    fn accepts_an_http_probe_url() {
+        let settings: ObservatorySettings = serde_json::from_str(
+            r#"{"observatory":{"probeURL":"https://www.google.com/generate_204","probeInterval":"1m"}}"#,
+        )
+        .unwrap();
+
+        assert!(validate(&settings).is_ok());
+    }
+}
@@ -1,68 +1,192 @@
+use rusqlite::Connection;
+
 use crate::{
-    http::models::xray_config::XrayOutboundClientConfig,
+    http::models::{xray_config::XrayOutboundClientConfig, xray_file::XrayConfig},
     services::{
-        common::convertors::config_models_to_xray_outbounds, repository::config::ConfigModel,
-        xray::file::XrayFileCore,
+        common::convertors::config_models_to_xray_outbounds,
+        repository::config::ConfigModel,
+        xray::{
+            apply::write_and_apply,
+            file::{XrayFileCore, XrayFileError, managed_id, managed_outbound_ids, managed_tag},
+            service::{XrayService, XrayServiceError},
+            state::remember_outbounds,
+            validator::XrayConfigError,
+        },
     },
 };
 use elux::XRAY_CONFIG_FILE;
-use std::io::{Error, ErrorKind};
 
-pub fn get_outbounds() -> Result<Vec<XrayOutboundClientConfig>, Box<dyn std::error::Error>> {
-    Ok(XrayFileCore::new(XRAY_CONFIG_FILE).read_xray_outbounds()?)
+pub fn get_outbounds() -> Result<Vec<XrayOutboundClientConfig>, XrayFileError> {
+    XrayFileCore::new(XRAY_CONFIG_FILE).read_managed_outbounds()
 }
 
-pub fn update_outbounds(
-    configs_models: &[ConfigModel],
-) -> Result<Vec<XrayOutboundClientConfig>, Error> {
+/// Config IDs of the managed outbounds currently written to `xray.json`.
+pub fn applied_outbound_ids() -> Result<Vec<i32>, XrayFileError> {
+    XrayFileCore::new(XRAY_CONFIG_FILE).managed_outbound_ids()
+}
+
+/// Gives outbounds still carrying the bare numeric tags of older elux
+/// versions their `elux-` tags. Only the applied `configs_models` are
+/// retagged, and only in a file without `elux-` tags yet, so numeric tags
+/// of the user's own outbounds are never touched.
+pub fn migrate_legacy_tags(configs_models: &[ConfigModel]) -> Result<bool, XrayFileError> {
     let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
 
-    let configs = config_models_to_xray_outbounds(configs_models.to_vec())
-        .unwrap()
+    if !xray_config.managed_outbound_ids()?.is_empty() {
+        return Ok(false);
+    }
+
+    let renames = configs_models
         .iter()
-        .map(|xray_config_model| {
-            let mut config = xray_config_model.clone().config;
+        .map(|config| {
+            (
+                config.id.to_string(),
+                managed_tag(config.group_id, config.id),
+            )
+        })
+        .collect();
+
+    xray_config.retag_outbounds(&renames)
+}
+
+fn to_managed_outbounds(
+    configs_models: &[ConfigModel],
+) -> Result<Vec<XrayOutboundClientConfig>, XrayConfigError> {
+    let configs = config_models_to_xray_outbounds(configs_models.to_vec())?
+        .into_iter()
+        .zip(configs_models)
+        .map(|(xray_config_model, config_model)| {
+            let mut config = xray_config_model.config;
 
-            config.tag = Some(xray_config_model.id.to_string());
+            config.tag = Some(managed_tag(config_model.group_id, xray_config_model.id));
 
             config
         })
-        .collect::<Vec<_>>();
-
-    match xray_config.write_xray_outbounds(configs) {
-        Ok(_) => match xray_config.read_xray_outbounds() {
-            Ok(outbound) => Ok(outbound),
-            Err(e) => Err(Error::new(
-                ErrorKind::Other,
-                format!("Failed to read Xray outbounds: {}", e),
-            )),
-        },
-        Err(e) => Err(Error::new(
-            ErrorKind::Other,
-            format!("Failed to write Xray outbounds: {}", e),
-        )),
-    }
+        .collect();
+
+    Ok(configs)
+}
+
+/// Writes `candidate`, records its managed outbounds as the applied set and
+/// returns them as written.
+async fn write_outbounds(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    conn: &mut Connection,
+    candidate: XrayConfig,
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
+    let outbound_ids = managed_outbound_ids(&candidate);
+
+    write_and_apply(xray_service, xray_config, candidate).await?;
+    remember_outbounds(conn, outbound_ids);
+
+    Ok(xray_config
+        .read_managed_outbounds()
+        .map_err(XrayConfigError::from)?)
+}
+
+async fn replace_managed_outbounds(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    conn: &mut Connection,
+    configs: &[XrayOutboundClientConfig],
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
+    let candidate = xray_config
+        .with_managed_outbounds(configs)
+        .map_err(XrayConfigError::from)?;
+
+    write_outbounds(xray_service, xray_config, conn, candidate).await
 }
-pub fn delete_outbounds(config_ids: &Vec<i32>) -> Result<Vec<XrayOutboundClientConfig>, Error> {
+
+/// Replaces the applied set with `configs_models`, keeping their order.
+pub async fn update_outbounds(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    configs_models: &[ConfigModel],
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
     let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
 
-    for id in config_ids {
-        match xray_config.delete_xray_outbound_by_id(&id) {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Failed to delete Xray outbound with ID {}: {}", id, e),
-                ));
-            }
+    let configs = to_managed_outbounds(configs_models)?;
+
+    replace_managed_outbounds(xray_service, &xray_config, conn, &configs).await
+}
+
+/// Adds one config to the end of the applied set, or refreshes it in place
+/// if it is already applied.
+pub async fn append_outbound(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    config_model: &ConfigModel,
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    let mut configs = xray_config
+        .read_managed_outbounds()
+        .map_err(XrayConfigError::from)?;
+
+    for config in to_managed_outbounds(std::slice::from_ref(config_model))? {
+        let id = config.tag.as_deref().and_then(managed_id);
+
+        match configs
+            .iter_mut()
+            .find(|current| current.tag.as_deref().and_then(managed_id) == id)
+        {
+            Some(current) => *current = config,
+            None => configs.push(config),
         }
     }
 
-    match xray_config.read_xray_outbounds() {
-        Ok(outbound) => Ok(outbound),
-        Err(e) => Err(Error::new(
-            ErrorKind::Other,
-            format!("Failed to read Xray outbounds after deletion: {}", e),
-        )),
+    replace_managed_outbounds(xray_service, &xray_config, conn, &configs).await
+}
+
+/// Reorders the applied set. `config_ids` must list every applied config
+/// exactly once; the first one becomes the first managed outbound.
+pub async fn reorder_outbounds(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    config_ids: &[i32],
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    let mut configs = xray_config
+        .read_managed_outbounds()
+        .map_err(XrayConfigError::from)?;
+
+    let mut applied = xray_config
+        .managed_outbound_ids()
+        .map_err(XrayConfigError::from)?;
+    let mut requested = config_ids.to_vec();
+    applied.sort_unstable();
+    requested.sort_unstable();
+
+    if applied != requested {
+        return Err(XrayConfigError::Invalid(
+            "Order must list every applied outbound exactly once".to_string(),
+        )
+        .into());
     }
+
+    configs.sort_by_key(|config| {
+        config
+            .tag
+            .as_deref()
+            .and_then(managed_id)
+            .and_then(|id| config_ids.iter().position(|requested| *requested == id))
+    });
+
+    replace_managed_outbounds(xray_service, &xray_config, conn, &configs).await
+}
+
+pub async fn delete_outbounds(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    config_ids: &[i32],
+) -> Result<Vec<XrayOutboundClientConfig>, XrayServiceError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    let candidate = xray_config
+        .without_xray_outbounds(config_ids)
+        .map_err(XrayConfigError::from)?;
+
+    write_outbounds(xray_service, &xray_config, conn, candidate).await
 }
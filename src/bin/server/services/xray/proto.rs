@@ -0,0 +1,160 @@
+/// Minimal protobuf reader, enough for the geo data files and the few API
+/// responses elux decodes.
+pub struct ProtoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+pub enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> ProtoReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ProtoReader { data, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Next `(field number, value)`, or `None` at the end or on bad input.
+    pub fn field(&mut self) -> Option<(u64, ProtoValue<'a>)> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => {
+                self.pos += 8;
+                ProtoValue::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+                self.pos += len;
+                ProtoValue::Bytes(bytes)
+            }
+            5 => {
+                self.pos += 4;
+                ProtoValue::Fixed
+            }
+            _ => return None,
+        };
+
+        Some((key >> 3, value))
+    }
+}
+
+/// Encoders for building messages in tests.
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub fn varint(mut value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return out;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    pub fn varint_field(number: u64, value: u64) -> Vec<u8> {
+        let mut out = varint(number << 3);
+        out.extend(varint(value));
+        out
+    }
+
+    pub fn bytes_field(number: u64, bytes: &[u8]) -> Vec<u8> {
+        let mut out = varint((number << 3) | 2);
+        out.extend(varint(bytes.len() as u64));
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    fn fields(data: &[u8]) -> Vec<(u64, String)> {
+        let mut reader = ProtoReader::new(data);
+        let mut fields = Vec::new();
+        while let Some((number, value)) = reader.field() {
+            let value = match value {
+                ProtoValue::Varint(v) => format!("varint {}", v),
+                ProtoValue::Bytes(b) => format!("bytes {}", String::from_utf8_lossy(b)),
+                ProtoValue::Fixed => "fixed".to_string(),
+            };
+            fields.push((number, value));
+        }
+        fields
+    }
+
+    #[test]
+    fn reads_varints_and_bytes() {
+        let mut data = varint_field(1, 1);
+        data.extend(varint_field(2, 300));
+        data.extend(varint_field(15, u64::MAX));
+        data.extend(bytes_field(4, b"proxy"));
+        data.extend(bytes_field(20, b""));
+
+        assert_eq!(
+            fields(&data),
+            vec![
+                (1, "varint 1".to_string()),
+                (2, "varint 300".to_string()),
+                (15, format!("varint {}", u64::MAX)),
+                (4, "bytes proxy".to_string()),
+                (20, "bytes ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_fixed_width_fields() {
+        let mut data = varint((3 << 3) | 1);
+        data.extend([0; 8]);
+        data.extend(varint((4 << 3) | 5));
+        data.extend([0; 4]);
+        data.extend(varint_field(5, 7));
+
+        assert_eq!(
+            fields(&data),
+            vec![
+                (3, "fixed".to_string()),
+                (4, "fixed".to_string()),
+                (5, "varint 7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_on_bad_input() {
+        // Length runs past the end of the data.
+        let mut data = varint_field(1, 5);
+        data.extend(varint((2 << 3) | 2));
+        data.extend(varint(10));
+        data.extend(b"abc");
+        assert_eq!(fields(&data), vec![(1, "varint 5".to_string())]);
+
+        // Varint without its last byte.
+        assert!(fields(&[0x08, 0x80]).is_empty());
+
+        // Group wire types are not supported.
+        assert!(fields(&varint((1 << 3) | 3)).is_empty());
+    }
+}
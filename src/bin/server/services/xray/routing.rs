@@ -0,0 +1,383 @@
+use elux::XRAY_CONFIG_FILE;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::{
+    http::models::xray_file::{RoutingRule, XrayConfig},
+    services::{
+        db::TransactionManager,
+        repository::routing_rule::{RoutingRuleModel, RoutingRuleRepository},
+        xray::{
+            apply::write_and_apply,
+            file::{XrayFileCore, XrayFileError},
+            geodata::{self, GeoKind},
+            service::{XrayService, XrayServiceError},
+            validator::XrayConfigError,
+        },
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoutingRuleError {
+    #[error("Routing rule {0} not found")]
+    NotFound(i32),
+
+    #[error("Failed to access routing rules: {0}")]
+    Db(#[from] rusqlite::Error),
+
+    #[error("Malformed stored routing rule: {0}")]
+    Stored(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error(transparent)]
+    Service(#[from] XrayServiceError),
+}
+
+impl From<XrayConfigError> for RoutingRuleError {
+    fn from(err: XrayConfigError) -> Self {
+        RoutingRuleError::Service(err.into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRuleEntry {
+    pub id: i32,
+    pub enabled: bool,
+
+    #[serde(flatten)]
+    pub rule: RoutingRule,
+}
+
+fn load(conn: &mut Connection) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    TransactionManager::execute_with_result(conn, RoutingRuleRepository::get_all)?
+        .into_iter()
+        .map(|model| {
+            Ok(RoutingRuleEntry {
+                id: model.id,
+                enabled: model.enabled,
+                rule: serde_json::from_str(&model.data)?,
+            })
+        })
+        .collect()
+}
+
+fn store(
+    conn: &mut Connection,
+    entries: &[RoutingRuleEntry],
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let models = entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            Ok(RoutingRuleModel {
+                id: entry.id,
+                position: position as i32,
+                enabled: entry.enabled,
+                data: serde_json::to_string(&entry.rule)?,
+            })
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+    TransactionManager::execute_with_result(conn, |tx| {
+        RoutingRuleRepository::replace_all(tx, &models)
+    })?;
+
+    load(conn)
+}
+
+/// Lines the stored rules up with `routing.rules` of `xray.json`, which wins
+/// if it was edited directly. Stored rules keep their IDs where they still
+/// match, and disabled rules stay at their old positions. Returns `None`
+/// when nothing has to change.
+fn synced(
+    stored: &[RoutingRuleEntry],
+    file_rules: &[RoutingRule],
+) -> Option<Vec<RoutingRuleEntry>> {
+    let mut unused: Vec<&RoutingRuleEntry> = stored.iter().filter(|e| e.enabled).collect();
+
+    if unused.len() == file_rules.len()
+        && unused
+            .iter()
+            .zip(file_rules)
+            .all(|(e, rule)| e.rule == *rule)
+    {
+        return None;
+    }
+
+    let mut entries: Vec<RoutingRuleEntry> = file_rules
+        .iter()
+        .map(|rule| {
+            let id = unused
+                .iter()
+                .position(|e| e.rule == *rule)
+                .map(|i| unused.remove(i).id)
+                .unwrap_or(0);
+
+            RoutingRuleEntry {
+                id,
+                enabled: true,
+                rule: rule.clone(),
+            }
+        })
+        .collect();
+
+    for (position, entry) in stored.iter().enumerate().filter(|(_, e)| !e.enabled) {
+        entries.insert(position.min(entries.len()), entry.clone());
+    }
+
+    Some(entries)
+}
+
+fn current(
+    xray_config: &XrayFileCore,
+    conn: &mut Connection,
+) -> Result<(XrayConfig, Vec<RoutingRuleEntry>), RoutingRuleError> {
+    let config = xray_config.read_config()?;
+
+    let file_rules = config
+        .routing
+        .as_ref()
+        .and_then(|routing| routing.rules.as_deref())
+        .unwrap_or_default();
+
+    let stored = load(conn)?;
+    let entries = match synced(&stored, file_rules) {
+        Some(entries) => store(conn, &entries)?,
+        None => stored,
+    };
+
+    Ok((config, entries))
+}
+
+/// Writes the enabled rules into `xray.json`, applies it and only then
+/// records the new rule set.
+async fn save(
+    xray_service: &XrayService,
+    xray_config: &XrayFileCore,
+    conn: &mut Connection,
+    mut config: XrayConfig,
+    entries: Vec<RoutingRuleEntry>,
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let rules = entries
+        .iter()
+        .filter(|entry| entry.enabled)
+        .map(|entry| entry.rule.clone())
+        .collect();
+
+    config.routing.get_or_insert_with(Default::default).rules = Some(rules);
+
+    write_and_apply(xray_service, xray_config, config).await?;
+
+    store(conn, &entries)
+}
+
+fn check_geo<'a>(
+    kind: GeoKind,
+    matchers: impl Iterator<Item = &'a String>,
+) -> Result<(), XrayConfigError> {
+    let wanted: Vec<String> = matchers
+        .filter_map(|matcher| geodata::category(kind, matcher))
+        .collect();
+
+    if wanted.is_empty() {
+        return Ok(());
+    }
+
+    let Some(known) = geodata::categories(kind) else {
+        eprintln!(
+            "{} not found, cannot check categories: {}",
+            kind.file_name(),
+            wanted.join(", ")
+        );
+        return Ok(());
+    };
+
+    let missing: Vec<String> = wanted
+        .into_iter()
+        .filter(|category| !known.contains(category))
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(XrayConfigError::Invalid(format!(
+            "Unknown {} categories: {}",
+            kind.file_name(),
+            missing.join(", ")
+        )))
+    }
+}
+
+/// Checks that a rule matches on something, points at an outbound or
+/// balancer that exists in `config` and only uses known geo categories.
+pub fn validate_rule(config: &XrayConfig, rule: &RoutingRule) -> Result<(), XrayConfigError> {
+    match (&rule.outbound_tag, &rule.balancer_tag) {
+        (Some(tag), None) => {
+            if !config
+                .outbounds()
+                .iter()
+                .any(|outbound| outbound.tag.as_ref() == Some(tag))
+            {
+                return Err(XrayConfigError::Invalid(format!(
+                    "Unknown outbound '{}'",
+                    tag
+                )));
+            }
+        }
+        (None, Some(tag)) => {
+            if !config
+                .routing
+                .as_ref()
+                .and_then(|routing| routing.balancers.as_ref())
+                .is_some_and(|balancers| balancers.iter().any(|b| b.tag == *tag))
+            {
+                return Err(XrayConfigError::Invalid(format!(
+                    "Unknown balancer '{}'",
+                    tag
+                )));
+            }
+        }
+        _ => {
+            return Err(XrayConfigError::Invalid(
+                "A rule needs exactly one of outboundTag or balancerTag".to_string(),
+            ));
+        }
+    }
+
+    let has_condition = rule.domain.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.ip.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.source.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.user.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.inbound_tag.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.protocol.as_ref().is_some_and(|v| !v.is_empty())
+        || rule.port.is_some()
+        || rule.source_port.is_some()
+        || rule.network.is_some()
+        || rule.attrs.is_some();
+
+    if !has_condition {
+        return Err(XrayConfigError::Invalid(
+            "A rule needs at least one condition".to_string(),
+        ));
+    }
+
+    check_geo(GeoKind::Site, rule.domain.iter().flatten())?;
+    check_geo(
+        GeoKind::Ip,
+        rule.ip.iter().flatten().chain(rule.source.iter().flatten()),
+    )?;
+
+    Ok(())
+}
+
+fn position(entries: &[RoutingRuleEntry], id: i32) -> Result<usize, RoutingRuleError> {
+    entries
+        .iter()
+        .position(|entry| entry.id == id)
+        .ok_or(RoutingRuleError::NotFound(id))
+}
+
+pub fn list_rules(conn: &mut Connection) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+
+    Ok(current(&xray_config, conn)?.1)
+}
+
+/// Adds an enabled rule after the existing ones.
+pub async fn create_rule(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    rule: RoutingRule,
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let (config, mut entries) = current(&xray_config, conn)?;
+
+    validate_rule(&config, &rule)?;
+
+    entries.push(RoutingRuleEntry {
+        id: 0,
+        enabled: true,
+        rule,
+    });
+
+    save(xray_service, &xray_config, conn, config, entries).await
+}
+
+pub async fn update_rule(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    id: i32,
+    rule: RoutingRule,
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let (config, mut entries) = current(&xray_config, conn)?;
+
+    let index = position(&entries, id)?;
+    validate_rule(&config, &rule)?;
+    entries[index].rule = rule;
+
+    save(xray_service, &xray_config, conn, config, entries).await
+}
+
+pub async fn delete_rule(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    id: i32,
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let (config, mut entries) = current(&xray_config, conn)?;
+
+    let index = position(&entries, id)?;
+    entries.remove(index);
+
+    save(xray_service, &xray_config, conn, config, entries).await
+}
+
+/// Disabled rules are kept by elux but left out of `xray.json`.
+pub async fn set_rule_enabled(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    id: i32,
+    enabled: bool,
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let (config, mut entries) = current(&xray_config, conn)?;
+
+    let index = position(&entries, id)?;
+    if enabled {
+        validate_rule(&config, &entries[index].rule)?;
+    }
+    entries[index].enabled = enabled;
+
+    save(xray_service, &xray_config, conn, config, entries).await
+}
+
+/// Reorders the rules. `ids` must list every rule, disabled ones included,
+/// exactly once; xray evaluates them top to bottom.
+pub async fn reorder_rules(
+    xray_service: &XrayService,
+    conn: &mut Connection,
+    ids: &[i32],
+) -> Result<Vec<RoutingRuleEntry>, RoutingRuleError> {
+    let xray_config = XrayFileCore::new(XRAY_CONFIG_FILE);
+    let (config, mut entries) = current(&xray_config, conn)?;
+
+    let mut existing: Vec<i32> = entries.iter().map(|entry| entry.id).collect();
+    let mut requested = ids.to_vec();
+    existing.sort_unstable();
+    requested.sort_unstable();
+
+    if existing != requested {
+        return Err(XrayConfigError::Invalid(
+            "Order must list every routing rule exactly once".to_string(),
+        )
+        .into());
+    }
+
+    entries.sort_by_key(|entry| ids.iter().position(|id| *id == entry.id));
+
+    save(xray_service, &xray_config, conn, config, entries).await
+}
@@ -1,82 +1,261 @@
-use std::{io::SeekFrom, path::PathBuf, process::Stdio, time::Duration};
+use std::{
+    collections::VecDeque,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use serde::Serialize;
 use tokio::{
     fs::File,
     io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
     process::{Child, Command},
-    sync::{Mutex, broadcast},
+    sync::{Mutex, broadcast, watch},
     task,
     time::sleep,
 };
 
+use crate::{
+    http::models::xray_file::XrayConfig,
+    services::xray::{
+        api::{OutboundStatus, XrayApi, XrayApiError, XrayCliApi, api_listen},
+        apply::{self, ApplyOutcome},
+        binary::{XrayBinary, XrayVersion},
+        sockopt::ManagedSockopt,
+        validator::{XrayConfigError, validate_file},
+    },
+    utils::settings::SupervisorSettings,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrayServiceError {
+    #[error("Xray is already running")]
+    AlreadyRunning,
+
+    #[error("Xray is not running")]
+    NotRunning,
+
+    #[error(transparent)]
+    InvalidConfig(#[from] XrayConfigError),
+
+    #[error("Failed to open log file: {0}")]
+    LogFile(std::io::Error),
+
+    #[error("Failed to spawn xray: {0}")]
+    Spawn(std::io::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How many crashes inside `crash_loop_window` are tolerated before the
+    /// supervisor gives up and leaves xray in the `failed` state.
+    pub crash_loop_limit: usize,
+    pub crash_loop_window: Duration,
+    pub log_tail_lines: usize,
+    /// Time xray gets to exit after SIGTERM before it is killed.
+    pub stop_grace: Duration,
+}
+
+impl From<&SupervisorSettings> for SupervisorConfig {
+    fn from(settings: &SupervisorSettings) -> Self {
+        SupervisorConfig {
+            initial_backoff: Duration::from_secs(settings.initial_backoff),
+            max_backoff: Duration::from_secs(settings.max_backoff),
+            crash_loop_limit: settings.crash_loop_limit,
+            crash_loop_window: Duration::from_secs(settings.crash_loop_window),
+            log_tail_lines: settings.log_tail_lines,
+            stop_grace: Duration::from_secs(settings.stop_grace),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum XrayState {
+    Stopped,
+    Running,
+    Restarting,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XrayExit {
+    pub reason: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+
+    /// Unix timestamp (seconds).
+    pub at: u64,
+    pub log_tail: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XrayStatus {
+    pub state: XrayState,
+    pub running: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+
+    pub restart_count: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_exit: Option<XrayExit>,
+
+    pub binary: PathBuf,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<XrayVersion>,
+}
+
+struct Runtime {
+    state: XrayState,
+    pid: Option<u32>,
+    started_at: Option<Instant>,
+    restart_count: u32,
+    last_exit: Option<XrayExit>,
+}
+
+impl Runtime {
+    fn running(&mut self, pid: Option<u32>) {
+        self.state = XrayState::Running;
+        self.pid = pid;
+        self.started_at = Some(Instant::now());
+    }
+
+    fn halted(&mut self, state: XrayState) {
+        self.state = state;
+        self.pid = None;
+        self.started_at = None;
+    }
+}
+
+struct Supervisor {
+    stop: watch::Sender<bool>,
+    handle: task::JoinHandle<()>,
+}
+
+/// Everything the supervisor task needs, shared with `XrayService`.
+#[derive(Clone)]
+struct SupervisorContext {
+    config: SupervisorConfig,
+    config_file_path: PathBuf,
+    log_file_path: PathBuf,
+    runtime: Arc<StdMutex<Runtime>>,
+    log_tail: Arc<StdMutex<VecDeque<String>>>,
+}
+
 pub struct XrayService {
-    child: Mutex<Option<Child>>,
+    supervisor: Mutex<Option<Supervisor>>,
     sender: broadcast::Sender<String>,
     sender_handle: Mutex<Option<task::JoinHandle<()>>>,
-    config_file_path: PathBuf,
-    log_file_path: PathBuf,
+    context: SupervisorContext,
+    api: Arc<dyn XrayApi>,
+
+    /// Socket options for managed outbounds, set while transparent mode is on.
+    outbound_sockopt: StdMutex<Option<ManagedSockopt>>,
 }
 
 impl XrayService {
-    pub fn new(config_file_path: PathBuf, log_file_path: PathBuf) -> XrayService {
+    pub fn new(
+        config_file_path: PathBuf,
+        log_file_path: PathBuf,
+        supervisor_config: SupervisorConfig,
+    ) -> XrayService {
         let (sender, _) = broadcast::channel(128);
         XrayService {
-            child: Mutex::new(None),
+            supervisor: Mutex::new(None),
             sender,
             sender_handle: Mutex::new(None),
-            config_file_path,
-            log_file_path,
+            context: SupervisorContext {
+                config: supervisor_config,
+                config_file_path,
+                log_file_path,
+                runtime: Arc::new(StdMutex::new(Runtime {
+                    state: XrayState::Stopped,
+                    pid: None,
+                    started_at: None,
+                    restart_count: 0,
+                    last_exit: None,
+                })),
+                log_tail: Arc::new(StdMutex::new(VecDeque::new())),
+            },
+            api: Arc::new(XrayCliApi),
+            outbound_sockopt: StdMutex::new(None),
         }
     }
 
+    pub fn outbound_sockopt(&self) -> Option<ManagedSockopt> {
+        self.outbound_sockopt.lock().unwrap().clone()
+    }
+
+    /// Takes effect on the next write of `xray.json`.
+    pub fn set_outbound_sockopt(&self, sockopt: Option<ManagedSockopt>) {
+        *self.outbound_sockopt.lock().unwrap() = sockopt;
+    }
+
     pub fn logs(&self) -> broadcast::Receiver<String> {
         self.sender.subscribe()
     }
 
-    pub async fn status(&self) -> bool {
-        match self.child.lock().await.as_mut() {
-            Some(child) => match child.try_wait() {
-                Ok(maybe_code) => maybe_code.is_none(),
-                Err(_) => false,
-            },
-            None => false,
+    pub async fn status(&self) -> XrayStatus {
+        let runtime = self.context.runtime.lock().unwrap();
+
+        XrayStatus {
+            state: runtime.state,
+            running: runtime.state == XrayState::Running,
+            pid: runtime.pid,
+            uptime_secs: runtime.started_at.map(|at| at.elapsed().as_secs()),
+            restart_count: runtime.restart_count,
+            last_exit: runtime.last_exit.clone(),
+            binary: XrayBinary::get().path.clone(),
+            version: XrayBinary::get().version,
         }
     }
 
-    pub async fn start(&self) -> bool {
-        let mut child = self.child.lock().await;
+    pub async fn start(&self) -> Result<(), XrayServiceError> {
+        let mut supervisor = self.supervisor.lock().await;
         let mut sender_handle = self.sender_handle.lock().await;
 
-        if child.is_some() {
-            return false;
+        if supervisor.as_ref().is_some_and(|s| !s.handle.is_finished()) {
+            return Err(XrayServiceError::AlreadyRunning);
         }
 
-        let stdout_file = match std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_file_path)
+        validate_file(&self.context.config_file_path).await?;
+
+        let child = spawn_xray(&self.context.config_file_path, &self.context.log_file_path)?;
+
         {
-            Ok(file) => file,
-            Err(err) => {
-                eprintln!("Не удалось открыть файл логов: {}", err);
-                return false;
-            }
-        };
+            let mut runtime = self.context.runtime.lock().unwrap();
+            runtime.restart_count = 0;
+            runtime.running(child.id());
+        }
 
-        let stderr_file = stdout_file.try_clone().unwrap();
+        let (stop, stop_rx) = watch::channel(false);
+        let handle = tokio::spawn(supervise(self.context.clone(), child, stop_rx));
+        *supervisor = Some(Supervisor { stop, handle });
 
-        let Ok(process) = Command::new("xray")
-            .args(["run", "-c", self.config_file_path.to_str().unwrap()])
-            .stdout(Stdio::from(stdout_file))
-            .stderr(Stdio::from(stderr_file))
-            .spawn()
-        else {
-            return false;
-        };
-        *child = Some(process);
+        if sender_handle.as_ref().is_some_and(|h| !h.is_finished()) {
+            return Ok(());
+        }
 
         let sender = self.sender.clone();
-        let log_path = self.log_file_path.clone();
+        let log_path = self.context.log_file_path.clone();
+        let log_tail = self.context.log_tail.clone();
+        let log_tail_lines = self.context.config.log_tail_lines;
 
         *sender_handle = Some(tokio::spawn(async move {
             // Ждем появления файла
@@ -103,6 +282,13 @@ impl XrayService {
                     Ok(_) => {
                         let clean_line = line_buf.trim_end();
                         if !clean_line.is_empty() {
+                            {
+                                let mut tail = log_tail.lock().unwrap();
+                                if tail.len() >= log_tail_lines {
+                                    tail.pop_front();
+                                }
+                                tail.push_back(clean_line.to_string());
+                            }
                             let _ = sender.send(clean_line.to_string());
                         }
                     }
@@ -114,29 +300,282 @@ impl XrayService {
             }
         }));
 
-        true
+        Ok(())
     }
 
-    pub async fn stop(&self) -> bool {
-        let mut child = self.child.lock().await;
+    pub async fn stop(&self) -> Result<(), XrayServiceError> {
+        let mut supervisor = self.supervisor.lock().await;
         let mut sender_handle = self.sender_handle.lock().await;
 
-        if child.is_none()
-            && sender_handle
-                .as_ref()
-                .map(|v| v.is_finished())
-                .unwrap_or(true)
+        if let Some(handle) = sender_handle.take() {
+            handle.abort();
+        }
+
+        let Some(current) = supervisor.take() else {
+            return Err(XrayServiceError::NotRunning);
+        };
+
+        let was_running = !current.handle.is_finished();
+
+        let _ = current.stop.send(true);
+        let _ = current.handle.await;
+
+        self.context
+            .runtime
+            .lock()
+            .unwrap()
+            .halted(XrayState::Stopped);
+
+        if was_running {
+            Ok(())
+        } else {
+            Err(XrayServiceError::NotRunning)
+        }
+    }
+
+    /// Stops and starts xray again. The config on disk is checked before the
+    /// running instance is touched, so a broken config never takes xray down.
+    /// Returns whether xray was running before the restart.
+    pub async fn restart(&self) -> Result<bool, XrayServiceError> {
+        validate_file(&self.context.config_file_path).await?;
+
+        let was_running = match self.stop().await {
+            Ok(()) => true,
+            Err(XrayServiceError::NotRunning) => false,
+            Err(err) => return Err(err),
+        };
+
+        self.start().await?;
+
+        Ok(was_running)
+    }
+
+    /// Brings a running xray from `previous` to `next` (already written to
+    /// disk). Outbound and routing changes go through the xray API so open
+    /// connections survive; anything else, or a failed API call, restarts.
+    pub async fn apply(
+        &self,
+        previous: &XrayConfig,
+        next: &XrayConfig,
+    ) -> Result<ApplyOutcome, XrayServiceError> {
+        if !self.status().await.running {
+            return Ok(ApplyOutcome::NotRunning);
+        }
+
+        if previous == next {
+            return Ok(ApplyOutcome::Unchanged);
+        }
+
+        if let Some(plan) = apply::plan(previous, next) {
+            match plan.execute(self.api.as_ref()).await {
+                Ok(()) => return Ok(ApplyOutcome::Live),
+                Err(err) => eprintln!(
+                    "Failed to apply changes through xray api at {}, restarting: {}",
+                    api_listen(next).unwrap_or_default(),
+                    err
+                ),
+            }
+        }
+
+        self.restart().await?;
+
+        Ok(ApplyOutcome::Restarted)
+    }
+
+    pub async fn observatory_status(
+        &self,
+        server: &str,
+    ) -> Result<Vec<OutboundStatus>, XrayApiError> {
+        self.api.observatory_status(server).await
+    }
+}
+
+fn spawn_xray(config_file_path: &Path, log_file_path: &Path) -> Result<Child, XrayServiceError> {
+    let stdout_file = match std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file_path)
+    {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Не удалось открыть файл логов: {}", err);
+            return Err(XrayServiceError::LogFile(err));
+        }
+    };
+
+    let stderr_file = stdout_file.try_clone().map_err(XrayServiceError::LogFile)?;
+
+    Command::new(&XrayBinary::get().path)
+        .arg("run")
+        .arg("-c")
+        .arg(config_file_path)
+        .stdout(Stdio::from(stdout_file))
+        .stderr(Stdio::from(stderr_file))
+        .spawn()
+        .map_err(XrayServiceError::Spawn)
+}
+
+/// Owns the xray child process: waits for it to exit, records why, and
+/// brings it back with exponential backoff until stopped or crash-looping.
+async fn supervise(context: SupervisorContext, child: Child, mut stop: watch::Receiver<bool>) {
+    let mut child = Some(child);
+    let mut crashes: VecDeque<Instant> = VecDeque::new();
+
+    loop {
+        let exit = match child.take() {
+            Some(mut process) => {
+                tokio::select! {
+                    status = process.wait() => Some(exit_from_status(status, &context)),
+                    _ = stop.changed() => {
+                        terminate(&mut process, context.config.stop_grace).await;
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // xray only exits cleanly when asked to, so it was stopped on purpose.
+        if let Some(exit) = exit.as_ref().filter(|exit| stopped_on_purpose(exit)) {
+            eprintln!("xray exited: {}, not restarting", exit.reason);
+
+            let mut runtime = context.runtime.lock().unwrap();
+            runtime.last_exit = Some(exit.clone());
+            runtime.halted(XrayState::Stopped);
+            return;
+        }
+
+        let now = Instant::now();
+        crashes.push_back(now);
+        while crashes
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > context.config.crash_loop_window)
         {
-            return false;
+            crashes.pop_front();
         }
 
-        if let Some(mut child) = child.take() {
-            let _ = child.kill().await;
+        let give_up = crashes.len() > context.config.crash_loop_limit;
+
+        {
+            let mut runtime = context.runtime.lock().unwrap();
+            if let Some(exit) = exit {
+                eprintln!("xray exited: {}", exit.reason);
+                runtime.last_exit = Some(exit);
+            }
+            runtime.halted(if give_up {
+                XrayState::Failed
+            } else {
+                XrayState::Restarting
+            });
         }
-        if let Some(handle) = sender_handle.take() {
-            handle.abort();
+
+        if give_up {
+            eprintln!(
+                "xray crashed {} times within {:?}, giving up",
+                crashes.len(),
+                context.config.crash_loop_window
+            );
+            return;
+        }
+
+        let backoff = context
+            .config
+            .initial_backoff
+            .saturating_mul(1 << (crashes.len() - 1).min(16))
+            .min(context.config.max_backoff);
+
+        tokio::select! {
+            _ = sleep(backoff) => {}
+            _ = stop.changed() => return,
+        }
+
+        match spawn_xray(&context.config_file_path, &context.log_file_path) {
+            Ok(process) => {
+                let mut runtime = context.runtime.lock().unwrap();
+                runtime.restart_count += 1;
+                runtime.running(process.id());
+                child = Some(process);
+            }
+            Err(err) => {
+                context.runtime.lock().unwrap().last_exit = Some(XrayExit {
+                    reason: err.to_string(),
+                    code: None,
+                    signal: None,
+                    at: unix_now(),
+                    log_tail: Vec::new(),
+                });
+            }
+        }
+    }
+}
+
+/// Asks the process to exit with SIGTERM and falls back to SIGKILL once
+/// `grace` has passed.
+async fn terminate(child: &mut Child, grace: Duration) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: `pid` belongs to a child we spawned and have not reaped yet.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+
+        if tokio::time::timeout(grace, child.wait()).await.is_ok() {
+            return;
         }
 
-        true
+        eprintln!("xray did not exit within {:?}, killing it", grace);
     }
+
+    #[cfg(not(unix))]
+    let _ = grace;
+
+    let _ = child.kill().await;
+}
+
+fn exit_from_status(status: std::io::Result<ExitStatus>, context: &SupervisorContext) -> XrayExit {
+    let log_tail = context.log_tail.lock().unwrap().iter().cloned().collect();
+
+    let (reason, code, signal) = match status {
+        Ok(status) => {
+            let signal = exit_signal(&status);
+            let reason = match (status.code(), signal) {
+                (Some(code), _) => format!("exited with code {}", code),
+                (None, Some(signal)) => format!("killed by signal {}", signal),
+                (None, None) => "exited".to_string(),
+            };
+            (reason, status.code(), signal)
+        }
+        Err(err) => (format!("failed to wait for xray: {}", err), None, None),
+    };
+
+    XrayExit {
+        reason,
+        code,
+        signal,
+        at: unix_now(),
+        log_tail,
+    }
+}
+
+/// Exit code 0, or SIGTERM/SIGINT sent from outside elux.
+fn stopped_on_purpose(exit: &XrayExit) -> bool {
+    exit.code == Some(0) || matches!(exit.signal, Some(libc::SIGTERM | libc::SIGINT))
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
@@ -0,0 +1,727 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    http::models::xray_file::{RoutingRule, XrayConfig},
+    services::xray::geodata::{DomainKind, GeoData, GeoDomain, GeoKind},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SimulateError {
+    #[error("Either domain or ip is required")]
+    EmptyQuery,
+
+    #[error("{0} not found")]
+    MissingData(String),
+
+    #[error("Invalid regexp '{0}': {1}")]
+    Regex(String, regex::Error),
+}
+
+/// A connection as xray's router sees it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteQuery {
+    pub domain: Option<String>,
+
+    /// Destination IP, or what `domain` resolves to when both are given.
+    pub ip: Option<IpAddr>,
+
+    pub port: Option<u16>,
+
+    /// `tcp` unless set.
+    pub network: Option<String>,
+
+    /// Sniffed protocol: `http`, `tls`, `quic` or `bittorrent`.
+    pub protocol: Option<String>,
+
+    pub inbound_tag: Option<String>,
+
+    pub source: Option<IpAddr>,
+
+    pub source_port: Option<u16>,
+
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteDecision {
+    /// `false` means no rule matched and the first outbound is used.
+    pub matched: bool,
+
+    /// Index into `routing.rules` of `xray.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_index: Option<usize>,
+
+    /// ID of the matching rule in `/xray/routing/rules`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<RoutingRule>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound_tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balancer_tag: Option<String>,
+
+    /// Outbounds the balancer picks from, fallback last.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub balancer_candidates: Vec<String>,
+
+    /// Addresses the domain was matched as, taken from the query or `dns.hosts`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resolved_ips: Vec<IpAddr>,
+}
+
+fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix.min(32)))
+                .unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix.min(128)))
+                .unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        (IpAddr::V6(_), IpAddr::V4(ip)) => {
+            cidr_contains(network, prefix, ip.to_ipv6_mapped().into())
+        }
+        (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+            .to_ipv4_mapped()
+            .is_some_and(|ip| cidr_contains(network, prefix, ip.into())),
+    }
+}
+
+/// Matches `port` against `53`, `"1000-2000"` or `"53,443,1000-2000"`.
+fn port_matches(spec: &Value, port: u16) -> bool {
+    match spec {
+        Value::Number(number) => number.as_u64() == Some(u64::from(port)),
+        Value::String(list) => list.split(',').any(|part| {
+            let part = part.trim();
+            match part.split_once('-') {
+                Some((from, to)) => match (from.trim().parse(), to.trim().parse()) {
+                    (Ok(from), Ok(to)) => (from..=to).contains(&port),
+                    _ => false,
+                },
+                None => part.parse() == Ok(port),
+            }
+        }),
+        _ => false,
+    }
+}
+
+fn list_contains(list: &Option<Vec<String>>, value: Option<&str>) -> Option<bool> {
+    let list = list.as_ref().filter(|list| !list.is_empty())?;
+    Some(value.is_some_and(|value| list.iter().any(|item| item.eq_ignore_ascii_case(value))))
+}
+
+/// Evaluates rules against one query, loading geo data files on demand.
+struct Router<'a> {
+    query: &'a RouteQuery,
+    domain: Option<String>,
+    files: HashMap<String, Option<GeoData>>,
+
+    /// Patterns compiled so far; a geosite category may hold thousands.
+    regexes: HashMap<String, Result<Regex, regex::Error>>,
+}
+
+impl<'a> Router<'a> {
+    fn regex(&mut self, pattern: &str) -> &Result<Regex, regex::Error> {
+        self.regexes
+            .entry(pattern.to_string())
+            .or_insert_with(|| Regex::new(pattern))
+    }
+
+    fn geo_domain_matches(&mut self, entry: &GeoDomain, domain: &str) -> bool {
+        match entry.kind {
+            DomainKind::Keyword => domain.contains(&entry.value),
+            DomainKind::Regex => self
+                .regex(&entry.value)
+                .as_ref()
+                .is_ok_and(|re| re.is_match(domain)),
+            DomainKind::Domain => {
+                domain == entry.value
+                    || domain
+                        .strip_suffix(&entry.value)
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }
+            DomainKind::Full => domain == entry.value,
+        }
+    }
+
+    fn site_matches(
+        &mut self,
+        file_name: &str,
+        category: &str,
+        domain: &str,
+    ) -> Result<bool, SimulateError> {
+        let entries = self.site(file_name, category)?;
+
+        Ok(entries
+            .iter()
+            .any(|entry| self.geo_domain_matches(entry, domain)))
+    }
+
+    fn geodata(&mut self, file_name: &str) -> Result<&GeoData, SimulateError> {
+        self.files
+            .entry(file_name.to_string())
+            .or_insert_with(|| GeoData::load(file_name))
+            .as_ref()
+            .ok_or_else(|| SimulateError::MissingData(file_name.to_string()))
+    }
+
+    fn site(&mut self, file_name: &str, category: &str) -> Result<Vec<GeoDomain>, SimulateError> {
+        let (code, attribute) = match category.split_once('@') {
+            Some((code, attribute)) => (code, Some(attribute.to_lowercase())),
+            None => (category, None),
+        };
+        let code = code.to_lowercase();
+
+        let domains = self
+            .geodata(file_name)?
+            .site(&code)
+            .ok_or_else(|| SimulateError::MissingData(format!("{}:{}", file_name, code)))?;
+
+        Ok(match attribute {
+            Some(attribute) => domains
+                .into_iter()
+                .filter(|d| d.attributes.contains(&attribute))
+                .collect(),
+            None => domains,
+        })
+    }
+
+    fn ip_in_geo(
+        &mut self,
+        file_name: &str,
+        category: &str,
+        ip: IpAddr,
+    ) -> Result<bool, SimulateError> {
+        let (reverse, code) = match category.strip_prefix('!') {
+            Some(code) => (true, code.to_lowercase()),
+            None => (false, category.to_lowercase()),
+        };
+
+        let geoip = self
+            .geodata(file_name)?
+            .ip(&code)
+            .ok_or_else(|| SimulateError::MissingData(format!("{}:{}", file_name, code)))?;
+
+        let inside = geoip
+            .cidrs
+            .iter()
+            .any(|cidr| cidr_contains(cidr.ip, cidr.prefix, ip));
+
+        Ok(inside ^ geoip.reverse ^ reverse)
+    }
+
+    /// Plain patterns are keywords in routing rules but exact names in
+    /// `dns.hosts`, hence `plain_is_full`.
+    fn domain_matches(
+        &mut self,
+        pattern: &str,
+        domain: &str,
+        plain_is_full: bool,
+    ) -> Result<bool, SimulateError> {
+        let matched = if let Some(value) = pattern.strip_prefix("full:") {
+            domain == value.to_lowercase()
+        } else if let Some(value) = pattern.strip_prefix("domain:") {
+            let value = value.to_lowercase();
+            domain == value
+                || domain
+                    .strip_suffix(&value)
+                    .is_some_and(|rest| rest.ends_with('.'))
+        } else if let Some(value) = pattern.strip_prefix("regexp:") {
+            self.regex(value)
+                .as_ref()
+                .map_err(|err| SimulateError::Regex(value.to_string(), err.clone()))?
+                .is_match(domain)
+        } else if let Some(value) = pattern.strip_prefix("keyword:") {
+            domain.contains(&value.to_lowercase())
+        } else if let Some(value) = pattern.strip_prefix("dotless:") {
+            !domain.contains('.') && domain.contains(&value.to_lowercase())
+        } else if let Some(category) = pattern.strip_prefix("geosite:") {
+            self.site_matches(GeoKind::Site.file_name(), category, domain)?
+        } else if let Some((file_name, category)) = pattern
+            .strip_prefix("ext:")
+            .and_then(|ext| ext.split_once(':'))
+        {
+            self.site_matches(file_name, category, domain)?
+        } else if plain_is_full {
+            domain == pattern.to_lowercase()
+        } else {
+            domain.contains(&pattern.to_lowercase())
+        };
+
+        Ok(matched)
+    }
+
+    fn ip_matches(&mut self, pattern: &str, ip: IpAddr) -> Result<bool, SimulateError> {
+        if let Some(category) = pattern.strip_prefix("geoip:") {
+            return self.ip_in_geo(GeoKind::Ip.file_name(), category, ip);
+        }
+
+        if let Some((file_name, category)) = pattern
+            .strip_prefix("ext:")
+            .and_then(|ext| ext.split_once(':'))
+        {
+            return self.ip_in_geo(file_name, category, ip);
+        }
+
+        let (network, prefix) = match pattern.split_once('/') {
+            Some((network, prefix)) => (network, prefix.parse().ok()),
+            None => (pattern, None),
+        };
+
+        Ok(match network.parse::<IpAddr>() {
+            Ok(network) => {
+                let full = if network.is_ipv4() { 32 } else { 128 };
+                cidr_contains(network, prefix.unwrap_or(full), ip)
+            }
+            Err(_) => false,
+        })
+    }
+
+    fn any_ip_matches(
+        &mut self,
+        patterns: &[String],
+        ips: &[IpAddr],
+    ) -> Result<bool, SimulateError> {
+        for ip in ips {
+            for pattern in patterns {
+                if self.ip_matches(pattern, *ip)? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// All conditions of a rule must hold; a condition the query carries no
+    /// value for never does, as in xray.
+    fn rule_matches(&mut self, rule: &RoutingRule, ips: &[IpAddr]) -> Result<bool, SimulateError> {
+        if let Some(patterns) = rule.domain.as_ref().filter(|v| !v.is_empty()) {
+            let Some(domain) = self.domain.clone() else {
+                return Ok(false);
+            };
+
+            let mut matched = false;
+            for pattern in patterns {
+                if self.domain_matches(pattern, &domain, false)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                return Ok(false);
+            }
+        }
+
+        if let Some(patterns) = rule.ip.as_ref().filter(|v| !v.is_empty())
+            && !self.any_ip_matches(patterns, ips)?
+        {
+            return Ok(false);
+        }
+
+        if let Some(patterns) = rule.source.as_ref().filter(|v| !v.is_empty())
+            && !self.any_ip_matches(patterns, self.query.source.as_slice())?
+        {
+            return Ok(false);
+        }
+
+        if let Some(spec) = &rule.port
+            && !self.query.port.is_some_and(|port| port_matches(spec, port))
+        {
+            return Ok(false);
+        }
+
+        if let Some(spec) = &rule.source_port
+            && !self
+                .query
+                .source_port
+                .is_some_and(|port| port_matches(spec, port))
+        {
+            return Ok(false);
+        }
+
+        if let Some(networks) = &rule.network {
+            let network = self.query.network.as_deref().unwrap_or("tcp");
+            if !networks
+                .split(',')
+                .any(|n| n.trim().eq_ignore_ascii_case(network))
+            {
+                return Ok(false);
+            }
+        }
+
+        let lists = [
+            list_contains(&rule.user, self.query.user.as_deref()),
+            list_contains(&rule.inbound_tag, self.query.inbound_tag.as_deref()),
+            list_contains(&rule.protocol, self.query.protocol.as_deref()),
+        ];
+        if lists.into_iter().flatten().any(|matched| !matched) {
+            return Ok(false);
+        }
+
+        // HTTP attributes are only known to a live xray.
+        Ok(rule.attrs.is_none())
+    }
+
+    fn first_match(
+        &mut self,
+        rules: &[RoutingRule],
+        ips: &[IpAddr],
+    ) -> Result<Option<usize>, SimulateError> {
+        for (index, rule) in rules.iter().enumerate() {
+            if self.rule_matches(rule, ips)? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Addresses for the queried domain from `dns.hosts`, the only
+    /// resolution that can be done without touching the network.
+    fn hosts(&mut self, config: &XrayConfig) -> Result<Vec<IpAddr>, SimulateError> {
+        let Some(domain) = self.domain.clone() else {
+            return Ok(Vec::new());
+        };
+        let Some(hosts) = config.dns.as_ref().and_then(|dns| dns.hosts.as_ref()) else {
+            return Ok(Vec::new());
+        };
+
+        for (pattern, value) in hosts {
+            if self.domain_matches(pattern, &domain, true)? {
+                let values = match value {
+                    Value::Array(values) => values.iter().collect(),
+                    value => vec![value],
+                };
+
+                return Ok(values
+                    .into_iter()
+                    .filter_map(|v| v.as_str()?.parse().ok())
+                    .collect());
+            }
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+/// Works out where xray would send `query` under `config`, following
+/// `routing.rules` top to bottom and honouring `domainStrategy`. Only the
+/// config and local data files are used; nothing is resolved over DNS.
+pub fn simulate(config: &XrayConfig, query: &RouteQuery) -> Result<RouteDecision, SimulateError> {
+    if query.domain.is_none() && query.ip.is_none() {
+        return Err(SimulateError::EmptyQuery);
+    }
+
+    let mut router = Router {
+        query,
+        domain: query
+            .domain
+            .as_ref()
+            .map(|d| d.trim_end_matches('.').to_lowercase()),
+        files: HashMap::new(),
+        regexes: HashMap::new(),
+    };
+
+    let routing = config.routing.clone().unwrap_or_default();
+    let rules = routing.rules.as_deref().unwrap_or_default();
+
+    // A bare IP is always matched as such; a domain only gets addresses
+    // when the strategy asks for them.
+    let (direct, resolved) = match (&router.domain, query.ip) {
+        (None, ip) => (ip.into_iter().collect(), Vec::new()),
+        (Some(_), Some(ip)) => (Vec::new(), vec![ip]),
+        (Some(_), None) => (Vec::new(), router.hosts(config)?),
+    };
+
+    let strategy = routing
+        .domain_strategy
+        .as_deref()
+        .unwrap_or("AsIs")
+        .to_lowercase();
+
+    let mut decision = RouteDecision::default();
+
+    let found = match strategy.as_str() {
+        "ipondemand" => {
+            decision.resolved_ips = resolved.clone();
+            router.first_match(
+                rules,
+                if resolved.is_empty() {
+                    &direct
+                } else {
+                    &resolved
+                },
+            )?
+        }
+        "ipifnonmatch" => match router.first_match(rules, &direct)? {
+            Some(index) => Some(index),
+            None if !resolved.is_empty() => {
+                decision.resolved_ips = resolved.clone();
+                router.first_match(rules, &resolved)?
+            }
+            None => None,
+        },
+        _ => router.first_match(rules, &direct)?,
+    };
+
+    let Some(index) = found else {
+        decision.outbound_tag = config.outbounds().first().and_then(|o| o.tag.clone());
+        return Ok(decision);
+    };
+
+    let rule = &rules[index];
+    decision.matched = true;
+    decision.rule_index = Some(index);
+    decision.rule = Some(rule.clone());
+    decision.outbound_tag = rule.outbound_tag.clone();
+    decision.balancer_tag = rule.balancer_tag.clone();
+
+    if let Some(balancer) = rule.balancer_tag.as_ref().and_then(|tag| {
+        routing
+            .balancers
+            .iter()
+            .flatten()
+            .find(|balancer| balancer.tag == *tag)
+    }) {
+        let selectors = balancer.selector.as_deref().unwrap_or_default();
+
+        decision.balancer_candidates = config
+            .outbounds()
+            .iter()
+            .filter_map(|outbound| outbound.tag.clone())
+            .filter(|tag| {
+                selectors
+                    .iter()
+                    .any(|prefix| tag.starts_with(prefix.as_str()))
+            })
+            .chain(balancer.fallback_tag.clone())
+            .collect();
+    }
+
+    Ok(decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::xray::geodata::tests::assets;
+
+    fn with_routing(routing: Value) -> XrayConfig {
+        serde_json::from_value(json!({
+            "outbounds": [
+                {"tag": "elux-1-1", "protocol": "vless"},
+                {"tag": "elux-1-2", "protocol": "vless"},
+                {"tag": "direct", "protocol": "freedom"},
+                {"tag": "block", "protocol": "blackhole"}
+            ],
+            "dns": {"hosts": {"internal.test": "10.1.2.3", "domain:cdn.test": ["192.168.1.1"]}},
+            "routing": routing
+        }))
+        .unwrap()
+    }
+
+    fn domain(domain: &str) -> RouteQuery {
+        RouteQuery {
+            domain: Some(domain.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn ip(ip: &str) -> RouteQuery {
+        RouteQuery {
+            ip: Some(ip.parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    fn outbound(config: &XrayConfig, query: &RouteQuery) -> Option<String> {
+        simulate(config, query).unwrap().outbound_tag
+    }
+
+    #[test]
+    fn matches_domain_patterns() {
+        let config = with_routing(json!({"rules": [
+            {"outboundTag": "block", "domain": ["full:ads.example.com", "keyword:tracker"]},
+            {"outboundTag": "direct", "domain": ["domain:example.com", "regexp:^api\\d+\\.test$"]},
+            {"outboundTag": "elux-1-2", "domain": ["dotless:intra"]}
+        ]}));
+
+        assert_eq!(
+            outbound(&config, &domain("ads.example.com")).as_deref(),
+            Some("block")
+        );
+        assert_eq!(
+            outbound(&config, &domain("my-tracker.net")).as_deref(),
+            Some("block")
+        );
+        assert_eq!(
+            outbound(&config, &domain("www.Example.com.")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&config, &domain("api12.test")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&config, &domain("intranet")).as_deref(),
+            Some("elux-1-2")
+        );
+
+        // Nothing matched: the first outbound.
+        let decision = simulate(&config, &domain("notexample.com")).unwrap();
+        assert!(!decision.matched);
+        assert_eq!(decision.outbound_tag.as_deref(), Some("elux-1-1"));
+    }
+
+    #[test]
+    fn reports_invalid_regexps() {
+        let config = with_routing(json!({"rules": [
+            {"outboundTag": "direct", "domain": ["regexp:(unclosed"]}
+        ]}));
+
+        assert!(matches!(
+            simulate(&config, &domain("example.com")),
+            Err(SimulateError::Regex(pattern, _)) if pattern == "(unclosed"
+        ));
+        assert!(matches!(
+            simulate(&config, &RouteQuery::default()),
+            Err(SimulateError::EmptyQuery)
+        ));
+    }
+
+    #[test]
+    fn matches_ips_ports_and_networks() {
+        let config = with_routing(json!({"rules": [
+            {"outboundTag": "block", "network": "udp", "port": "443"},
+            {"outboundTag": "direct", "ip": ["10.0.0.0/8", "fd00::/8"]},
+            {"outboundTag": "elux-1-2", "port": "53,1000-2000", "inboundTag": ["socks"]}
+        ]}));
+
+        assert_eq!(
+            outbound(&config, &ip("10.20.30.40")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(outbound(&config, &ip("fd12::1")).as_deref(), Some("direct"));
+        assert_eq!(
+            outbound(&config, &ip("::ffff:10.0.0.1")).as_deref(),
+            Some("direct")
+        );
+
+        let mut query = ip("1.1.1.1");
+        query.port = Some(443);
+        query.network = Some("udp".to_string());
+        assert_eq!(outbound(&config, &query).as_deref(), Some("block"));
+
+        query.network = None;
+        query.port = Some(1500);
+        assert_eq!(outbound(&config, &query).as_deref(), Some("elux-1-1"));
+
+        query.inbound_tag = Some("socks".to_string());
+        assert_eq!(outbound(&config, &query).as_deref(), Some("elux-1-2"));
+    }
+
+    #[test]
+    fn resolves_through_hosts_by_domain_strategy() {
+        let rules = json!([{"outboundTag": "direct", "ip": ["10.0.0.0/8", "192.168.0.0/16"]}]);
+
+        let as_is = with_routing(json!({"rules": rules}));
+        assert_eq!(
+            outbound(&as_is, &domain("internal.test")).as_deref(),
+            Some("elux-1-1")
+        );
+
+        let on_demand = with_routing(json!({"domainStrategy": "IPOnDemand", "rules": rules}));
+        let decision = simulate(&on_demand, &domain("internal.test")).unwrap();
+        assert_eq!(decision.outbound_tag.as_deref(), Some("direct"));
+        assert_eq!(
+            decision.resolved_ips,
+            ["10.1.2.3".parse::<IpAddr>().unwrap()]
+        );
+
+        let if_non_match = with_routing(json!({"domainStrategy": "IPIfNonMatch", "rules": rules}));
+        assert_eq!(
+            outbound(&if_non_match, &domain("img.cdn.test")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&if_non_match, &domain("internal.test.org")).as_deref(),
+            Some("elux-1-1")
+        );
+    }
+
+    #[test]
+    fn lists_balancer_candidates() {
+        let config = with_routing(json!({
+            "rules": [{"balancerTag": "b", "domain": ["example.com"]}],
+            "balancers": [{"tag": "b", "selector": ["elux-1-"], "fallbackTag": "direct"}]
+        }));
+
+        let decision = simulate(&config, &domain("example.com")).unwrap();
+        assert_eq!(decision.balancer_tag.as_deref(), Some("b"));
+        assert_eq!(
+            decision.balancer_candidates,
+            ["elux-1-1", "elux-1-2", "direct"]
+        );
+    }
+
+    #[test]
+    fn matches_geo_data() {
+        assets();
+        let config = with_routing(json!({"rules": [
+            {"outboundTag": "block", "domain": ["geosite:example@ads"]},
+            {"outboundTag": "direct", "domain": ["geosite:example", "ext:geosite.dat:other"]},
+            {"outboundTag": "elux-1-2", "ip": ["geoip:private"]},
+            {"outboundTag": "direct", "ip": ["geoip:notlan"]}
+        ]}));
+
+        assert_eq!(
+            outbound(&config, &domain("full.example.org")).as_deref(),
+            Some("block")
+        );
+        assert_eq!(
+            outbound(&config, &domain("a.tracker.io")).as_deref(),
+            Some("block")
+        );
+        assert_eq!(
+            outbound(&config, &domain("www.example.com")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&config, &domain("cdn7.example.net")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&config, &domain("other.test")).as_deref(),
+            Some("direct")
+        );
+        assert_eq!(
+            outbound(&config, &ip("192.168.1.10")).as_deref(),
+            Some("elux-1-2")
+        );
+        assert_eq!(outbound(&config, &ip("8.8.8.8")).as_deref(), Some("direct"));
+
+        let missing = with_routing(json!({"rules": [
+            {"outboundTag": "direct", "domain": ["geosite:missing"]}
+        ]}));
+        assert!(matches!(
+            simulate(&missing, &domain("example.com")),
+            Err(SimulateError::MissingData(name)) if name == "geosite.dat:missing"
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::{http::models::xray_file::XrayConfig, services::xray::file::managed_id};
+
+pub const DOMAIN_STRATEGIES: &[&str] = &[
+    "AsIs",
+    "UseIP",
+    "UseIPv4",
+    "UseIPv6",
+    "UseIPv4v6",
+    "UseIPv6v4",
+    "ForceIP",
+    "ForceIPv4",
+    "ForceIPv6",
+    "ForceIPv4v6",
+    "ForceIPv6v4",
+];
+
+/// `sockopt` keys owned by elux on managed outbounds.
+const MANAGED_KEYS: &[&str] = &[
+    "mark",
+    "interface",
+    "tcpFastOpen",
+    "tcpcongestion",
+    "domainStrategy",
+];
+
+/// Optional socket options put on managed outbounds next to the mark.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutboundSockopt {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_fast_open: Option<bool>,
+
+    #[serde(rename = "tcpcongestion", skip_serializing_if = "Option::is_none")]
+    pub tcp_congestion: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_strategy: Option<String>,
+}
+
+impl OutboundSockopt {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(strategy) = &self.domain_strategy
+            && !DOMAIN_STRATEGIES.contains(&strategy.as_str())
+        {
+            return Err(format!(
+                "Unknown domainStrategy '{}', expected one of: {}",
+                strategy,
+                DOMAIN_STRATEGIES.join(", ")
+            ));
+        }
+
+        if let Some(congestion) = &self.tcp_congestion
+            && (congestion.is_empty() || !congestion.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            return Err(format!(
+                "'{}' is not a TCP congestion control name",
+                congestion
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// What managed outbounds carry while transparent mode is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManagedSockopt {
+    /// The "done" mark nftables lets through without proxying it again.
+    pub mark: u32,
+    pub options: OutboundSockopt,
+}
+
+fn sockopt_mut(stream_settings: &mut Option<Value>) -> Option<&mut Map<String, Value>> {
+    stream_settings
+        .get_or_insert_with(|| json!({}))
+        .as_object_mut()?
+        .entry("sockopt")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+}
+
+/// Drops `sockopt` and `streamSettings` again if they ended up empty.
+fn prune(stream_settings: &mut Option<Value>) {
+    let Some(settings) = stream_settings.as_mut().and_then(Value::as_object_mut) else {
+        return;
+    };
+
+    if settings
+        .get("sockopt")
+        .and_then(Value::as_object)
+        .is_some_and(Map::is_empty)
+    {
+        settings.remove("sockopt");
+    }
+    if settings.is_empty() {
+        *stream_settings = None;
+    }
+}
+
+/// Brings the socket options of `config` in line with `wanted`. Managed
+/// outbounds get the mark and options, or lose them when `wanted` is `None`.
+/// Other outbounds that already set a mark (like `direct-outbound`) are only
+/// moved to the current mark, so there is one place to change it.
+pub fn ensure_sockopt(config: &mut XrayConfig, wanted: Option<&ManagedSockopt>) {
+    let options = wanted.map(|wanted| match serde_json::to_value(&wanted.options) {
+        Ok(Value::Object(options)) => options,
+        _ => Map::new(),
+    });
+
+    for outbound in config.outbounds.iter_mut().flatten() {
+        let managed = outbound.tag.as_deref().and_then(managed_id).is_some();
+
+        if managed {
+            if let Some(sockopt) = sockopt_mut(&mut outbound.stream_settings) {
+                sockopt.retain(|key, _| !MANAGED_KEYS.contains(&key.as_str()));
+
+                if let (Some(wanted), Some(options)) = (wanted, &options) {
+                    sockopt.insert("mark".to_string(), json!(wanted.mark));
+                    sockopt.extend(options.clone());
+                }
+            }
+
+            prune(&mut outbound.stream_settings);
+            continue;
+        }
+
+        let Some(wanted) = wanted else {
+            continue;
+        };
+
+        let mark = outbound
+            .stream_settings
+            .as_mut()
+            .and_then(|s| s.pointer_mut("/sockopt/mark"));
+        if let Some(mark) = mark
+            && mark.as_u64().is_some_and(|mark| mark != 0)
+        {
+            *mark = json!(wanted.mark);
+        }
+    }
+}
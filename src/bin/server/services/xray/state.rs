@@ -0,0 +1,59 @@
+use rusqlite::Connection;
+
+use crate::services::{
+    db::TransactionManager,
+    repository::{config::ConfigRepository, xray_state::XrayStateRepository},
+    tproxy::manager::TproxyManager,
+    xray::{outbounds, service::XrayService},
+};
+
+/// Records `outbound_ids` as the applied set [`restore`] brings back. The
+/// file is already written by then, so failures are only logged.
+pub fn remember_outbounds(conn: &mut Connection, outbound_ids: Vec<i32>) {
+    if let Err(err) = TransactionManager::execute_with_result(conn, |tx| {
+        XrayStateRepository::set_outbound_ids(tx, outbound_ids)
+    }) {
+        eprintln!("Failed to record applied outbounds: {}", err);
+    }
+}
+
+/// Brings xray back to the state recorded in the database: retags outbounds
+/// written by older versions, re-applies the saved outbound set if
+/// `xray.json` drifted, restores the transparent proxy rules and outbound
+/// marks and starts xray if it was running when elux went down.
+pub async fn restore(
+    xray_service: &XrayService,
+    tproxy: &TproxyManager,
+    conn: &mut Connection,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let desired = TransactionManager::execute_with_result(conn, XrayStateRepository::get)?;
+
+    let mut configs = TransactionManager::execute_with_result(conn, |tx| {
+        ConfigRepository::get_by_ids(tx, desired.outbound_ids.as_slice())
+    })?
+    .unwrap_or_default();
+    configs.sort_by_key(|config| desired.outbound_ids.iter().position(|id| *id == config.id));
+
+    if outbounds::migrate_legacy_tags(&configs)? {
+        println!("Retagged the applied outbounds in xray.json as elux-<group>-<id>");
+    }
+
+    // Before anything else writes xray.json, so managed outbounds keep marks.
+    tproxy.sync_outbounds(xray_service, conn).await?;
+
+    if !desired.outbound_ids.is_empty()
+        && outbounds::applied_outbound_ids()? != desired.outbound_ids
+    {
+        outbounds::update_outbounds(xray_service, conn, configs.as_slice()).await?;
+    }
+
+    if desired.tproxy {
+        tproxy.reapply(conn).await?;
+    }
+
+    if desired.running {
+        xray_service.start().await?;
+    }
+
+    Ok(())
+}
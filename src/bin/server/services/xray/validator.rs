@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use serde_json::Value;
+use tokio::{fs, process::Command};
+
+use crate::{
+    http::models::xray_file::XrayConfig,
+    services::xray::{
+        binary::{Capability, XrayBinary},
+        file::XrayFileError,
+        lint::LintIssue,
+    },
+    utils::config::AppPaths,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum XrayConfigError {
+    #[error("Xray rejected the configuration")]
+    Rejected { output: String },
+
+    #[error("Installed xray does not support: {}", list_capabilities(.0))]
+    Unsupported(Vec<Capability>),
+
+    #[error("Failed to run xray config test: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to serialize config: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    File(#[from] XrayFileError),
+
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error("Config has lint errors: {}", list_issues(.0))]
+    Lint(Vec<LintIssue>),
+}
+
+fn list_capabilities(capabilities: &[Capability]) -> String {
+    capabilities
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn list_issues(issues: &[LintIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("{}: {}", issue.path, issue.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn check_capabilities(config: &Value) -> Result<(), XrayConfigError> {
+    let unsupported = XrayBinary::get().unsupported(config);
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(XrayConfigError::Unsupported(unsupported))
+    }
+}
+
+/// Runs `xray run -test` against a config file that is already on disk.
+pub async fn validate_file(path: &Path) -> Result<(), XrayConfigError> {
+    if let Ok(config) = serde_json::from_slice::<Value>(&fs::read(path).await?) {
+        check_capabilities(&config)?;
+    }
+
+    let output = Command::new(&XrayBinary::get().path)
+        .arg("run")
+        .arg("-test")
+        .arg("-c")
+        .arg(path)
+        .output()
+        .await?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Err(XrayConfigError::Rejected {
+        output: text.trim().to_string(),
+    })
+}
+
+/// Writes the candidate config next to `xray.json` and lets xray check it,
+/// so nothing is applied until xray itself accepts the result.
+pub async fn validate_config(config: &XrayConfig) -> Result<(), XrayConfigError> {
+    let candidate = AppPaths::get()
+        .config_dir
+        .join(format!("xray.candidate.{}.json", rand::random::<u32>()));
+
+    fs::write(&candidate, serde_json::to_vec_pretty(config)?).await?;
+
+    let result = validate_file(&candidate).await;
+
+    let _ = fs::remove_file(&candidate).await;
+
+    result
+}
@@ -0,0 +1,316 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use elux::XRAY_CONFIG_FILE;
+use notify::{Event, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{
+    http::{models::xray_file::XrayConfig, server::AppState},
+    services::xray::{
+        apply::{ApplyOutcome, check_candidate},
+        file::{managed_outbound_ids, take_written},
+        lint::LintIssue,
+        state::remember_outbounds,
+        validator::XrayConfigError,
+    },
+    utils::{config::AppPaths, settings::Settings},
+};
+
+/// What came of an outside edit of `xray.json`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigChange {
+    /// Unix timestamp (seconds).
+    pub at: u64,
+
+    /// Whether the edit passed the checks elux runs on its own changes.
+    pub valid: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// `xray run -test` output when xray rejected the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<LintIssue>,
+
+    /// What happened to the running xray; unset unless `watch.reload` is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied: Option<ApplyOutcome>,
+
+    /// Config IDs of the managed outbounds now in the file.
+    pub outbound_ids: Vec<i32>,
+}
+
+impl ConfigChange {
+    fn new(valid: bool) -> Self {
+        ConfigChange {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            valid,
+            error: None,
+            output: None,
+            issues: Vec::new(),
+            applied: None,
+            outbound_ids: Vec::new(),
+        }
+    }
+
+    fn rejected(err: XrayConfigError) -> Self {
+        let mut change = ConfigChange::new(false);
+        change.error = Some(err.to_string());
+
+        match err {
+            XrayConfigError::Rejected { output } => change.output = Some(output),
+            XrayConfigError::Lint(issues) => change.issues = issues,
+            _ => {}
+        }
+
+        change
+    }
+}
+
+/// Fans out config changes to API clients.
+pub struct ConfigEvents {
+    sender: broadcast::Sender<ConfigChange>,
+    last: StdMutex<Option<ConfigChange>>,
+}
+
+impl Default for ConfigEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(16);
+
+        ConfigEvents {
+            sender,
+            last: StdMutex::new(None),
+        }
+    }
+}
+
+impl ConfigEvents {
+    pub fn subscribe(&self) -> broadcast::Receiver<ConfigChange> {
+        self.sender.subscribe()
+    }
+
+    pub fn last(&self) -> Option<ConfigChange> {
+        self.last.lock().unwrap().clone()
+    }
+
+    fn emit(&self, change: ConfigChange) {
+        *self.last.lock().unwrap() = Some(change.clone());
+        let _ = self.sender.send(change);
+    }
+}
+
+/// Runs the checks elux's own changes go through, see `check_candidate`.
+async fn check(previous: &XrayConfig, content: &str) -> Result<XrayConfig, XrayConfigError> {
+    let config: XrayConfig = serde_json::from_str(content)
+        .map_err(|err| XrayConfigError::Invalid(format!("Malformed xray config: {}", err)))?;
+
+    check_candidate(previous, &config).await?;
+
+    Ok(config)
+}
+
+/// Waits until no event came for `quiet`, swallowing the ones that do.
+/// False once the sender is gone.
+async fn debounce(rx: &mut mpsc::UnboundedReceiver<()>, quiet: Duration) -> bool {
+    loop {
+        match tokio::time::timeout(quiet, rx.recv()).await {
+            Ok(Some(())) => continue,
+            Ok(None) => return false,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// What elux knows about `xray.json` between edits.
+struct Known {
+    /// Content last looked at.
+    content: String,
+
+    /// Last config that passed the checks.
+    valid: XrayConfig,
+
+    /// Config the running xray was last brought to.
+    running: XrayConfig,
+}
+
+async fn react(state: &AppState, known: &mut Known, content: &str) -> ConfigChange {
+    let config = match check(&known.valid, content).await {
+        Ok(config) => config,
+        Err(err) => return ConfigChange::rejected(err),
+    };
+
+    let mut change = ConfigChange::new(true);
+    change.outbound_ids = managed_outbound_ids(&config);
+
+    // The file wins, or the next start would put the old set back.
+    remember_outbounds(&mut state.get_conn(), change.outbound_ids.clone());
+
+    if Settings::get().watch.reload {
+        match state.xray_service.apply(&known.running, &config).await {
+            Ok(outcome) => {
+                change.applied = Some(outcome);
+                known.running = config.clone();
+            }
+            Err(err) => change.error = Some(err.to_string()),
+        }
+    }
+
+    known.valid = config;
+    change
+}
+
+/// Watches `xray.json` for edits made outside elux, checks them, records the
+/// managed outbounds they leave and, with `watch.reload`, applies them.
+pub fn watch(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let path = AppPaths::get().xray_config.clone();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let ours = event.is_ok_and(|event| {
+                event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == Some(OsStr::new(XRAY_CONFIG_FILE)))
+            });
+            if ours {
+                let _ = tx.send(());
+            }
+        });
+
+        // Editors usually replace the file rather than write to it, which
+        // only shows up on the directory.
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                eprintln!("Failed to watch {}: {}", path.display(), err);
+                return;
+            }
+        };
+        if let Some(dir) = path.parent()
+            && let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive)
+        {
+            eprintln!("Failed to watch {}: {}", dir.display(), err);
+            return;
+        }
+
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let config: XrayConfig = serde_json::from_str(&content).unwrap_or_default();
+        let mut known = Known {
+            content,
+            valid: config.clone(),
+            running: config,
+        };
+
+        while rx.recv().await.is_some() {
+            let quiet = Duration::from_millis(Settings::get().watch.debounce);
+            if !debounce(&mut rx, quiet).await {
+                return;
+            }
+
+            // Missing in the middle of being replaced; the next event has it.
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            if content == known.content {
+                continue;
+            }
+            known.content = content.clone();
+
+            // elux's own writes were checked and applied already.
+            if take_written(&path, &content) {
+                if let Ok(config) = serde_json::from_str::<XrayConfig>(&content) {
+                    known.valid = config.clone();
+                    known.running = config;
+                }
+                continue;
+            }
+
+            if !Settings::get().watch.enabled {
+                continue;
+            }
+
+            let change = react(&state, &mut known, &content).await;
+            match &change.error {
+                Some(error) => eprintln!("{} was edited: {}", path.display(), error),
+                None => println!("{} was edited, changes accepted", path.display()),
+            }
+
+            state.config_events.emit(change);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn debounce_waits_for_events_to_stop() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let quiet = Duration::from_millis(100);
+
+        let burst = tokio::spawn(async move {
+            for _ in 0..5 {
+                tx.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(30)).await;
+            }
+            tx
+        });
+
+        let started = Instant::now();
+        assert!(debounce(&mut rx, quiet).await);
+
+        // The last event came after about 120ms, quiet followed it.
+        assert!(started.elapsed() >= Duration::from_millis(220));
+        assert!(rx.try_recv().is_err());
+
+        drop(burst.await.unwrap());
+        assert!(!debounce(&mut rx, quiet).await);
+    }
+
+    #[tokio::test]
+    async fn check_rejects_malformed_and_newly_broken_configs() {
+        let previous: XrayConfig = serde_json::from_value(json!({
+            "outbounds": [{"tag": "direct", "protocol": "freedom"}]
+        }))
+        .unwrap();
+
+        let err = check(&previous, "{ not json").await.unwrap_err();
+        assert!(matches!(err, XrayConfigError::Invalid(_)));
+
+        let dangling = json!({
+            "outbounds": [{"tag": "direct", "protocol": "freedom"}],
+            "routing": {"rules": [{"outboundTag": "missing", "domain": ["example.com"]}]}
+        });
+        let change =
+            ConfigChange::rejected(check(&previous, &dangling.to_string()).await.unwrap_err());
+        assert!(!change.valid);
+        assert!(
+            change
+                .issues
+                .iter()
+                .any(|issue| issue.code == "dangling-outbound")
+        );
+    }
+}
@@ -2,21 +2,39 @@ use dirs;
 use elux::{CONFIG_DIR, XRAY_CONFIG_FILE, XRAY_LOG_FILE};
 use std::{fs, path::PathBuf, sync::OnceLock};
 
-use crate::utils::templates;
+use crate::utils::{settings::Settings, templates};
 
 pub struct AppPaths {
     pub config_dir: PathBuf,
     pub xray_config: PathBuf,
     pub xray_log: PathBuf,
+    pub xray_backups: PathBuf,
+    /// Last rendered transparent proxy ruleset.
+    pub nft_config: PathBuf,
 }
 
 static INSTANCE: OnceLock<AppPaths> = OnceLock::new();
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses `dir` instead of `~/.config/elux`; must run before anything reads
+/// the config directory.
+pub fn set_config_dir(dir: PathBuf) {
+    CONFIG_DIR_OVERRIDE.set(dir).ok();
+}
+
+/// Directory holding `elux.kdl`, `xray.json` and the database.
+pub fn config_dir() -> PathBuf {
+    match CONFIG_DIR_OVERRIDE.get() {
+        Some(dir) => dir.clone(),
+        None => dirs::config_dir()
+            .expect("Failed to find config directory")
+            .join(CONFIG_DIR),
+    }
+}
 
 impl AppPaths {
     pub fn init() {
-        let config_dir = dirs::config_dir()
-            .expect("Failed to find config directory")
-            .join(CONFIG_DIR);
+        let config_dir = config_dir();
 
         if !config_dir.exists() {
             fs::create_dir_all(&config_dir).expect("Failed to create app config directory");
@@ -27,7 +45,15 @@ impl AppPaths {
             fs::create_dir_all(&xray_log_dir).expect("Failed to create xray log directory");
         }
 
-        let xray_log = xray_log_dir.join(XRAY_LOG_FILE);
+        let xray_log = match Settings::get().xray.log {
+            Some(log) => PathBuf::from(log),
+            None => xray_log_dir.join(XRAY_LOG_FILE),
+        };
+        if let Some(dir) = xray_log.parent()
+            && !dir.exists()
+        {
+            fs::create_dir_all(dir).expect("Failed to create xray log directory");
+        }
         if !xray_log.exists() {
             fs::File::create(&xray_log).expect("Failed to create xray log file");
         }
@@ -39,10 +65,19 @@ impl AppPaths {
             std::fs::write(&xray_config, config_content).expect("Failed to create xray log file");
         }
 
+        let xray_backups = config_dir.join("backups");
+        if !xray_backups.exists() {
+            fs::create_dir_all(&xray_backups).expect("Failed to create xray backups directory");
+        }
+
+        let nft_config = config_dir.join("proxy.conf");
+
         let paths = AppPaths {
             config_dir,
             xray_config,
             xray_log,
+            xray_backups,
+            nft_config,
         };
 
         INSTANCE.set(paths).ok();
@@ -52,3 +87,28 @@ impl AppPaths {
         INSTANCE.get().expect("AppPaths is not initialized")
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// Points the config directory at a temporary one holding the default
+    /// `elux.kdl` and `xray.json`, once per test run. Tests share it, so they
+    /// must not change those files.
+    pub fn init() -> &'static Path {
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+
+        DIR.get_or_init(|| {
+            let dir = tempfile::tempdir().unwrap();
+
+            set_config_dir(dir.path().to_path_buf());
+            Settings::init(Vec::new()).unwrap();
+            AppPaths::init();
+
+            dir
+        })
+        .path()
+    }
+}
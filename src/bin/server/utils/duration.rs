@@ -0,0 +1,31 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+static DURATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d+(\.\d+)?(ns|us|µs|ms|s|m|h))+$").unwrap());
+
+/// Whether `value` is a Go `time.Duration` string, as xray takes them:
+/// `30s`, `1m30s`, `1.5h`.
+pub fn is_duration(value: &str) -> bool {
+    DURATION.is_match(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_go_durations() {
+        for value in ["30s", "1m30s", "1.5h", "250ms", "10µs", "2h45m0.5s"] {
+            assert!(is_duration(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for value in ["", "30", "s", "1d", "1m 30s", "-5s", "1.s"] {
+            assert!(!is_duration(value), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,676 @@
+//! A small reader and writer for the subset of KDL used by `elux.kdl`:
+//! nodes with arguments, `key=value` properties and child blocks; quoted and
+//! raw strings, integers, floats, booleans and `null`; `//`, `/* */` and
+//! `/-` comments. Type annotations are not supported.
+
+use std::fmt::{self, Write};
+
+#[derive(Debug, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub struct KdlError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KdlValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+}
+
+impl fmt::Display for KdlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KdlValue::String(s) => {
+                f.write_char('"')?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            KdlValue::Integer(n) => write!(f, "{}", n),
+            KdlValue::Float(n) => write!(f, "{:?}", n),
+            KdlValue::Bool(b) => write!(f, "#{}", b),
+            KdlValue::Null => f.write_str("#null"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KdlNode {
+    pub name: String,
+    pub args: Vec<KdlValue>,
+    pub props: Vec<(String, KdlValue)>,
+    pub children: Vec<KdlNode>,
+    /// Line the node starts on, for error messages.
+    pub line: usize,
+}
+
+impl KdlNode {
+    pub fn new(name: &str) -> Self {
+        KdlNode {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A node with a single argument, the usual `key value` setting.
+    pub fn with_arg(name: &str, value: KdlValue) -> Self {
+        KdlNode {
+            args: vec![value],
+            ..KdlNode::new(name)
+        }
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+
+        out.push_str(&indent);
+        out.push_str(&identifier(&self.name));
+        for arg in &self.args {
+            let _ = write!(out, " {}", arg);
+        }
+        for (key, value) in &self.props {
+            let _ = write!(out, " {}={}", identifier(key), value);
+        }
+
+        if !self.children.is_empty() {
+            out.push_str(" {\n");
+            for child in &self.children {
+                child.write(out, depth + 1);
+            }
+            out.push_str(&indent);
+            out.push('}');
+        }
+        out.push('\n');
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    !c.is_whitespace()
+        && !matches!(
+            c,
+            '\\' | '/' | '(' | ')' | '{' | '}' | ';' | '[' | ']' | '=' | '"' | '#'
+        )
+}
+
+/// `name` as is if it is a valid bare identifier, quoted otherwise.
+fn identifier(name: &str) -> String {
+    let bare = !name.is_empty()
+        && name.chars().all(is_identifier_char)
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && !matches!(name, "true" | "false" | "null" | "inf" | "-inf" | "nan");
+
+    if bare {
+        name.to_string()
+    } else {
+        KdlValue::String(name.to_string()).to_string()
+    }
+}
+
+/// Renders `nodes` as a KDL document.
+pub fn to_string(nodes: &[KdlNode]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        node.write(&mut out, 0);
+    }
+    out
+}
+
+pub fn parse(input: &str) -> Result<Vec<KdlNode>, KdlError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+
+    let nodes = parser.nodes()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected '}'"));
+    }
+
+    Ok(nodes)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+enum Token {
+    Value(KdlValue),
+    /// A bare word: a node name, a property key or a KDL v1 keyword.
+    Word(String),
+}
+
+impl Parser {
+    fn error(&self, message: impl Into<String>) -> KdlError {
+        KdlError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), KdlError> {
+        let start = self.line;
+        self.pos += 2;
+        let mut depth = 1;
+
+        while depth > 0 {
+            match (self.bump(), self.peek()) {
+                (Some('/'), Some('*')) => {
+                    self.pos += 1;
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.pos += 1;
+                    depth -= 1;
+                }
+                (Some(_), _) => {}
+                (None, _) => {
+                    return Err(KdlError {
+                        line: start,
+                        message: "unterminated comment".to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skips spaces, comments and escaped newlines; stops at a newline
+    /// unless `newlines` is set.
+    fn skip_space(&mut self, newlines: bool) -> Result<(), KdlError> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some('\n'), _) if newlines => {
+                    self.bump();
+                }
+                (Some(c), _) if c != '\n' && c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => self.skip_block_comment()?,
+                (Some('\\'), _) => {
+                    self.bump();
+                    self.skip_space(false)?;
+                    if self.peek() == Some('\n') {
+                        self.bump();
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn nodes(&mut self) -> Result<Vec<KdlNode>, KdlError> {
+        let mut nodes = Vec::new();
+
+        loop {
+            self.skip_space(true)?;
+            while self.peek() == Some(';') {
+                self.bump();
+                self.skip_space(true)?;
+            }
+
+            match (self.peek(), self.peek_at(1)) {
+                (None, _) | (Some('}'), _) => return Ok(nodes),
+                (Some('/'), Some('-')) => {
+                    self.pos += 2;
+                    self.skip_space(true)?;
+                    self.node()?;
+                }
+                _ => nodes.push(self.node()?),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<KdlNode, KdlError> {
+        let line = self.line;
+        let name = match self.token()? {
+            Token::Word(name) | Token::Value(KdlValue::String(name)) => name,
+            Token::Value(value) => {
+                return Err(self.error(format!("expected a node name, found {}", value)));
+            }
+        };
+        let mut node = KdlNode {
+            line,
+            ..KdlNode::new(&name)
+        };
+
+        loop {
+            self.skip_space(false)?;
+
+            let discard = self.peek() == Some('/') && self.peek_at(1) == Some('-');
+            if discard {
+                self.pos += 2;
+                self.skip_space(false)?;
+            }
+
+            match self.peek() {
+                None | Some('\n') | Some(';') => return Ok(node),
+                Some('}') if !discard => return Ok(node),
+                Some('{') => {
+                    self.bump();
+                    let children = self.nodes()?;
+                    if self.bump() != Some('}') {
+                        return Err(self.error(format!("unclosed block of '{}'", node.name)));
+                    }
+                    if !discard {
+                        node.children = children;
+                    }
+                }
+                Some(_) => {
+                    let token = self.token()?;
+
+                    if self.peek() == Some('=') {
+                        self.bump();
+                        let key = match token {
+                            Token::Word(key) | Token::Value(KdlValue::String(key)) => key,
+                            Token::Value(value) => {
+                                return Err(
+                                    self.error(format!("{} cannot be a property name", value))
+                                );
+                            }
+                        };
+                        let value = self.value()?;
+                        if !discard {
+                            node.props.retain(|(k, _)| *k != key);
+                            node.props.push((key, value));
+                        }
+                    } else {
+                        let value = self.keyword(token)?;
+                        if !discard {
+                            node.args.push(value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<KdlValue, KdlError> {
+        let token = self.token()?;
+        self.keyword(token)
+    }
+
+    /// Bare words are only values as KDL v1 keywords.
+    fn keyword(&self, token: Token) -> Result<KdlValue, KdlError> {
+        match token {
+            Token::Value(value) => Ok(value),
+            Token::Word(word) => match word.as_str() {
+                "true" => Ok(KdlValue::Bool(true)),
+                "false" => Ok(KdlValue::Bool(false)),
+                "null" => Ok(KdlValue::Null),
+                _ => Ok(KdlValue::String(word)),
+            },
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, KdlError> {
+        match (self.peek(), self.peek_at(1)) {
+            (Some('"'), _) => Ok(Token::Value(KdlValue::String(self.string()?))),
+            (Some('r' | '#'), Some('"' | '#')) => {
+                Ok(Token::Value(KdlValue::String(self.raw_string()?)))
+            }
+            (Some('#'), _) => {
+                self.bump();
+                let word = self.word();
+                match word.as_str() {
+                    "true" => Ok(Token::Value(KdlValue::Bool(true))),
+                    "false" => Ok(Token::Value(KdlValue::Bool(false))),
+                    "null" => Ok(Token::Value(KdlValue::Null)),
+                    _ => Err(self.error(format!("unknown keyword '#{}'", word))),
+                }
+            }
+            (Some(c), next)
+                if c.is_ascii_digit()
+                    || (matches!(c, '-' | '+') && next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let word = self.word();
+                self.number(&word).map(Token::Value)
+            }
+            (Some(c), _) if is_identifier_char(c) => Ok(Token::Word(self.word())),
+            (Some(c), _) => Err(self.error(format!("unexpected '{}'", c))),
+            (None, _) => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek().filter(|c| is_identifier_char(*c)) {
+            word.push(c);
+            self.bump();
+        }
+        word
+    }
+
+    fn number(&self, word: &str) -> Result<KdlValue, KdlError> {
+        let clean = word.replace('_', "");
+        let (sign, digits) = match clean.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, clean.strip_prefix('+').unwrap_or(&clean)),
+        };
+
+        let radix = [("0x", 16), ("0o", 8), ("0b", 2)]
+            .into_iter()
+            .find_map(|(prefix, radix)| Some((digits.strip_prefix(prefix)?, radix)));
+
+        let parsed = match radix {
+            Some((digits, radix)) => i64::from_str_radix(digits, radix)
+                .ok()
+                .map(|n| KdlValue::Integer(sign * n)),
+            None => clean
+                .parse::<i64>()
+                .ok()
+                .map(KdlValue::Integer)
+                .or_else(|| clean.parse::<f64>().ok().map(KdlValue::Float)),
+        };
+
+        parsed.ok_or_else(|| self.error(format!("'{}' is not a number", word)))
+    }
+
+    fn string(&mut self) -> Result<String, KdlError> {
+        let start = self.line;
+        self.bump();
+        let mut value = String::new();
+
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(value),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('s') => ' ',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('u') => self.unicode_escape()?,
+                        Some(c) => return Err(self.error(format!("unknown escape '\\{}'", c))),
+                        None => break,
+                    };
+                    value.push(escaped);
+                }
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+
+        Err(KdlError {
+            line: start,
+            message: "unterminated string".to_string(),
+        })
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, KdlError> {
+        if self.bump() != Some('{') {
+            return Err(self.error("expected '{' after \\u"));
+        }
+
+        let mut hex = String::new();
+        while let Some(c) = self.bump() {
+            if c == '}' {
+                return u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error(format!("invalid unicode escape '{}'", hex)));
+            }
+            hex.push(c);
+        }
+
+        Err(self.error("unterminated unicode escape"))
+    }
+
+    /// `r#"..."#` (KDL v1) and `#"..."#` (KDL v2), no escapes inside.
+    fn raw_string(&mut self) -> Result<String, KdlError> {
+        let start = self.line;
+        if self.peek() == Some('r') {
+            self.bump();
+        }
+
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            self.bump();
+            hashes += 1;
+        }
+        if self.bump() != Some('"') {
+            return Err(self.error("expected '\"' in raw string"));
+        }
+
+        let mut value = String::new();
+        while let Some(c) = self.bump() {
+            if c == '"' && (0..hashes).all(|i| self.peek_at(i) == Some('#')) {
+                self.pos += hashes;
+                return Ok(value);
+            }
+            value.push(c);
+        }
+
+        Err(KdlError {
+            line: start,
+            message: "unterminated raw string".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nodes without the line numbers, which a rewrite moves around.
+    fn strip_lines(nodes: Vec<KdlNode>) -> Vec<KdlNode> {
+        nodes
+            .into_iter()
+            .map(|node| KdlNode {
+                line: 0,
+                children: strip_lines(node.children),
+                ..node
+            })
+            .collect()
+    }
+
+    fn parsed(input: &str) -> Vec<KdlNode> {
+        strip_lines(parse(input).unwrap())
+    }
+
+    fn args(input: &str) -> Vec<KdlValue> {
+        parsed(input).remove(0).args
+    }
+
+    fn error(input: &str) -> (usize, String) {
+        let err = parse(input).unwrap_err();
+        (err.line, err.message)
+    }
+
+    #[test]
+    fn round_trips_what_it_writes() {
+        let nodes = vec![
+            KdlNode {
+                children: vec![
+                    KdlNode::with_arg("listen", KdlValue::String("0.0.0.0:8400".into())),
+                    KdlNode::with_arg("tcp", KdlValue::Bool(true)),
+                    KdlNode::with_arg("socket", KdlValue::Null),
+                    KdlNode::with_arg("quoted", KdlValue::String("a \"b\" \\ c\n\td".into())),
+                    KdlNode::with_arg("ratio", KdlValue::Float(0.5)),
+                    KdlNode::with_arg("negative", KdlValue::Integer(-3)),
+                ],
+                ..KdlNode::new("server")
+            },
+            KdlNode {
+                args: vec![KdlValue::Integer(1), KdlValue::String("two".into())],
+                props: vec![("key".into(), KdlValue::Bool(false))],
+                ..KdlNode::new("node")
+            },
+            // Names that are not bare identifiers get quoted.
+            KdlNode::with_arg("true", KdlValue::Integer(1)),
+            KdlNode::with_arg("1st", KdlValue::Integer(1)),
+            KdlNode::with_arg("two words", KdlValue::Integer(2)),
+        ];
+
+        let written = to_string(&nodes);
+        assert_eq!(parsed(&written), nodes);
+        assert!(written.contains("    tcp #true\n"));
+        assert!(written.contains("\"two words\" 2\n"));
+    }
+
+    #[test]
+    fn reads_raw_strings() {
+        assert_eq!(
+            args(r##"path r#"C:\dir "quoted""# #"no \n escapes"# r"plain""##),
+            [
+                KdlValue::String(r#"C:\dir "quoted""#.into()),
+                KdlValue::String(r"no \n escapes".into()),
+                KdlValue::String("plain".into()),
+            ]
+        );
+
+        let nodes = parse("a r#\"two\nlines\"#\nb 1").unwrap();
+        assert_eq!(nodes[0].args, [KdlValue::String("two\nlines".into())]);
+        assert_eq!(nodes[1].line, 3);
+    }
+
+    #[test]
+    fn discards_slashdash_nodes_arguments_and_children() {
+        let input = "
+            /-gone 1 {
+                child 2
+            }
+            node /-1 2 /-key=3 other=4 /-{
+                hidden
+            }
+            parent {
+                /-hidden 1
+                shown 2
+            }
+        ";
+        let nodes = parsed(input);
+
+        assert_eq!(
+            nodes
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>(),
+            ["node", "parent"]
+        );
+        assert_eq!(nodes[0].args, [KdlValue::Integer(2)]);
+        assert_eq!(nodes[0].props, [("other".into(), KdlValue::Integer(4))]);
+        assert!(nodes[0].children.is_empty());
+        assert_eq!(
+            nodes[1].children,
+            [KdlNode::with_arg("shown", KdlValue::Integer(2))]
+        );
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        let input = "/* outer /* inner */ still\n comment */ node 1 /* inline */ 2 // rest\nnext";
+        let nodes = parse(input).unwrap();
+
+        assert_eq!(nodes[0].args, [KdlValue::Integer(1), KdlValue::Integer(2)]);
+        assert_eq!((nodes[1].name.as_str(), nodes[1].line), ("next", 3));
+    }
+
+    #[test]
+    fn reads_keywords_with_and_without_hash() {
+        assert_eq!(
+            args("flags #true true #false false #null null \"true\""),
+            [
+                KdlValue::Bool(true),
+                KdlValue::Bool(true),
+                KdlValue::Bool(false),
+                KdlValue::Bool(false),
+                KdlValue::Null,
+                KdlValue::Null,
+                KdlValue::String("true".into()),
+            ]
+        );
+        assert_eq!(
+            error("flag #maybe"),
+            (1, "unknown keyword '#maybe'".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_numbers_in_any_radix() {
+        assert_eq!(
+            args("n 0xff 0o17 0b101 -0x10 +7 1_000 1.5 -2.5e3"),
+            [
+                KdlValue::Integer(255),
+                KdlValue::Integer(15),
+                KdlValue::Integer(5),
+                KdlValue::Integer(-16),
+                KdlValue::Integer(7),
+                KdlValue::Integer(1000),
+                KdlValue::Float(1.5),
+                KdlValue::Float(-2500.0),
+            ]
+        );
+        assert_eq!(error("n 0xzz"), (1, "'0xzz' is not a number".to_string()));
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(
+            error("a 1\nb \"open\n\n"),
+            (2, "unterminated string".to_string())
+        );
+        assert_eq!(
+            error("a 1\n/* open\n/* nested */\n"),
+            (2, "unterminated comment".to_string())
+        );
+        assert_eq!(
+            error("a 1\nb r#\"open\"\n"),
+            (2, "unterminated raw string".to_string())
+        );
+        assert_eq!(error("a 1\n}\n"), (2, "unexpected '}'".to_string()));
+        assert_eq!(
+            error("a {\n    b 1\n"),
+            (3, "unclosed block of 'a'".to_string())
+        );
+        assert_eq!(
+            error("a 1\nb \"\\q\""),
+            (2, "unknown escape '\\q'".to_string())
+        );
+        assert_eq!(
+            error("a\n1 2"),
+            (2, "expected a node name, found 1".to_string())
+        );
+    }
+}
@@ -1,2 +1,7 @@
+pub mod atomic;
 pub mod config;
+pub mod duration;
+pub mod kdl;
+pub mod settings;
+pub mod tail;
 pub mod templates;
@@ -0,0 +1,786 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{OnceLock, RwLock},
+};
+
+use elux::{ELUX_CONFIG_FILE, SOCKET, SOCKET_NAME};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::utils::{
+    atomic, config,
+    duration::is_duration,
+    kdl::{self, KdlError, KdlNode, KdlValue},
+};
+
+/// `ELUX_<SECTION>_<KEY>` overrides `<key>` of `<section>` in `elux.kdl`,
+/// e.g. `ELUX_SERVER_LISTEN` or `ELUX_XRAY_BIN`.
+pub const ENV_PREFIX: &str = "ELUX_";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("Failed to access {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("{path}: {error}")]
+    Syntax { path: PathBuf, error: KdlError },
+
+    #[error("{0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Address the HTTP API and the web UI listen on.
+    pub listen: String,
+
+    /// Serve on `listen` at all; without it only the Unix socket is open.
+    pub tcp: bool,
+
+    /// Unix socket serving the same API; `elux-core.sock` in the config
+    /// directory if unset, so the daemon and the CLI agree on it.
+    pub socket: Option<String>,
+
+    /// Permissions of the socket file, in octal. Connecting takes write
+    /// permission, so this decides who may control elux locally.
+    pub socket_mode: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            listen: SOCKET.to_string(),
+            tcp: true,
+            socket: None,
+            socket_mode: "660".to_string(),
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn socket_path(&self) -> PathBuf {
+        match &self.socket {
+            Some(socket) => PathBuf::from(socket),
+            None => config::config_dir().join(SOCKET_NAME),
+        }
+    }
+
+    pub fn socket_mode(&self) -> Option<u32> {
+        u32::from_str_radix(&self.socket_mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct XraySettings {
+    /// xray executable, a path or a name looked up in `PATH`.
+    pub bin: String,
+
+    /// Log file xray writes to; `xray/file.log` in the config directory if
+    /// unset.
+    pub log: Option<String>,
+}
+
+impl Default for XraySettings {
+    fn default() -> Self {
+        XraySettings {
+            bin: "xray".to_string(),
+            log: None,
+        }
+    }
+}
+
+/// How the xray process is kept running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct SupervisorSettings {
+    /// Seconds before the first restart after a crash; doubled with every
+    /// further crash up to `max_backoff`.
+    pub initial_backoff: u64,
+    pub max_backoff: u64,
+
+    /// Crashes within `crash_loop_window` seconds after which xray is left
+    /// in the `failed` state.
+    pub crash_loop_limit: usize,
+    pub crash_loop_window: u64,
+
+    /// Seconds xray gets to exit after SIGTERM before it is killed.
+    pub stop_grace: u64,
+
+    /// Log lines kept with the reason of the last exit.
+    pub log_tail_lines: usize,
+}
+
+impl Default for SupervisorSettings {
+    fn default() -> Self {
+        SupervisorSettings {
+            initial_backoff: 1,
+            max_backoff: 60,
+            crash_loop_limit: 5,
+            crash_loop_window: 300,
+            stop_grace: 5,
+            log_tail_lines: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct SubscriptionSettings {
+    /// Seconds a subscription download may take.
+    pub timeout: u64,
+    pub user_agent: String,
+
+    /// Minutes between refreshes of every group with a subscription URL;
+    /// 0 leaves refreshing to `POST /groups/{id}/refresh`.
+    pub refresh_interval: u64,
+
+    /// Subscriptions downloaded at once during such a refresh.
+    pub refresh_concurrency: usize,
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        SubscriptionSettings {
+            timeout: 30,
+            user_agent: format!("elux/{}", env!("CARGO_PKG_VERSION")),
+            refresh_interval: 0,
+            refresh_concurrency: 4,
+        }
+    }
+}
+
+/// Probe settings of the observatories elux adds for `leastPing`/`leastLoad`
+/// balancers. Observatories already in `xray.json` are left alone; edit
+/// those through `/xray/observatory`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ObservatoryDefaults {
+    /// URL probed through each outbound.
+    pub url: String,
+
+    /// Time between probes, as an xray duration.
+    pub interval: String,
+
+    /// xray's `enableConcurrency`: probe all outbounds at once instead of one
+    /// by one. xray has no limit in between.
+    pub enable_concurrency: bool,
+}
+
+impl Default for ObservatoryDefaults {
+    fn default() -> Self {
+        ObservatoryDefaults {
+            url: "https://www.google.com/generate_204".to_string(),
+            interval: "1m".to_string(),
+            enable_concurrency: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct TproxyOptions {
+    /// Seconds between checks of the default route.
+    pub route_poll_interval: u64,
+
+    /// Remove the rules when elux stops; they come back on the next start.
+    pub cleanup_on_exit: bool,
+}
+
+impl Default for TproxyOptions {
+    fn default() -> Self {
+        TproxyOptions {
+            route_poll_interval: 5,
+            cleanup_on_exit: true,
+        }
+    }
+}
+
+/// How elux reacts to `xray.json` being edited by hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct WatchSettings {
+    /// Check outside edits and update the applied outbounds from them.
+    pub enabled: bool,
+
+    /// Also bring a running xray in line with a valid edit.
+    pub reload: bool,
+
+    /// Milliseconds without further writes before an edit is looked at.
+    pub debounce: u64,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        WatchSettings {
+            enabled: true,
+            reload: false,
+            debounce: 500,
+        }
+    }
+}
+
+/// Application settings from `elux.kdl`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub xray: XraySettings,
+    pub supervisor: SupervisorSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub observatory: ObservatoryDefaults,
+    pub tproxy: TproxyOptions,
+    pub watch: WatchSettings,
+}
+
+/// Settings as loaded, with the values taken from the environment.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsView {
+    pub settings: Settings,
+
+    /// `section.key` of every setting overridden by an environment variable
+    /// or a command-line flag.
+    pub overrides: Vec<String>,
+
+    /// Whether a changed setting only takes effect after elux restarts.
+    pub restart_required: bool,
+}
+
+struct State {
+    /// What was in effect at startup; `server`, `xray` and `supervisor` are
+    /// not reloaded.
+    started: Settings,
+    current: Settings,
+    overrides: Vec<String>,
+
+    /// Command-line flags as `ELUX_*` pairs, applied after the environment.
+    flags: Vec<(String, String)>,
+}
+
+static INSTANCE: OnceLock<RwLock<State>> = OnceLock::new();
+
+const SECTIONS: &[&str] = &[
+    "server",
+    "xray",
+    "supervisor",
+    "subscriptions",
+    "observatory",
+    "tproxy",
+    "watch",
+];
+
+fn kebab_to_camel(name: &str) -> String {
+    let mut camel = String::new();
+    let mut upper = false;
+
+    for c in name.chars() {
+        match c {
+            '-' | '_' => upper = true,
+            c if upper => {
+                camel.push(c.to_ascii_uppercase());
+                upper = false;
+            }
+            c => camel.push(c),
+        }
+    }
+
+    camel
+}
+
+fn camel_to_kebab(name: &str) -> String {
+    let mut kebab = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            kebab.push('-');
+            kebab.push(c.to_ascii_lowercase());
+        } else {
+            kebab.push(c);
+        }
+    }
+
+    kebab
+}
+
+fn kdl_to_json(value: &KdlValue) -> Value {
+    match value {
+        KdlValue::String(s) => Value::String(s.clone()),
+        KdlValue::Integer(n) => Value::from(*n),
+        KdlValue::Float(n) => Value::from(*n),
+        KdlValue::Bool(b) => Value::Bool(*b),
+        KdlValue::Null => Value::Null,
+    }
+}
+
+fn json_to_kdl(value: &Value) -> Option<KdlValue> {
+    match value {
+        Value::String(s) => Some(KdlValue::String(s.clone())),
+        Value::Number(n) => n
+            .as_i64()
+            .map(KdlValue::Integer)
+            .or_else(|| n.as_f64().map(KdlValue::Float)),
+        Value::Bool(b) => Some(KdlValue::Bool(*b)),
+        _ => None,
+    }
+}
+
+fn section<T: DeserializeOwned>(node: &KdlNode, fields: Map<String, Value>) -> Result<T, String> {
+    serde_json::from_value(Value::Object(fields))
+        .map_err(|err| format!("line {}: {}: {}", node.line, node.name, err))
+}
+
+/// Reads the sections of a document; `key value` children become fields.
+fn from_nodes(nodes: &[KdlNode]) -> Result<Settings, String> {
+    let mut sections = Map::new();
+
+    for node in nodes {
+        if !SECTIONS.contains(&node.name.as_str()) {
+            return Err(format!(
+                "line {}: unknown section '{}', expected one of: {}",
+                node.line,
+                node.name,
+                SECTIONS.join(", ")
+            ));
+        }
+
+        let mut fields = Map::new();
+        for child in &node.children {
+            let [value] = child.args.as_slice() else {
+                return Err(format!(
+                    "line {}: {}.{} takes exactly one value",
+                    child.line, node.name, child.name
+                ));
+            };
+            fields.insert(kebab_to_camel(&child.name), kdl_to_json(value));
+        }
+
+        // Each section is checked on its own so errors can point at a line.
+        let checked = match node.name.as_str() {
+            "server" => section::<ServerSettings>(node, fields.clone()).map(drop),
+            "xray" => section::<XraySettings>(node, fields.clone()).map(drop),
+            "supervisor" => section::<SupervisorSettings>(node, fields.clone()).map(drop),
+            "subscriptions" => section::<SubscriptionSettings>(node, fields.clone()).map(drop),
+            "observatory" => section::<ObservatoryDefaults>(node, fields.clone()).map(drop),
+            "tproxy" => section::<TproxyOptions>(node, fields.clone()).map(drop),
+            _ => section::<WatchSettings>(node, fields.clone()).map(drop),
+        };
+        checked?;
+
+        sections.insert(node.name.clone(), Value::Object(fields));
+    }
+
+    serde_json::from_value(Value::Object(sections)).map_err(|err| err.to_string())
+}
+
+fn to_nodes(settings: &Settings) -> Vec<KdlNode> {
+    let Ok(Value::Object(sections)) = serde_json::to_value(settings) else {
+        return Vec::new();
+    };
+
+    sections
+        .iter()
+        .map(|(name, fields)| {
+            let mut node = KdlNode::new(name);
+            for (key, value) in fields.as_object().into_iter().flatten() {
+                if let Some(value) = json_to_kdl(value) {
+                    node.children
+                        .push(KdlNode::with_arg(&camel_to_kebab(key), value));
+                }
+            }
+            node
+        })
+        .collect()
+}
+
+/// `ELUX_<SECTION>_<KEY>` variables applied on top of `settings`. Numbers
+/// and booleans are taken as such unless the setting is a string.
+fn apply_env(
+    settings: &Settings,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<(Settings, Vec<String>), String> {
+    let mut value = serde_json::to_value(settings).map_err(|err| err.to_string())?;
+    let mut overrides = Vec::new();
+
+    for (name, raw) in vars {
+        let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let rest = rest.to_ascii_lowercase();
+
+        let Some((section, key)) = SECTIONS.iter().find_map(|section| {
+            let key = rest.strip_prefix(section)?.strip_prefix('_')?;
+            Some((*section, kebab_to_camel(key)))
+        }) else {
+            continue;
+        };
+
+        match value.get_mut(section).and_then(Value::as_object_mut) {
+            Some(fields) if fields.contains_key(&key) => {
+                let parsed = match (&fields[&key], serde_json::from_str::<Value>(&raw)) {
+                    (Value::String(_), _) => Value::String(raw),
+                    (_, Ok(parsed @ (Value::Number(_) | Value::Bool(_)))) => parsed,
+                    _ => Value::String(raw),
+                };
+
+                fields.insert(key.clone(), parsed);
+                overrides.push(format!("{}.{}", section, key));
+            }
+            _ => eprintln!("Ignoring {}: {} has no setting '{}'", name, section, key),
+        }
+    }
+
+    let settings = serde_json::from_value(value)
+        .map_err(|err| format!("Invalid {}* environment variable: {}", ENV_PREFIX, err))?;
+
+    Ok((settings, overrides))
+}
+
+fn check_duration(field: &str, value: &str) -> Result<(), String> {
+    if is_duration(value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}: '{}' is not a duration like 30s or 1m",
+            field, value
+        ))
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.listen.parse::<SocketAddr>().is_err() {
+            return Err(format!(
+                "server.listen: '{}' is not an address like 0.0.0.0:8400",
+                self.server.listen
+            ));
+        }
+        if let Some(socket) = &self.server.socket
+            && !Path::new(socket).is_absolute()
+        {
+            return Err(format!(
+                "server.socket: '{}' is not an absolute path",
+                socket
+            ));
+        }
+        if self.server.socket_mode().is_none() {
+            return Err(format!(
+                "server.socket-mode: '{}' is not an octal mode like 660",
+                self.server.socket_mode
+            ));
+        }
+
+        if self.xray.bin.trim().is_empty() {
+            return Err("xray.bin must not be empty".to_string());
+        }
+        if let Some(log) = &self.xray.log
+            && !Path::new(log).is_absolute()
+        {
+            return Err(format!("xray.log: '{}' is not an absolute path", log));
+        }
+
+        let supervisor = &self.supervisor;
+        if supervisor.initial_backoff == 0 || supervisor.max_backoff < supervisor.initial_backoff {
+            return Err(
+                "supervisor.initial-backoff must be at least 1 and at most max-backoff seconds"
+                    .to_string(),
+            );
+        }
+        if supervisor.crash_loop_limit == 0 || supervisor.crash_loop_window == 0 {
+            return Err(
+                "supervisor.crash-loop-limit and crash-loop-window must be at least 1".to_string(),
+            );
+        }
+        if !(1..=300).contains(&supervisor.stop_grace) {
+            return Err("supervisor.stop-grace must be between 1 and 300 seconds".to_string());
+        }
+        if !(1..=1000).contains(&supervisor.log_tail_lines) {
+            return Err("supervisor.log-tail-lines must be between 1 and 1000".to_string());
+        }
+
+        if !(1..=600).contains(&self.subscriptions.timeout) {
+            return Err("subscriptions.timeout must be between 1 and 600 seconds".to_string());
+        }
+        if !(1..=32).contains(&self.subscriptions.refresh_concurrency) {
+            return Err("subscriptions.refresh-concurrency must be between 1 and 32".to_string());
+        }
+
+        match Url::parse(&self.observatory.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => {
+                return Err(format!(
+                    "observatory.url: '{}' is not an http(s) URL",
+                    self.observatory.url
+                ));
+            }
+        }
+        check_duration("observatory.interval", &self.observatory.interval)?;
+
+        if !(1..=3600).contains(&self.tproxy.route_poll_interval) {
+            return Err(
+                "tproxy.route-poll-interval must be between 1 and 3600 seconds".to_string(),
+            );
+        }
+
+        if !(50..=10000).contains(&self.watch.debounce) {
+            return Err("watch.debounce must be between 50 and 10000 milliseconds".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn path() -> PathBuf {
+        config::config_dir().join(ELUX_CONFIG_FILE)
+    }
+
+    /// Settings stored in `path`, defaults if it does not exist.
+    pub fn read(path: &Path) -> Result<Settings, SettingsError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Settings::default()),
+            Err(source) => {
+                return Err(SettingsError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                });
+            }
+        };
+
+        let nodes = kdl::parse(&content).map_err(|error| SettingsError::Syntax {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let settings = from_nodes(&nodes).map_err(|message| {
+            SettingsError::Invalid(format!("{}: {}", path.display(), message))
+        })?;
+        settings.validate().map_err(|message| {
+            SettingsError::Invalid(format!("{}: {}", path.display(), message))
+        })?;
+
+        Ok(settings)
+    }
+
+    /// Rewrites `path` from scratch, atomically; comments in the old file are
+    /// lost.
+    pub fn write(&self, path: &Path) -> Result<(), SettingsError> {
+        let content = format!(
+            "// elux settings, see README. Rewritten by elux on changes.\n{}",
+            kdl::to_string(&to_nodes(self))
+        );
+
+        atomic::write(path, content.as_bytes()).map_err(|source| SettingsError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// `elux.kdl` with environment overrides, without touching the file or
+    /// the settings in effect. Used by the command-line client.
+    pub fn load() -> Result<Settings, SettingsError> {
+        let stored = Self::read(&Self::path())?;
+        let (settings, _) = apply_env(&stored, std::env::vars()).map_err(SettingsError::Invalid)?;
+
+        Ok(settings)
+    }
+
+    /// Loads `elux.kdl` (writing the defaults if it is missing) and applies
+    /// environment overrides, then `flags` (`ELUX_*` pairs from the command
+    /// line).
+    pub fn init(flags: Vec<(String, String)>) -> Result<(), SettingsError> {
+        let path = Self::path();
+
+        let stored = Self::read(&path)?;
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|source| SettingsError::Io {
+                    path: dir.to_path_buf(),
+                    source,
+                })?;
+            }
+            stored.write(&path)?;
+        }
+
+        let (current, overrides) = apply_env(&stored, std::env::vars().chain(flags.clone()))
+            .map_err(SettingsError::Invalid)?;
+        current.validate().map_err(SettingsError::Invalid)?;
+
+        INSTANCE
+            .set(RwLock::new(State {
+                started: current.clone(),
+                current,
+                overrides,
+                flags,
+            }))
+            .ok();
+
+        Ok(())
+    }
+
+    /// The settings in effect.
+    pub fn get() -> Settings {
+        INSTANCE
+            .get()
+            .expect("Settings are not initialized")
+            .read()
+            .unwrap()
+            .current
+            .clone()
+    }
+
+    pub fn view() -> SettingsView {
+        let state = INSTANCE
+            .get()
+            .expect("Settings are not initialized")
+            .read()
+            .unwrap();
+
+        SettingsView {
+            settings: state.current.clone(),
+            overrides: state.overrides.clone(),
+            restart_required: state.started.server != state.current.server
+                || state.started.xray != state.current.xray
+                || state.started.supervisor != state.current.supervisor,
+        }
+    }
+
+    /// Validates `settings`, writes them to `elux.kdl` and makes them current.
+    /// Environment overrides and flags still win over the file, so the result
+    /// is validated as well.
+    pub fn update(settings: Settings) -> Result<SettingsView, SettingsError> {
+        settings.validate().map_err(SettingsError::Invalid)?;
+
+        let instance = INSTANCE.get().expect("Settings are not initialized");
+        let flags = instance.read().unwrap().flags.clone();
+
+        let (current, overrides) =
+            apply_env(&settings, std::env::vars().chain(flags)).map_err(SettingsError::Invalid)?;
+        current.validate().map_err(SettingsError::Invalid)?;
+        settings.write(&Self::path())?;
+
+        {
+            let mut state = instance.write().unwrap();
+            state.current = current;
+            state.overrides = overrides;
+        }
+
+        Ok(Self::view())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn defaults_survive_a_rewrite() {
+        let written = kdl::to_string(&to_nodes(&Settings::default()));
+
+        assert!(written.contains("    refresh-concurrency 4\n"));
+        assert!(written.contains("observatory {\n"));
+        assert_eq!(
+            from_nodes(&kdl::parse(&written).unwrap()).unwrap(),
+            Settings::default()
+        );
+        assert!(Settings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn reads_sections_and_points_at_bad_lines() {
+        let nodes = kdl::parse(
+            "subscriptions {\n    refresh-interval 30\n}\nobservatory {\n    enable-concurrency #true\n}",
+        )
+        .unwrap();
+        let settings = from_nodes(&nodes).unwrap();
+
+        assert_eq!(settings.subscriptions.refresh_interval, 30);
+        assert!(settings.observatory.enable_concurrency);
+
+        let nodes = kdl::parse("server {}\nchecker {\n    concurrency #true\n}").unwrap();
+        assert!(
+            from_nodes(&nodes)
+                .unwrap_err()
+                .starts_with("line 2: unknown section 'checker'")
+        );
+
+        let nodes = kdl::parse("observatory {\n    concurrency 4\n}").unwrap();
+        let err = from_nodes(&nodes).unwrap_err();
+        assert!(
+            err.starts_with("line 1: observatory: unknown field `concurrency`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn applies_environment_overrides() {
+        let (settings, overrides) = apply_env(
+            &Settings::default(),
+            vars(&[
+                ("ELUX_SUBSCRIPTIONS_REFRESH_INTERVAL", "15"),
+                ("ELUX_OBSERVATORY_ENABLE_CONCURRENCY", "true"),
+                ("ELUX_SUBSCRIPTIONS_USER_AGENT", "42"),
+                ("PATH", "/usr/bin"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(settings.subscriptions.refresh_interval, 15);
+        assert!(settings.observatory.enable_concurrency);
+        assert_eq!(settings.subscriptions.user_agent, "42");
+        assert_eq!(
+            overrides,
+            [
+                "subscriptions.refreshInterval",
+                "observatory.enableConcurrency",
+                "subscriptions.userAgent"
+            ]
+        );
+
+        let err = apply_env(
+            &Settings::default(),
+            vars(&[("ELUX_WATCH_DEBOUNCE", "soon")]),
+        )
+        .unwrap_err();
+        assert!(
+            err.starts_with("Invalid ELUX_* environment variable"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let mut settings = Settings::default();
+        settings.subscriptions.refresh_concurrency = 0;
+        assert!(
+            settings
+                .validate()
+                .unwrap_err()
+                .starts_with("subscriptions.refresh-concurrency")
+        );
+
+        let mut settings = Settings::default();
+        settings.observatory.interval = "every minute".to_string();
+        assert!(
+            settings
+                .validate()
+                .unwrap_err()
+                .starts_with("observatory.interval")
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{io::SeekFrom, path::Path};
+
+use tokio::{
+    fs::File,
+    io::{self, AsyncReadExt, AsyncSeekExt},
+};
+
+/// How much is read at a time, walking back from the end of the file.
+const CHUNK: u64 = 8 * 1024;
+
+/// The last `count` lines of `path`, each ending in a newline. Only the end
+/// of the file is read, so this stays cheap on a large log.
+pub async fn last_lines(path: &Path, count: usize) -> io::Result<String> {
+    if count == 0 {
+        return Ok(String::new());
+    }
+
+    let mut file = File::open(path).await?;
+    let mut pos = file.metadata().await?.len();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut newlines = 0;
+
+    // `count` lines are complete once a newline precedes the first of them;
+    // the newline ending the file does not start another line.
+    while pos > 0 && newlines <= count {
+        let start = pos.saturating_sub(CHUNK);
+
+        let mut chunk = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(&mut chunk).await?;
+
+        newlines += chunk.iter().filter(|byte| **byte == b'\n').count();
+        if buf.is_empty() && chunk.last() == Some(&b'\n') {
+            newlines -= 1;
+        }
+
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+        pos = start;
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let lines = text.lines().collect::<Vec<_>>();
+
+    let mut tail = lines[lines.len().saturating_sub(count)..].join("\n");
+    if !tail.is_empty() {
+        tail.push('\n');
+    }
+
+    Ok(tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_lines_across_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.log");
+        let content = (0..5000)
+            .map(|n| format!("line {}\n", n))
+            .collect::<String>();
+        fs::write(&path, content).unwrap();
+
+        let tail = last_lines(&path, 3000).await.unwrap();
+
+        assert_eq!(tail.lines().count(), 3000);
+        assert!(tail.starts_with("line 2000\n"));
+        assert!(tail.ends_with("line 4999\n"));
+    }
+
+    #[tokio::test]
+    async fn returns_the_whole_file_when_it_is_shorter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.log");
+        fs::write(&path, "first\nsecond").unwrap();
+
+        assert_eq!(last_lines(&path, 1).await.unwrap(), "second\n");
+        assert_eq!(last_lines(&path, 10).await.unwrap(), "first\nsecond\n");
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(
+            last_lines(&dir.path().join("missing.log"), 10)
+                .await
+                .is_err()
+        );
+    }
+}
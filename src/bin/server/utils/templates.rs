@@ -1,5 +1,5 @@
 use rust_embed::RustEmbed;
-use serde_json::{json, Value};
+use serde_json::{Value, json};
 use std::{fs, path::PathBuf, sync::OnceLock};
 
 use crate::utils::config::AppPaths;
@@ -22,10 +22,10 @@ pub fn get_init_xray_config(log_path: &PathBuf) -> String {
     serde_json::to_string_pretty(&config).expect("Serialization failed")
 }
 
-pub fn get_nft_config() -> String {
-    let file = Assets::get("proxy.conf").expect("nftables.conf missing");
+/// The nftables ruleset elux renders the transparent proxy rules from.
+pub fn get_nft_template() -> String {
+    let file = Assets::get("proxy.conf").expect("proxy.conf missing");
     let content = std::str::from_utf8(file.data.as_ref()).expect("Invalid UTF-8");
 
-    // Пример простой замены
-    content.replace("PORT_PLACEHOLDER", "1080")
+    content.to_string()
 }
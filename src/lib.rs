@@ -7,6 +7,10 @@ pub const XRAY_CONFIG_FILE: &str = "xray.json";
 pub const XRAY_LOG_FILE: &str = "file.log";
 pub const XRAY_CHECKER_CONFIG_FILE: &str = "xray_checker.json";
 
+/// Outbounds whose tag starts with this prefix belong to elux; everything
+/// else in `xray.json` is user-defined and left alone.
+pub const MANAGED_OUTBOUND_PREFIX: &str = "elux-";
+
 pub const SOCKET_NAME: &str = "elux-core.sock";
 pub const EDITOR_NAME: &str = "zeditor";
 